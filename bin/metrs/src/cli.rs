@@ -0,0 +1,241 @@
+use std::{path::PathBuf, time::Duration};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use metrs_stubs::parse_duration;
+
+/// Daemon reached when `--host` is not set
+pub const DEFAULT_HOST: &str = "unix:///run/metrsd.sock";
+
+/// Parse the url of a daemon
+fn parse_host(s: &str) -> Result<String, String> {
+  match ["unix://", "http://", "https://"]
+    .iter()
+    .any(|scheme| s.starts_with(scheme))
+  {
+    true => Ok(s.to_owned()),
+    false => Err(format!(
+      "Invalid host valid scheme are [unix,http,https] got: {s}"
+    )),
+  }
+}
+
+/// Output format of `metrs get`
+#[derive(Debug, Default, Clone, Copy, PartialEq, ValueEnum)]
+pub enum GetFormat {
+  /// The value alone, json when it is not a single value
+  #[default]
+  Value,
+  Json,
+  Yaml,
+  Table,
+}
+
+/// Output format of `metrs watch`
+#[derive(Debug, Default, Clone, Copy, PartialEq, ValueEnum)]
+pub enum WatchFormat {
+  /// One json object per line
+  #[default]
+  Ndjson,
+  /// Comma separated values with a header line
+  Csv,
+}
+
+#[derive(Debug, Clone, PartialEq, Args)]
+pub struct GetArg {
+  /// Dotted path of the field (e.g. memory.used, cpus.0.usage,
+  /// networks.eth0), the whole event when not set
+  pub field: Option<String>,
+  /// Output format
+  #[clap(short, long, value_enum, default_value = "value")]
+  pub format: GetFormat,
+}
+
+#[derive(Debug, Clone, PartialEq, Args)]
+pub struct WatchArg {
+  /// Comma separated list of fields to stream (e.g. cpus,memory.used), the
+  /// whole event when not set
+  #[clap(long, value_delimiter = ',')]
+  pub fields: Vec<String>,
+  /// Output format
+  #[clap(short, long, value_enum, default_value = "ndjson")]
+  pub format: WatchFormat,
+  /// Minimum interval in seconds between two events
+  #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+  pub interval: Option<u64>,
+  /// Stop after this number of events
+  #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+  pub count: Option<u64>,
+}
+
+/// Parse a playback speed
+fn parse_speed(s: &str) -> Result<f64, String> {
+  match s.parse::<f64>() {
+    Ok(speed) if speed > 0.0 && speed.is_finite() => Ok(speed),
+    _ => Err(format!("Invalid speed, expected a number above 0 got: {s}")),
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Args)]
+pub struct RecordArg {
+  /// Path of the recording to write
+  #[clap(short, long)]
+  pub output: PathBuf,
+  /// How long to record (e.g. 90s, 10m, 1h), until interrupted when not set
+  #[clap(short, long, value_parser = parse_duration)]
+  pub duration: Option<Duration>,
+  /// Minimum interval in seconds between two events
+  #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+  pub interval: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Args)]
+pub struct ReplayArg {
+  /// Path of the recording to play
+  pub file: PathBuf,
+  /// Playback speed, 2 plays the recording twice as fast
+  #[clap(long, default_value = "1", value_parser = parse_speed)]
+  pub speed: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Args)]
+pub struct TopArg {
+  /// Minimum interval in seconds between two refreshes
+  #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+  pub interval: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum Command {
+  /// Show a live dashboard of the metrics of the daemon
+  Top(TopArg),
+  /// Print a field of the latest metrics of the daemon
+  Get(GetArg),
+  /// Stream fields of the metrics of the daemon to stdout
+  Watch(WatchArg),
+  /// Record the metrics of the daemon to a file
+  Record(RecordArg),
+  /// Play a recording in the live dashboard
+  Replay(ReplayArg),
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct Cli {
+  #[clap(subcommand)]
+  pub command: Command,
+  /// Url of the daemon (e.g. unix:///run/metrsd.sock, http://127.0.0.1:8080)
+  #[clap(
+    short = 'H',
+    long,
+    global = true,
+    default_value = DEFAULT_HOST,
+    value_parser = parse_host,
+    env = "METRS_HOST"
+  )]
+  pub host: String,
+  /// Bearer token sent to a daemon started with `--auth-tokens`
+  #[clap(long, global = true, env = "METRS_TOKEN")]
+  pub token: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_cli() {
+    let cli = Cli::parse_from(["metrs", "top"]);
+    assert_eq!(cli.host, DEFAULT_HOST);
+    assert_eq!(cli.command, Command::Top(TopArg { interval: None }));
+    let cli = Cli::parse_from([
+      "metrs",
+      "top",
+      "--host",
+      "http://127.0.0.1:8080",
+      "--interval",
+      "5",
+    ]);
+    assert_eq!(cli.host, "http://127.0.0.1:8080");
+    assert_eq!(cli.command, Command::Top(TopArg { interval: Some(5) }));
+    assert!(Cli::try_parse_from(["metrs", "top", "-H", "tcp://:8080"]).is_err());
+    assert!(Cli::try_parse_from(["metrs", "top", "--interval", "0"]).is_err());
+    let cli = Cli::parse_from(["metrs", "get", "memory.used"]);
+    assert_eq!(
+      cli.command,
+      Command::Get(GetArg {
+        field: Some("memory.used".into()),
+        format: GetFormat::Value,
+      })
+    );
+    let cli = Cli::parse_from(["metrs", "get", "-f", "yaml"]);
+    assert_eq!(
+      cli.command,
+      Command::Get(GetArg {
+        field: None,
+        format: GetFormat::Yaml,
+      })
+    );
+    let cli = Cli::parse_from([
+      "metrs",
+      "watch",
+      "--fields",
+      "cpus,memory.used",
+      "--format",
+      "csv",
+      "--count",
+      "3",
+    ]);
+    assert_eq!(
+      cli.command,
+      Command::Watch(WatchArg {
+        fields: vec!["cpus".into(), "memory.used".into()],
+        format: WatchFormat::Csv,
+        interval: None,
+        count: Some(3),
+      })
+    );
+    assert!(Cli::try_parse_from(["metrs", "get", "-f", "xml"]).is_err());
+    assert!(Cli::try_parse_from([
+      "metrs",
+      "record",
+      "-o",
+      "out.gz",
+      "--duration",
+      "999999999999999d",
+    ])
+    .is_err());
+    let cli = Cli::parse_from([
+      "metrs",
+      "record",
+      "-o",
+      "incident.mtrs",
+      "--duration",
+      "10m",
+    ]);
+    assert_eq!(
+      cli.command,
+      Command::Record(RecordArg {
+        output: "incident.mtrs".into(),
+        duration: Some(Duration::from_secs(600)),
+        interval: None,
+      })
+    );
+    let cli = Cli::parse_from(["metrs", "replay", "incident.mtrs"]);
+    assert_eq!(
+      cli.command,
+      Command::Replay(ReplayArg {
+        file: "incident.mtrs".into(),
+        speed: 1.0,
+      })
+    );
+    let cli =
+      Cli::parse_from(["metrs", "replay", "incident.mtrs", "--speed", "0.5"]);
+    assert!(
+      matches!(cli.command, Command::Replay(ReplayArg { speed, .. }) if speed == 0.5)
+    );
+    assert!(
+      Cli::try_parse_from(["metrs", "replay", "a.mtrs", "--speed", "0"])
+        .is_err()
+    );
+    assert!(Cli::try_parse_from(["metrs", "record"]).is_err());
+  }
+}
@@ -0,0 +1,99 @@
+use metrsd_client::error::{ApiError, MetrsClientError};
+use ntex::client::error::ClientError;
+
+/// Exit code of an invalid command line or field
+pub const EXIT_USAGE: i32 = 2;
+
+/// Exit code when the daemon cannot be reached
+pub const EXIT_UNREACHABLE: i32 = 3;
+
+#[derive(Debug)]
+pub enum CliError {
+  Error(String),
+  /// Invalid arguments, such as a field not found in the events
+  Usage(String),
+  /// The daemon cannot be reached or closed the connection
+  Unreachable(String),
+}
+
+impl CliError {
+  /// Exit code of the process, usable by scripts and health checks
+  pub fn exit_code(&self) -> i32 {
+    match self {
+      CliError::Error(_) => 1,
+      CliError::Usage(_) => EXIT_USAGE,
+      CliError::Unreachable(_) => EXIT_UNREACHABLE,
+    }
+  }
+}
+
+impl std::fmt::Display for CliError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      CliError::Error(err)
+      | CliError::Usage(err)
+      | CliError::Unreachable(err) => write!(f, "{err}"),
+    }
+  }
+}
+
+impl From<ApiError> for CliError {
+  fn from(err: ApiError) -> Self {
+    CliError::Error(err.msg)
+  }
+}
+
+impl From<MetrsClientError> for CliError {
+  fn from(err: MetrsClientError) -> Self {
+    match err {
+      MetrsClientError::ClientError(
+        err @ (ClientError::Connect(_)
+        | ClientError::Send(_)
+        | ClientError::Timeout),
+      ) => CliError::Unreachable(err.to_string()),
+      err => CliError::Error(err.to_string()),
+    }
+  }
+}
+
+impl From<std::io::Error> for CliError {
+  fn from(err: std::io::Error) -> Self {
+    CliError::Error(err.to_string())
+  }
+}
+
+impl From<serde_json::Error> for CliError {
+  fn from(err: serde_json::Error) -> Self {
+    CliError::Error(err.to_string())
+  }
+}
+
+impl From<serde_yaml::Error> for CliError {
+  fn from(err: serde_yaml::Error) -> Self {
+    CliError::Error(err.to_string())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use ntex::http::StatusCode;
+
+  use super::*;
+
+  #[test]
+  fn test_exit_code() {
+    let err =
+      CliError::from(MetrsClientError::ClientError(ClientError::Timeout));
+    assert_eq!(err.exit_code(), EXIT_UNREACHABLE);
+    let err = CliError::from(MetrsClientError::Api(ApiError {
+      status: StatusCode::UNAUTHORIZED,
+      msg: "Missing bearer token".into(),
+    }));
+    assert_eq!(err.exit_code(), 1);
+    assert_eq!(err.to_string(), "Missing bearer token");
+    assert_eq!(
+      CliError::Usage("Unknown field".into()).exit_code(),
+      EXIT_USAGE
+    );
+  }
+}
@@ -0,0 +1,215 @@
+use serde_json::Value;
+
+use metrsd_client::stubs::{EventSection, MetrsdEvent};
+
+use crate::error::CliError;
+
+/// Convert a PascalCase key of the daemon to snake_case
+fn to_snake_case(key: &str) -> String {
+  let mut snake = String::with_capacity(key.len() + 4);
+  for (i, c) in key.chars().enumerate() {
+    if c.is_ascii_uppercase() {
+      if i > 0 {
+        snake.push('_');
+      }
+      snake.push(c.to_ascii_lowercase());
+    } else {
+      snake.push(c);
+    }
+  }
+  snake
+}
+
+fn snake_case_keys(value: Value) -> Value {
+  match value {
+    Value::Object(map) => Value::Object(
+      map
+        .into_iter()
+        .map(|(key, value)| (to_snake_case(&key), snake_case_keys(value)))
+        .collect(),
+    ),
+    Value::Array(items) => {
+      Value::Array(items.into_iter().map(snake_case_keys).collect())
+    }
+    value => value,
+  }
+}
+
+/// Serialize an event with the snake_case keys used by the field paths
+pub fn event_value(ev: &MetrsdEvent) -> Result<Value, CliError> {
+  Ok(snake_case_keys(serde_json::to_value(ev)?))
+}
+
+/// Select an item of a list by its index, its name or its mount point
+fn select_item<'a>(items: &'a [Value], segment: &str) -> Option<&'a Value> {
+  if let Ok(index) = segment.parse::<usize>() {
+    return items.get(index);
+  }
+  items.iter().find(|item| {
+    ["name", "mount_point"]
+      .iter()
+      .any(|key| item.get(key).and_then(Value::as_str) == Some(segment))
+  })
+}
+
+/// Select a field of an event by its dotted path (e.g. `memory.used`,
+/// `cpus.0.usage` or `networks.eth0.received_rate`)
+pub fn select<'a>(value: &'a Value, path: &str) -> Result<&'a Value, CliError> {
+  path
+    .split('.')
+    .try_fold(value, |value, segment| match value {
+      Value::Object(map) => map.get(segment),
+      Value::Array(items) => select_item(items, segment),
+      _ => None,
+    })
+    .ok_or_else(|| CliError::Usage(format!("Unknown field {path}")))
+}
+
+/// Sections of the events holding fields, so the daemon only sends them
+pub fn sections(fields: &[String]) -> Vec<EventSection> {
+  let mut sections = Vec::new();
+  for field in fields {
+    let root = field.split('.').next().unwrap_or_default();
+    match root.parse::<EventSection>() {
+      // Fields outside of the sections are sent with every event
+      Err(_) => return Vec::new(),
+      Ok(section) if !sections.contains(&section) => sections.push(section),
+      Ok(_) => {}
+    }
+  }
+  sections
+}
+
+/// Flatten a value to its scalars with their dotted paths
+pub fn flatten(prefix: &str, value: &Value, out: &mut Vec<(String, Value)>) {
+  let join = |key: &str| match prefix {
+    "" => key.to_owned(),
+    prefix => format!("{prefix}.{key}"),
+  };
+  match value {
+    Value::Object(map) => {
+      for (key, value) in map {
+        flatten(&join(key), value, out);
+      }
+    }
+    Value::Array(items) => {
+      for (i, value) in items.iter().enumerate() {
+        flatten(&join(&i.to_string()), value, out);
+      }
+    }
+    value => out.push((prefix.to_owned(), value.clone())),
+  }
+}
+
+/// Format a scalar for a shell, strings are not quoted and null is empty
+pub fn scalar_string(value: &Value) -> String {
+  match value {
+    Value::Null => String::new(),
+    Value::String(s) => s.clone(),
+    value => value.to_string(),
+  }
+}
+
+/// Keep the fields of an event in their order, the timestamp is always
+/// kept first
+pub fn record(
+  value: &Value,
+  fields: &[String],
+) -> Result<Vec<(String, Value)>, CliError> {
+  let mut record = vec![("timestamp".to_owned(), value["timestamp"].clone())];
+  if fields.is_empty() {
+    if let Value::Object(map) = value {
+      record.extend(
+        map
+          .iter()
+          .filter(|(key, _)| *key != "timestamp")
+          .map(|(key, value)| (key.clone(), value.clone())),
+      );
+    }
+    return Ok(record);
+  }
+  for field in fields {
+    record.push((field.clone(), select(value, field)?.clone()));
+  }
+  Ok(record)
+}
+
+/// Serialize a record as a json object keeping the order of its fields
+pub fn record_json(record: &[(String, Value)]) -> String {
+  let fields = record
+    .iter()
+    .map(|(key, value)| format!("{}:{value}", Value::from(key.as_str())))
+    .collect::<Vec<_>>();
+  format!("{{{}}}", fields.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+  use metrsd_client::stubs::{CpuInfo, MemoryInfo, NetworkInfo};
+
+  use super::*;
+
+  fn event() -> MetrsdEvent {
+    MetrsdEvent {
+      timestamp: 1760000000000,
+      hostname: "metrs-host".into(),
+      memory: MemoryInfo {
+        total: 2048,
+        used: 1024,
+        ..Default::default()
+      },
+      cpus: vec![CpuInfo {
+        name: "cpu0".into(),
+        usage: 12.5,
+        ..Default::default()
+      }],
+      networks: vec![NetworkInfo {
+        name: "eth0".into(),
+        received_rate: 10.0,
+        ..Default::default()
+      }],
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn test_select() {
+    let value = event_value(&event()).unwrap();
+    assert_eq!(select(&value, "memory.used").unwrap(), 1024);
+    assert_eq!(select(&value, "memory.swap_total").unwrap(), 0);
+    assert_eq!(select(&value, "cpus.0.usage").unwrap(), 12.5);
+    assert_eq!(select(&value, "cpus.cpu0.name").unwrap(), "cpu0");
+    assert_eq!(select(&value, "networks.eth0.received_rate").unwrap(), 10.0);
+    assert_eq!(select(&value, "host.load_average.one").unwrap(), 0.0);
+    let err = select(&value, "memory.cached").unwrap_err();
+    assert_eq!(err.to_string(), "Unknown field memory.cached");
+    assert!(select(&value, "cpus.1").is_err());
+    assert!(select(&value, "memory.used.bytes").is_err());
+  }
+
+  #[test]
+  fn test_sections() {
+    let fields = ["cpus".into(), "memory.used".into(), "memory.free".into()];
+    assert_eq!(
+      sections(&fields),
+      vec![EventSection::Cpus, EventSection::Memory]
+    );
+    assert!(sections(&["memory".into(), "hostname".into()]).is_empty());
+  }
+
+  #[test]
+  fn test_record() {
+    let value = event_value(&event()).unwrap();
+    let record =
+      record(&value, &["memory.used".into(), "hostname".into()]).unwrap();
+    assert_eq!(
+      record_json(&record),
+      r#"{"timestamp":1760000000000,"memory.used":1024,"hostname":"metrs-host"}"#
+    );
+    let mut out = Vec::new();
+    flatten("memory", select(&value, "memory").unwrap(), &mut out);
+    assert!(out.contains(&("memory.total".into(), Value::from(2048))));
+    assert_eq!(scalar_string(&Value::from("eth0")), "eth0");
+    assert_eq!(scalar_string(&Value::Null), "");
+  }
+}
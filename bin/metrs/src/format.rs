@@ -0,0 +1,46 @@
+/// Format a number of bytes with the largest binary unit below it
+pub fn format_bytes(bytes: u64) -> String {
+  const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+  let mut value = bytes as f64;
+  let mut unit = 0;
+  while value >= 1024.0 && unit < UNITS.len() - 1 {
+    value /= 1024.0;
+    unit += 1;
+  }
+  match unit {
+    0 => format!("{bytes} B"),
+    unit => format!("{value:.1} {}", UNITS[unit]),
+  }
+}
+
+/// Format a throughput in bytes per second
+pub fn format_rate(rate: f64) -> String {
+  format!("{}/s", format_bytes(rate.max(0.0) as u64))
+}
+
+/// Format an uptime in seconds with days, hours and minutes
+pub fn format_uptime(secs: u64) -> String {
+  let (days, hours, minutes) =
+    (secs / 86400, secs % 86400 / 3600, secs % 3600 / 60);
+  match days {
+    0 => format!("{hours}h {minutes}m"),
+    days => format!("{days}d {hours}h {minutes}m"),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_format() {
+    assert_eq!(format_bytes(0), "0 B");
+    assert_eq!(format_bytes(1023), "1023 B");
+    assert_eq!(format_bytes(1536), "1.5 KiB");
+    assert_eq!(format_bytes(8 * 1024 * 1024 * 1024), "8.0 GiB");
+    assert_eq!(format_rate(2048.0), "2.0 KiB/s");
+    assert_eq!(format_rate(-1.0), "0 B/s");
+    assert_eq!(format_uptime(59), "0h 0m");
+    assert_eq!(format_uptime(90061), "1d 1h 1m");
+  }
+}
@@ -0,0 +1,20 @@
+use metrsd_client::MetrsdClient;
+
+use crate::cli::GetArg;
+use crate::error::CliError;
+use crate::{field, output};
+
+/// Print a field of the latest metrics of the daemon
+pub async fn exec(
+  client: &MetrsdClient,
+  args: &GetArg,
+) -> Result<(), CliError> {
+  let ev = client.snapshot().await?;
+  let value = field::event_value(&ev)?;
+  let (path, value) = match &args.field {
+    Some(path) => (path.as_str(), field::select(&value, path)?),
+    None => ("", &value),
+  };
+  output::print(&output::format_value(path, value, args.format)?)?;
+  Ok(())
+}
@@ -1,13 +1,59 @@
-fn main() {
-  println!("In comming soon...");
+/*
+* metrs is the command line client of metrsd
+* It connects to a daemon to show its metrics in a terminal.
+*/
+
+mod cli;
+mod error;
+mod field;
+mod format;
+mod get;
+mod output;
+mod record;
+mod replay;
+mod top;
+mod watch;
+
+use clap::Parser;
+
+use metrsd_client::MetrsdClient;
+
+use error::CliError;
+
+/// Connect to the daemon set with `--host`
+async fn connect(cli: &cli::Cli) -> Result<MetrsdClient, CliError> {
+  let client = MetrsdClient::connect(cli.host.clone().leak()).await?;
+  Ok(match &cli.token {
+    Some(token) => client.with_token(token),
+    None => client,
+  })
 }
 
-#[cfg(test)]
-mod tests {
-  use super::*;
+async fn run(cli: &cli::Cli) -> Result<(), CliError> {
+  match &cli.command {
+    cli::Command::Top(args) => top::exec(&connect(cli).await?, args).await,
+    cli::Command::Get(args) => get::exec(&connect(cli).await?, args).await,
+    cli::Command::Watch(args) => watch::exec(&connect(cli).await?, args).await,
+    cli::Command::Record(args) => {
+      record::exec(&connect(cli).await?, &cli.host, args).await
+    }
+    cli::Command::Replay(args) => replay::exec(args).await,
+  }
+}
 
-  #[test]
-  fn test_main() {
-    main();
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+  let cli = cli::Cli::parse();
+  match run(&cli).await {
+    Err(CliError::Unreachable(err)) => {
+      eprintln!("Unable to reach the daemon on {}: {err}", cli.host);
+      std::process::exit(error::EXIT_UNREACHABLE);
+    }
+    Err(err) => {
+      eprintln!("{err}");
+      std::process::exit(err.exit_code());
+    }
+    Ok(()) => {}
   }
+  Ok(())
 }
@@ -0,0 +1,192 @@
+use std::io::Write;
+
+use serde_json::Value;
+
+use crate::cli::GetFormat;
+use crate::error::CliError;
+use crate::field::{flatten, scalar_string};
+
+/// Write to stdout, false when the reader closed the pipe, as `head` does
+pub fn print(out: &str) -> Result<bool, CliError> {
+  let mut stdout = std::io::stdout().lock();
+  match stdout
+    .write_all(out.as_bytes())
+    .and_then(|_| stdout.flush())
+  {
+    Ok(()) => Ok(true),
+    Err(err) if err.kind() == std::io::ErrorKind::BrokenPipe => Ok(false),
+    Err(err) => Err(err.into()),
+  }
+}
+
+/// Align the cells in columns under an uppercase header
+fn render_table(header: &[String], rows: &[Vec<String>]) -> String {
+  let widths = header
+    .iter()
+    .enumerate()
+    .map(|(i, title)| {
+      rows
+        .iter()
+        .filter_map(|row| row.get(i))
+        .map(String::len)
+        .fold(title.len(), usize::max)
+    })
+    .collect::<Vec<_>>();
+  let header = header.iter().map(|title| title.to_uppercase()).collect();
+  std::iter::once(&header)
+    .chain(rows)
+    .map(|row: &Vec<String>| {
+      let line = row
+        .iter()
+        .zip(&widths)
+        .map(|(cell, width)| format!("{cell:width$}"))
+        .collect::<Vec<_>>()
+        .join("  ");
+      format!("{}\n", line.trim_end())
+    })
+    .collect()
+}
+
+/// Render a list of objects with a column per field, any other value with a
+/// row per field
+fn table(path: &str, value: &Value) -> String {
+  match value {
+    Value::Array(items)
+      if !items.is_empty() && items.iter().all(Value::is_object) =>
+    {
+      let items = items
+        .iter()
+        .map(|item| {
+          let mut cells = Vec::new();
+          flatten("", item, &mut cells);
+          cells
+        })
+        .collect::<Vec<_>>();
+      let mut header = Vec::<String>::new();
+      for (key, _) in items.iter().flatten() {
+        if !header.contains(key) {
+          header.push(key.clone());
+        }
+      }
+      let rows = items
+        .iter()
+        .map(|cells| {
+          header
+            .iter()
+            .map(|key| {
+              cells
+                .iter()
+                .find(|(cell, _)| cell == key)
+                .map(|(_, value)| scalar_string(value))
+                .unwrap_or_default()
+            })
+            .collect()
+        })
+        .collect::<Vec<_>>();
+      render_table(&header, &rows)
+    }
+    value => {
+      let mut cells = Vec::new();
+      flatten(path, value, &mut cells);
+      let rows = cells
+        .iter()
+        .map(|(key, value)| vec![key.clone(), scalar_string(value)])
+        .collect::<Vec<_>>();
+      render_table(&["field".into(), "value".into()], &rows)
+    }
+  }
+}
+
+/// Format a field of an event selected by its path
+pub fn format_value(
+  path: &str,
+  value: &Value,
+  format: GetFormat,
+) -> Result<String, CliError> {
+  let out = match format {
+    GetFormat::Value => match value {
+      Value::Array(items) if !items.iter().any(is_nested) => items
+        .iter()
+        .map(|item| format!("{}\n", scalar_string(item)))
+        .collect(),
+      Value::Array(_) | Value::Object(_) => format!("{value}\n"),
+      value => format!("{}\n", scalar_string(value)),
+    },
+    GetFormat::Json => format!("{}\n", serde_json::to_string_pretty(value)?),
+    GetFormat::Yaml => serde_yaml::to_string(value)?,
+    GetFormat::Table => table(path, value),
+  };
+  Ok(out)
+}
+
+fn is_nested(value: &Value) -> bool {
+  matches!(value, Value::Array(_) | Value::Object(_))
+}
+
+/// Quote a csv cell holding a separator, a quote or a line break
+pub fn csv_cell(cell: &str) -> String {
+  match cell.contains([',', '"', '\n', '\r']) {
+    true => format!("\"{}\"", cell.replace('"', "\"\"")),
+    false => cell.to_owned(),
+  }
+}
+
+/// Join the cells of a csv line
+pub fn csv_line<S: AsRef<str>>(cells: &[S]) -> String {
+  let cells = cells
+    .iter()
+    .map(|cell| csv_cell(cell.as_ref()))
+    .collect::<Vec<_>>();
+  format!("{}\n", cells.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+
+  #[test]
+  fn test_format_value() {
+    let value = json!(1024);
+    let format = |value, format| format_value("memory.used", value, format);
+    assert_eq!(format(&value, GetFormat::Value).unwrap(), "1024\n");
+    assert_eq!(format(&value, GetFormat::Json).unwrap(), "1024\n");
+    assert_eq!(format(&value, GetFormat::Yaml).unwrap(), "1024\n");
+    assert_eq!(
+      format(&value, GetFormat::Table).unwrap(),
+      "FIELD        VALUE\nmemory.used  1024\n"
+    );
+    let value = json!({ "free": 1024, "used": 2048 });
+    assert_eq!(
+      format(&value, GetFormat::Value).unwrap(),
+      "{\"free\":1024,\"used\":2048}\n"
+    );
+    assert_eq!(
+      format(&value, GetFormat::Yaml).unwrap(),
+      "free: 1024\nused: 2048\n"
+    );
+    assert_eq!(
+      format_value("memory", &value, GetFormat::Table).unwrap(),
+      "FIELD        VALUE\nmemory.free  1024\nmemory.used  2048\n"
+    );
+    let value = json!(["cpu0", "cpu1"]);
+    assert_eq!(format(&value, GetFormat::Value).unwrap(), "cpu0\ncpu1\n");
+    let value = json!([
+      { "name": "cpu0", "usage": 12.5 },
+      { "name": "cpu10", "usage": 1.0 },
+    ]);
+    assert_eq!(
+      format_value("cpus", &value, GetFormat::Table).unwrap(),
+      "NAME   USAGE\ncpu0   12.5\ncpu10  1.0\n"
+    );
+  }
+
+  #[test]
+  fn test_csv() {
+    assert_eq!(
+      csv_line(&["a", "b c", "d,e", "f\"g"]),
+      "a,b c,\"d,e\",\"f\"\"g\"\n"
+    );
+  }
+}
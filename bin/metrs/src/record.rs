@@ -0,0 +1,69 @@
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use futures::StreamExt;
+
+use metrsd_client::{MetrsdClient, stubs::EventFilter};
+use metrs_stubs::recording::{RecordingHeader, RecordingWriter};
+
+use crate::cli::RecordArg;
+use crate::error::CliError;
+
+fn now_millis() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_millis() as u64)
+    .unwrap_or_default()
+}
+
+/// Record the metrics of the daemon until the duration elapsed
+pub async fn exec(
+  client: &MetrsdClient,
+  host: &str,
+  args: &RecordArg,
+) -> Result<(), CliError> {
+  let filter = EventFilter {
+    interval: args.interval,
+    ..Default::default()
+  };
+  let mut events = client.subscribe_with(&filter).await?;
+  let path = args.output.display();
+  let header = RecordingHeader::new(host, now_millis());
+  let mut recording =
+    RecordingWriter::create(&args.output, &header).map_err(|err| {
+      CliError::Error(format!("Unable to create recording {path}: {err}"))
+    })?;
+  let deadline = args.duration.map(|duration| Instant::now() + duration);
+  let mut count = 0;
+  let res = loop {
+    let next = match deadline {
+      None => events.next().await,
+      Some(deadline) => {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match ntex::time::timeout(remaining, events.next()).await {
+          Ok(next) => next,
+          Err(()) => break Ok(()),
+        }
+      }
+    };
+    let ev = match next {
+      None => {
+        break Err(CliError::Unreachable(
+          "The daemon closed the connection".into(),
+        ));
+      }
+      Some(Err(err)) => break Err(err.into()),
+      Some(Ok(ev)) => ev,
+    };
+    if let Err(err) = recording.write(&ev) {
+      break Err(CliError::Error(format!(
+        "Unable to write recording {path}: {err}"
+      )));
+    }
+    count += 1;
+  };
+  recording.finish().map_err(|err| {
+    CliError::Error(format!("Unable to write recording {path}: {err}"))
+  })?;
+  eprintln!("Recorded {count} events to {path}");
+  res
+}
@@ -0,0 +1,84 @@
+use std::time::Duration;
+
+use futures::{Stream, stream};
+
+use metrsd_client::stubs::MetrsdEvent;
+use metrs_stubs::recording::RecordingReader;
+
+use crate::cli::ReplayArg;
+use crate::error::CliError;
+use crate::top;
+
+/// Emit the events of a recording at the pace they were recorded, divided
+/// by the speed
+fn paced<R: std::io::Read>(
+  recording: RecordingReader<R>,
+  speed: f64,
+) -> impl Stream<Item = Result<MetrsdEvent, std::io::Error>> {
+  stream::unfold(
+    (recording, None::<u64>),
+    move |(mut recording, previous)| async move {
+      let ev = match recording.next()? {
+        Err(err) => return Some((Err(err), (recording, previous))),
+        Ok(ev) => ev,
+      };
+      if let Some(previous) = previous {
+        let elapsed = ev.timestamp.saturating_sub(previous);
+        let wait = Duration::from_millis(elapsed).div_f64(speed);
+        ntex::time::sleep(wait).await;
+      }
+      let timestamp = ev.timestamp;
+      Some((Ok(ev), (recording, Some(timestamp))))
+    },
+  )
+}
+
+/// Play a recording in the live dashboard
+pub async fn exec(args: &ReplayArg) -> Result<(), CliError> {
+  let recording = RecordingReader::open(&args.file).map_err(|err| {
+    CliError::Error(format!(
+      "Unable to read recording {}: {err}",
+      args.file.display()
+    ))
+  })?;
+  let replay =
+    format!("Replay of {} at {}x", recording.header().source, args.speed);
+  top::show(Box::pin(paced(recording, args.speed)), Some(replay)).await
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Instant;
+
+  use futures::StreamExt;
+  use metrs_stubs::recording::{RecordingHeader, RecordingWriter};
+
+  use super::*;
+
+  #[ntex::test]
+  async fn test_paced() {
+    let header = RecordingHeader::new("http://127.0.0.1:8080", 0);
+    let mut writer = RecordingWriter::new(Vec::new(), &header).unwrap();
+    for sequence in 0..3 {
+      let ev = MetrsdEvent {
+        timestamp: 1760000000000 + sequence * 1000,
+        sequence,
+        ..Default::default()
+      };
+      writer.write(&ev).unwrap();
+    }
+    let data = writer.finish().unwrap();
+    let recording = RecordingReader::new(data.as_slice()).unwrap();
+    let started = Instant::now();
+    let events = paced(recording, 10.0).collect::<Vec<_>>().await;
+    let sequences = events
+      .into_iter()
+      .map(|ev| ev.unwrap().sequence)
+      .collect::<Vec<_>>();
+    assert_eq!(sequences, vec![0, 1, 2]);
+    // 2s of recording played ten times as fast
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(190), "{elapsed:?}");
+    assert!(elapsed < Duration::from_secs(1), "{elapsed:?}");
+  }
+}
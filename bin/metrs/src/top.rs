@@ -0,0 +1,432 @@
+use futures::{Stream, StreamExt, channel::mpsc, stream};
+use ratatui::{
+  DefaultTerminal, Frame,
+  crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+  layout::{Constraint, Layout, Rect},
+  style::{Color, Modifier, Style},
+  text::Line,
+  widgets::{Block, Gauge, LineGauge, Paragraph, Row, Table},
+};
+
+use metrsd_client::{
+  MetrsdClient,
+  stubs::{EventFilter, EventSection, MetrsdEvent},
+};
+
+use crate::cli::TopArg;
+use crate::error::CliError;
+use crate::format::{format_bytes, format_rate, format_uptime};
+
+/// Maximum number of cores shown in a column of the cpu bars
+const CPU_ROWS: usize = 8;
+
+/// What the dashboard reacts to
+enum Message {
+  Event(Result<Box<MetrsdEvent>, String>),
+  Input(Event),
+  /// The daemon closed the stream or the recording ended
+  Closed,
+}
+
+/// Last metrics received from the daemon
+#[derive(Default)]
+struct Dashboard {
+  event: Option<MetrsdEvent>,
+  /// Last invalid event sent by the daemon
+  error: Option<String>,
+  /// Description of the recording played, none when live
+  replay: Option<String>,
+  /// The recording played has no more event
+  ended: bool,
+}
+
+/// Color of a usage in percent
+fn usage_color(percent: f64) -> Color {
+  match percent {
+    p if p >= 90.0 => Color::Red,
+    p if p >= 70.0 => Color::Yellow,
+    _ => Color::Green,
+  }
+}
+
+fn percent(used: u64, total: u64) -> f64 {
+  match total {
+    0 => 0.0,
+    total => used as f64 * 100.0 / total as f64,
+  }
+}
+
+fn header_row(cells: &[&'static str]) -> Row<'static> {
+  Row::new(cells.to_vec()).style(Style::new().add_modifier(Modifier::BOLD))
+}
+
+impl Dashboard {
+  fn render(&self, frame: &mut Frame) {
+    let Some(ev) = &self.event else {
+      frame.render_widget(
+        Paragraph::new("Waiting for the metrics of the daemon..."),
+        frame.area(),
+      );
+      return;
+    };
+    let cpu_rows = ev.cpus.len().min(CPU_ROWS) as u16;
+    let [header, cpus, memory, tables, footer] = Layout::vertical([
+      Constraint::Length(2),
+      Constraint::Length(cpu_rows + 2),
+      Constraint::Length(4),
+      Constraint::Fill(1),
+      Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [disks, networks] =
+      Layout::vertical([Constraint::Fill(1), Constraint::Fill(1)])
+        .areas(tables);
+    self.render_header(frame, header, ev);
+    self.render_cpus(frame, cpus, ev);
+    self.render_memory(frame, memory, ev);
+    self.render_disks(frame, disks, ev);
+    self.render_networks(frame, networks, ev);
+    frame.render_widget(self.status(), footer);
+  }
+
+  fn status(&self) -> Line<'_> {
+    if let Some(err) = &self.error {
+      return Line::styled(err.as_str(), Style::new().fg(Color::Red));
+    }
+    let hint = match self.ended {
+      true => "End of the recording, press q to quit",
+      false => "Press q to quit",
+    };
+    let status = match &self.replay {
+      Some(replay) => format!("{replay} - {hint}"),
+      None => hint.to_owned(),
+    };
+    Line::styled(status, Style::new().fg(Color::DarkGray))
+  }
+
+  fn render_header(&self, frame: &mut Frame, area: Rect, ev: &MetrsdEvent) {
+    let host = &ev.host;
+    let load = &host.load_average;
+    let lines = vec![
+      Line::from(format!(
+        "{} - up {} - load average {:.2} {:.2} {:.2}",
+        ev.hostname,
+        format_uptime(host.uptime),
+        load.one,
+        load.five,
+        load.fifteen,
+      ))
+      .style(Style::new().add_modifier(Modifier::BOLD)),
+      Line::from(format!(
+        "{} {} - kernel {} - {}",
+        host.os_name, host.os_version, host.kernel_version, host.arch,
+      )),
+    ];
+    frame.render_widget(Paragraph::new(lines), area);
+  }
+
+  fn render_cpus(&self, frame: &mut Frame, area: Rect, ev: &MetrsdEvent) {
+    let average = match ev.cpus.len() {
+      0 => 0.0,
+      count => {
+        ev.cpus.iter().map(|cpu| cpu.usage as f64).sum::<f64>() / count as f64
+      }
+    };
+    let block = Block::bordered().title(format!(" Cpu {average:.1}% "));
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+    if ev.cpus.is_empty() {
+      return;
+    }
+    let columns = ev.cpus.len().div_ceil(CPU_ROWS);
+    let columns =
+      Layout::horizontal(vec![Constraint::Fill(1); columns]).split(inner);
+    for (column, cpus) in columns.iter().zip(ev.cpus.chunks(CPU_ROWS)) {
+      let rows = Layout::vertical(vec![Constraint::Length(1); cpus.len()])
+        .split(*column);
+      for (row, cpu) in rows.iter().zip(cpus) {
+        let usage = (cpu.usage as f64).clamp(0.0, 100.0);
+        let gauge = LineGauge::default()
+          .ratio(usage / 100.0)
+          .label(format!("{:<6} {usage:5.1}%", cpu.name))
+          .filled_style(Style::new().fg(usage_color(usage)));
+        frame.render_widget(gauge, *row);
+      }
+    }
+  }
+
+  fn render_memory(&self, frame: &mut Frame, area: Rect, ev: &MetrsdEvent) {
+    let block = Block::bordered().title(" Memory ");
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+    let rows = Layout::vertical([Constraint::Length(1); 2]).split(inner);
+    let memory = &ev.memory;
+    let gauges = [
+      ("Mem", memory.used, memory.total),
+      ("Swap", memory.swap_used, memory.swap_total),
+    ];
+    for (row, (name, used, total)) in rows.iter().zip(gauges) {
+      let usage = percent(used, total);
+      let gauge = Gauge::default()
+        .ratio((usage / 100.0).clamp(0.0, 1.0))
+        .label(format!(
+          "{name} {} / {} ({usage:.1}%)",
+          format_bytes(used),
+          format_bytes(total)
+        ))
+        .gauge_style(Style::new().fg(usage_color(usage)));
+      frame.render_widget(gauge, *row);
+    }
+  }
+
+  fn render_disks(&self, frame: &mut Frame, area: Rect, ev: &MetrsdEvent) {
+    let rows = ev.disks.iter().map(|disk| {
+      let used = disk.total_space.saturating_sub(disk.available_space);
+      let usage = percent(used, disk.total_space);
+      Row::new(vec![
+        disk.mount_point.clone(),
+        disk.device_name.clone(),
+        disk.file_system.clone(),
+        format_bytes(used),
+        format_bytes(disk.total_space),
+        format!("{usage:.1}%"),
+        format_rate(disk.read_rate),
+        format_rate(disk.written_rate),
+      ])
+      .style(Style::new().fg(usage_color(usage)))
+    });
+    let table = Table::new(
+      rows,
+      [
+        Constraint::Fill(2),
+        Constraint::Fill(2),
+        Constraint::Length(8),
+        Constraint::Length(10),
+        Constraint::Length(10),
+        Constraint::Length(6),
+        Constraint::Length(12),
+        Constraint::Length(12),
+      ],
+    )
+    .header(header_row(&[
+      "Mount", "Device", "Fs", "Used", "Total", "Use%", "Read", "Write",
+    ]))
+    .block(Block::bordered().title(" Disks "));
+    frame.render_widget(table, area);
+  }
+
+  fn render_networks(&self, frame: &mut Frame, area: Rect, ev: &MetrsdEvent) {
+    let rows = ev.networks.iter().map(|network| {
+      Row::new(vec![
+        network.name.clone(),
+        format_rate(network.received_rate),
+        format_rate(network.transmitted_rate),
+        format_bytes(network.total_received),
+        format_bytes(network.total_transmitted),
+      ])
+    });
+    let table = Table::new(
+      rows,
+      [
+        Constraint::Fill(1),
+        Constraint::Length(12),
+        Constraint::Length(12),
+        Constraint::Length(10),
+        Constraint::Length(10),
+      ],
+    )
+    .header(header_row(&[
+      "Interface",
+      "Rx",
+      "Tx",
+      "Total rx",
+      "Total tx",
+    ]))
+    .block(Block::bordered().title(" Networks "));
+    frame.render_widget(table, area);
+  }
+}
+
+/// Forward the terminal events, the reads are blocking so they run on
+/// their own thread
+fn read_input(tx: mpsc::UnboundedSender<Message>) {
+  while let Ok(input) = event::read() {
+    if tx.unbounded_send(Message::Input(input)).is_err() {
+      break;
+    }
+  }
+}
+
+/// Return true when a key quits the dashboard
+fn is_quit(input: &Event) -> bool {
+  let Event::Key(key) = input else {
+    return false;
+  };
+  key.kind == KeyEventKind::Press
+    && (matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+      || (key.code == KeyCode::Char('c')
+        && key.modifiers.contains(KeyModifiers::CONTROL)))
+}
+
+async fn run<S, E>(
+  terminal: &mut DefaultTerminal,
+  events: S,
+  replay: Option<String>,
+) -> Result<(), CliError>
+where
+  S: Stream<Item = Result<MetrsdEvent, E>> + Unpin,
+  E: std::fmt::Display,
+{
+  let (tx, input) = mpsc::unbounded();
+  std::thread::spawn(move || read_input(tx));
+  let events = events
+    .map(|ev| Message::Event(ev.map(Box::new).map_err(|err| err.to_string())))
+    .chain(stream::iter([Message::Closed]));
+  let mut messages = stream::select(events, input);
+  let mut dashboard = Dashboard {
+    replay,
+    ..Default::default()
+  };
+  terminal.draw(|frame| dashboard.render(frame))?;
+  while let Some(message) = messages.next().await {
+    match message {
+      Message::Event(Ok(ev)) => {
+        dashboard.event = Some(*ev);
+        dashboard.error = None;
+      }
+      Message::Event(Err(err)) => dashboard.error = Some(err),
+      Message::Input(input) if is_quit(&input) => return Ok(()),
+      // Redraw on resize
+      Message::Input(_) => {}
+      // The last frame of a recording stays until the user quits
+      Message::Closed if dashboard.replay.is_some() => dashboard.ended = true,
+      Message::Closed => {
+        return Err(CliError::Unreachable(
+          "The daemon closed the connection".into(),
+        ));
+      }
+    }
+    terminal.draw(|frame| dashboard.render(frame))?;
+  }
+  Ok(())
+}
+
+/// Take over the terminal to show the events until the user quits, a
+/// replay describes the recording the events come from
+pub async fn show<S, E>(
+  events: S,
+  replay: Option<String>,
+) -> Result<(), CliError>
+where
+  S: Stream<Item = Result<MetrsdEvent, E>> + Unpin,
+  E: std::fmt::Display,
+{
+  let mut terminal = ratatui::init();
+  let res = run(&mut terminal, events, replay).await;
+  ratatui::restore();
+  res
+}
+
+/// Show a live dashboard of the metrics until the user quits
+pub async fn exec(
+  client: &MetrsdClient,
+  args: &TopArg,
+) -> Result<(), CliError> {
+  let filter = EventFilter {
+    include: vec![
+      EventSection::Host,
+      EventSection::Memory,
+      EventSection::Cpus,
+      EventSection::Disks,
+      EventSection::Networks,
+    ],
+    interval: args.interval,
+    ..Default::default()
+  };
+  // Subscribe before taking over the terminal so errors are printed
+  let events = client.subscribe_with(&filter).await?;
+  show(events, None).await
+}
+
+#[cfg(test)]
+mod tests {
+  use ratatui::{Terminal, backend::TestBackend};
+
+  use metrsd_client::stubs::{
+    CpuInfo, DiskInfo, DiskInfoKind, MemoryInfo, NetworkInfo,
+  };
+
+  use super::*;
+
+  fn render(dashboard: &Dashboard) -> String {
+    let mut terminal = Terminal::new(TestBackend::new(120, 40)).unwrap();
+    terminal.draw(|frame| dashboard.render(frame)).unwrap();
+    let buffer = terminal.backend().buffer();
+    buffer
+      .content()
+      .chunks(buffer.area.width as usize)
+      .map(|line| line.iter().map(|cell| cell.symbol()).collect::<String>())
+      .collect::<Vec<_>>()
+      .join("\n")
+  }
+
+  #[test]
+  fn test_render() {
+    let mut dashboard = Dashboard::default();
+    assert!(render(&dashboard).contains("Waiting for the metrics"));
+    dashboard.event = Some(MetrsdEvent {
+      hostname: "metrs-host".into(),
+      memory: MemoryInfo {
+        total: 8 * 1024 * 1024 * 1024,
+        used: 2 * 1024 * 1024 * 1024,
+        ..Default::default()
+      },
+      cpus: (0..12)
+        .map(|i| CpuInfo {
+          name: format!("cpu{i}"),
+          usage: i as f32 * 8.0,
+          ..Default::default()
+        })
+        .collect(),
+      disks: vec![DiskInfo {
+        kind: DiskInfoKind::SSD,
+        device_name: "/dev/sda1".into(),
+        file_system: "ext4".into(),
+        mount_point: "/data".into(),
+        total_space: 1024 * 1024 * 1024,
+        available_space: 512 * 1024 * 1024,
+        is_removable: false,
+        read_bytes: 0,
+        written_bytes: 0,
+        total_read_bytes: 0,
+        total_written_bytes: 0,
+        read_rate: 2048.0,
+        written_rate: 0.0,
+      }],
+      networks: vec![NetworkInfo {
+        name: "eth0".into(),
+        received_rate: 1536.0,
+        ..Default::default()
+      }],
+      ..Default::default()
+    });
+    let screen = render(&dashboard);
+    assert!(screen.contains("metrs-host"));
+    assert!(screen.contains("cpu0"));
+    // Cores past the first column are rendered in a second one
+    assert!(screen.contains("cpu11"));
+    assert!(screen.contains("Mem 2.0 GiB / 8.0 GiB (25.0%)"));
+    assert!(screen.contains("/data"));
+    assert!(screen.contains("50.0%"));
+    assert!(screen.contains("2.0 KiB/s"));
+    assert!(screen.contains("eth0"));
+    assert!(screen.contains("1.5 KiB/s"));
+    dashboard.replay = Some("Replay of unix:///run/metrsd.sock at 2x".into());
+    dashboard.ended = true;
+    assert!(render(&dashboard).contains(
+      "Replay of unix:///run/metrsd.sock at 2x - End of the recording"
+    ));
+    dashboard.error = Some("Invalid event".into());
+    assert!(render(&dashboard).contains("Invalid event"));
+  }
+}
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+
+use futures::StreamExt;
+use serde_json::Value;
+
+use metrsd_client::{MetrsdClient, stubs::EventFilter};
+
+use crate::cli::{WatchArg, WatchFormat};
+use crate::error::CliError;
+use crate::{field, output};
+
+/// Write the records of the events as csv, the columns are the ones of the
+/// first event
+#[derive(Default)]
+struct CsvWriter {
+  columns: Option<Vec<String>>,
+}
+
+impl CsvWriter {
+  fn write(&mut self, record: &[(String, Value)]) -> String {
+    let mut cells = Vec::new();
+    for (key, value) in record {
+      field::flatten(key, value, &mut cells);
+    }
+    let mut out = String::new();
+    let columns = self.columns.get_or_insert_with(|| {
+      let columns =
+        cells.iter().map(|(key, _)| key.clone()).collect::<Vec<_>>();
+      out.push_str(&output::csv_line(&columns));
+      columns
+    });
+    let cells = cells.into_iter().collect::<HashMap<_, _>>();
+    let row = columns
+      .iter()
+      .map(|column| {
+        cells
+          .get(column)
+          .map(field::scalar_string)
+          .unwrap_or_default()
+      })
+      .collect::<Vec<_>>();
+    out.push_str(&output::csv_line(&row));
+    out
+  }
+}
+
+/// Stream fields of the metrics of the daemon to stdout
+pub async fn exec(
+  client: &MetrsdClient,
+  args: &WatchArg,
+) -> Result<(), CliError> {
+  let filter = EventFilter {
+    include: field::sections(&args.fields),
+    interval: args.interval,
+    ..Default::default()
+  };
+  let mut events = client.subscribe_with(&filter).await?;
+  let mut csv = CsvWriter::default();
+  let mut count = 0;
+  while let Some(ev) = events.next().await {
+    let value = field::event_value(&ev?)?;
+    let record = field::record(&value, &args.fields)?;
+    let out = match args.format {
+      WatchFormat::Ndjson => format!("{}\n", field::record_json(&record)),
+      WatchFormat::Csv => csv.write(&record),
+    };
+    if !output::print(&out)? {
+      return Ok(());
+    }
+    count += 1;
+    if args.count == Some(count) {
+      return Ok(());
+    }
+  }
+  Err(CliError::Unreachable(
+    "The daemon closed the connection".into(),
+  ))
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+
+  #[test]
+  fn test_csv_writer() {
+    let mut csv = CsvWriter::default();
+    let record = vec![
+      ("timestamp".to_owned(), json!(1)),
+      ("memory".to_owned(), json!({ "free": 1024, "used": 2048 })),
+      ("hostname".to_owned(), json!("metrs, host")),
+    ];
+    assert_eq!(
+      csv.write(&record),
+      "timestamp,memory.free,memory.used,hostname\n1,1024,2048,\"metrs, host\"\n"
+    );
+    let record = vec![
+      ("timestamp".to_owned(), json!(2)),
+      ("memory".to_owned(), json!({ "used": 4096 })),
+    ];
+    assert_eq!(csv.write(&record), "2,,4096,\n");
+  }
+}
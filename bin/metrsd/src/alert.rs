@@ -0,0 +1,649 @@
+use std::{
+  collections::{HashMap, HashSet},
+  path::Path,
+  time::Duration,
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use metrs_stubs::{
+  AlertEvent, AlertState, MetrsdEvent, format_duration, parse_duration,
+  parse_size,
+};
+
+use crate::error::MetrsError;
+
+/// Number of resolved alerts kept for the alerts route
+const MAX_RESOLVED: usize = 100;
+
+/// Metric of an event a rule is evaluated against
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum AlertMetric {
+  #[serde(rename = "memory.used")]
+  MemoryUsed,
+  #[serde(rename = "memory.used_percent")]
+  MemoryUsedPercent,
+  #[serde(rename = "swap.used_percent")]
+  SwapUsedPercent,
+  /// Average usage of the cpus in percent
+  #[serde(rename = "cpu.usage")]
+  CpuUsage,
+  #[serde(rename = "load.one")]
+  LoadOne,
+  #[serde(rename = "load.five")]
+  LoadFive,
+  #[serde(rename = "load.fifteen")]
+  LoadFifteen,
+  /// Available space of a disk in bytes, per mount point
+  #[serde(rename = "disk.available")]
+  DiskAvailable,
+  #[serde(rename = "disk.used_percent")]
+  DiskUsedPercent,
+  /// Bytes received per second, per interface
+  #[serde(rename = "network.received_rate")]
+  NetworkReceivedRate,
+  #[serde(rename = "network.transmitted_rate")]
+  NetworkTransmittedRate,
+  /// Temperature in celsius, per sensor label
+  #[serde(rename = "component.temperature")]
+  ComponentTemperature,
+  /// Cpu usage in percent, per cgroup path
+  #[serde(rename = "cgroup.cpu_usage")]
+  CgroupCpuUsage,
+  #[serde(rename = "cgroup.memory_current")]
+  CgroupMemoryCurrent,
+}
+
+/// Percentage of a part of a total, 0 when the total is unknown
+fn percent(part: u64, total: u64) -> f64 {
+  if total == 0 {
+    return 0.0;
+  }
+  part as f64 * 100.0 / total as f64
+}
+
+impl AlertMetric {
+  /// Values of the metric in an event with the instance they belong to
+  fn values(&self, ev: &MetrsdEvent) -> Vec<(String, f64)> {
+    let host = |value: f64| vec![(String::new(), value)];
+    match self {
+      Self::MemoryUsed => host(ev.memory.used as f64),
+      Self::MemoryUsedPercent => host(percent(ev.memory.used, ev.memory.total)),
+      Self::SwapUsedPercent => {
+        host(percent(ev.memory.swap_used, ev.memory.swap_total))
+      }
+      Self::CpuUsage if ev.cpus.is_empty() => Vec::new(),
+      Self::CpuUsage => host(
+        ev.cpus.iter().map(|cpu| cpu.usage as f64).sum::<f64>()
+          / ev.cpus.len() as f64,
+      ),
+      Self::LoadOne => host(ev.host.load_average.one),
+      Self::LoadFive => host(ev.host.load_average.five),
+      Self::LoadFifteen => host(ev.host.load_average.fifteen),
+      Self::DiskAvailable => ev
+        .disks
+        .iter()
+        .map(|disk| (disk.mount_point.clone(), disk.available_space as f64))
+        .collect(),
+      Self::DiskUsedPercent => ev
+        .disks
+        .iter()
+        .map(|disk| {
+          let used = disk.total_space.saturating_sub(disk.available_space);
+          (disk.mount_point.clone(), percent(used, disk.total_space))
+        })
+        .collect(),
+      Self::NetworkReceivedRate => ev
+        .networks
+        .iter()
+        .map(|net| (net.name.clone(), net.received_rate))
+        .collect(),
+      Self::NetworkTransmittedRate => ev
+        .networks
+        .iter()
+        .map(|net| (net.name.clone(), net.transmitted_rate))
+        .collect(),
+      Self::ComponentTemperature => ev
+        .components
+        .iter()
+        .filter_map(|component| {
+          Some((component.label.clone(), component.temperature? as f64))
+        })
+        .collect(),
+      Self::CgroupCpuUsage => ev
+        .cgroups
+        .iter()
+        .map(|cgroup| (cgroup.path.clone(), cgroup.cpu_usage))
+        .collect(),
+      Self::CgroupMemoryCurrent => ev
+        .cgroups
+        .iter()
+        .map(|cgroup| (cgroup.path.clone(), cgroup.memory_current as f64))
+        .collect(),
+    }
+  }
+}
+
+impl std::fmt::Display for AlertMetric {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let value = serde_json::to_value(self).map_err(|_| std::fmt::Error)?;
+    write!(f, "{}", value.as_str().unwrap_or_default())
+  }
+}
+
+/// Comparison of the value of a metric with the threshold of a rule
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Comparison {
+  #[serde(rename = ">")]
+  Above,
+  #[serde(rename = ">=")]
+  AboveOrEqual,
+  #[serde(rename = "<")]
+  Below,
+  #[serde(rename = "<=")]
+  BelowOrEqual,
+}
+
+impl Comparison {
+  fn holds(&self, value: f64, threshold: f64) -> bool {
+    match self {
+      Self::Above => value > threshold,
+      Self::AboveOrEqual => value >= threshold,
+      Self::Below => value < threshold,
+      Self::BelowOrEqual => value <= threshold,
+    }
+  }
+
+  /// Threshold moved by the hysteresis a firing alert must cross to resolve
+  fn resolve_threshold(&self, threshold: f64, hysteresis: f64) -> f64 {
+    match self {
+      Self::Above | Self::AboveOrEqual => threshold - hysteresis,
+      Self::Below | Self::BelowOrEqual => threshold + hysteresis,
+    }
+  }
+}
+
+impl std::fmt::Display for Comparison {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let op = match self {
+      Self::Above => ">",
+      Self::AboveOrEqual => ">=",
+      Self::Below => "<",
+      Self::BelowOrEqual => "<=",
+    };
+    write!(f, "{op}")
+  }
+}
+
+/// Accept a threshold as a number, a percentage such as `"90%"` or a size
+/// such as `"5G"`
+fn deserialize_threshold<'de, D>(deserializer: D) -> Result<f64, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  #[derive(Deserialize)]
+  #[serde(untagged)]
+  enum Threshold {
+    Number(f64),
+    Text(String),
+  }
+  match Threshold::deserialize(deserializer)? {
+    Threshold::Number(value) => Ok(value),
+    Threshold::Text(text) => match text.trim().strip_suffix('%') {
+      Some(percent) => percent.trim().parse().map_err(serde::de::Error::custom),
+      None => parse_size(&text)
+        .map(|size| size as f64)
+        .map_err(serde::de::Error::custom),
+    },
+  }
+}
+
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  let duration = String::deserialize(deserializer)?;
+  parse_duration(&duration).map_err(serde::de::Error::custom)
+}
+
+fn serialize_duration<S>(
+  duration: &Duration,
+  serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+  S: Serializer,
+{
+  serializer.serialize_str(&format_duration(*duration))
+}
+
+fn default_severity() -> String {
+  "warning".into()
+}
+
+/// A threshold on a metric firing once its condition held for a duration
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRule {
+  pub name: String,
+  pub metric: AlertMetric,
+  /// Mount point, interface, sensor label or cgroup path to evaluate, all
+  /// of them when not set
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub target: Option<String>,
+  pub op: Comparison,
+  #[serde(deserialize_with = "deserialize_threshold")]
+  pub threshold: f64,
+  /// Duration the condition must hold before the alert fires
+  #[serde(
+    default,
+    rename = "for",
+    deserialize_with = "deserialize_duration",
+    serialize_with = "serialize_duration"
+  )]
+  pub for_duration: Duration,
+  /// Margin the value must go back past the threshold to resolve the alert
+  #[serde(default)]
+  pub hysteresis: f64,
+  #[serde(default = "default_severity")]
+  pub severity: String,
+}
+
+impl AlertRule {
+  fn summary(&self, instance: &str, value: f64) -> String {
+    let metric = match instance {
+      "" => self.metric.to_string(),
+      instance => format!("{} {instance}", self.metric),
+    };
+    format!(
+      "{metric} {op} {threshold} for {duration}s, value {value:.2}",
+      op = self.op,
+      threshold = self.threshold,
+      duration = self.for_duration.as_secs(),
+    )
+  }
+}
+
+/// Content of an alert rules file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRules {
+  #[serde(default)]
+  pub rules: Vec<AlertRule>,
+}
+
+impl AlertRules {
+  /// Read the rules of a toml file
+  pub fn load(path: &Path) -> Result<Self, MetrsError> {
+    let content = std::fs::read_to_string(path).map_err(|err| {
+      MetrsError::Error(format!(
+        "Unable to read alert rules {}: {err}",
+        path.display()
+      ))
+    })?;
+    let rules = toml::from_str::<Self>(&content).map_err(|err| {
+      MetrsError::Error(format!(
+        "Invalid alert rules {}: {err}",
+        path.display()
+      ))
+    })?;
+    rules.validate()?;
+    Ok(rules)
+  }
+
+  /// Check the rules names are unique
+  pub fn validate(&self) -> Result<(), MetrsError> {
+    for (index, rule) in self.rules.iter().enumerate() {
+      if self.rules[..index]
+        .iter()
+        .any(|other| other.name == rule.name)
+      {
+        return Err(MetrsError::Error(format!(
+          "Duplicate alert rule: {}",
+          rule.name
+        )));
+      }
+    }
+    Ok(())
+  }
+}
+
+/// State of a rule for an instance whose condition holds
+#[derive(Debug, Clone, Copy)]
+enum RuleState {
+  /// The condition holds since a timestamp but not for long enough yet
+  Pending { since: u64 },
+  /// The alert fires since a timestamp
+  Firing { since: u64 },
+}
+
+/// Evaluate the alert rules against every event
+#[derive(Debug, Default)]
+pub struct AlertEngine {
+  rules: Vec<AlertRule>,
+  /// State of the rules by rule name and instance
+  states: HashMap<(String, String), RuleState>,
+  /// Latest transition of the firing and recently resolved alerts
+  alerts: Vec<AlertEvent>,
+  /// Firing alerts of the rules removed or changed by a reload, resolved
+  /// with the next event
+  orphaned: Vec<AlertEvent>,
+}
+
+/// Resolve an alert whose rule or instance can no longer be evaluated
+fn resolve(alert: AlertEvent, now: u64) -> AlertEvent {
+  AlertEvent {
+    state: AlertState::Resolved,
+    timestamp: now,
+    ..alert
+  }
+}
+
+impl AlertEngine {
+  pub fn new(rules: AlertRules) -> Self {
+    Self {
+      rules: rules.rules,
+      ..Default::default()
+    }
+  }
+
+  /// Replace the rules, the state of the rules still defined the same way
+  /// is kept while the firing alerts of the removed or changed ones resolve
+  /// with the next event
+  pub fn set_rules(&mut self, rules: AlertRules) {
+    let previous = std::mem::replace(&mut self.rules, rules.rules);
+    let current = &self.rules;
+    let unchanged = |name: &str| {
+      current
+        .iter()
+        .any(|rule| rule.name == name && previous.contains(rule))
+    };
+    self.states.retain(|(name, _), _| unchanged(name));
+    let (kept, orphaned) = std::mem::take(&mut self.alerts)
+      .into_iter()
+      .partition(|alert| {
+        alert.state == AlertState::Resolved || unchanged(&alert.rule)
+      });
+    self.alerts = kept;
+    self.orphaned.extend(orphaned);
+  }
+
+  /// Keep the latest transition of an alert, dropping the oldest resolved
+  /// alerts past the limit
+  fn record(&mut self, alert: AlertEvent) {
+    self.alerts.retain(|other| {
+      other.rule != alert.rule || other.instance != alert.instance
+    });
+    self.alerts.push(alert);
+    let resolved = self
+      .alerts
+      .iter()
+      .filter(|alert| alert.state == AlertState::Resolved)
+      .count();
+    if resolved > MAX_RESOLVED {
+      if let Some(index) = self
+        .alerts
+        .iter()
+        .position(|alert| alert.state == AlertState::Resolved)
+      {
+        self.alerts.remove(index);
+      }
+    }
+  }
+
+  /// Evaluate the rules against an event, returning the alerts firing or
+  /// resolving with it
+  pub fn evaluate(&mut self, ev: &MetrsdEvent) -> Vec<AlertEvent> {
+    let now = ev.timestamp;
+    let mut transitions = std::mem::take(&mut self.orphaned)
+      .into_iter()
+      .map(|alert| resolve(alert, now))
+      .collect::<Vec<_>>();
+    let mut seen = HashSet::new();
+    for rule in &self.rules {
+      let values =
+        rule.metric.values(ev).into_iter().filter(|(instance, _)| {
+          rule.target.as_ref().is_none_or(|target| target == instance)
+        });
+      for (instance, value) in values {
+        let key = (rule.name.clone(), instance.clone());
+        seen.insert(key.clone());
+        let alert = |state, started_at| AlertEvent {
+          rule: rule.name.clone(),
+          instance: instance.clone(),
+          state,
+          severity: rule.severity.clone(),
+          summary: rule.summary(&instance, value),
+          value,
+          threshold: rule.threshold,
+          started_at,
+          timestamp: now,
+          hostname: ev.hostname.clone(),
+        };
+        let holds = rule.op.holds(value, rule.threshold);
+        let for_duration = rule.for_duration.as_millis() as u64;
+        match self.states.get(&key).copied() {
+          None | Some(RuleState::Pending { .. }) if !holds => {
+            self.states.remove(&key);
+          }
+          None if for_duration > 0 => {
+            self.states.insert(key, RuleState::Pending { since: now });
+          }
+          Some(RuleState::Pending { since })
+            if now.saturating_sub(since) < for_duration => {}
+          None | Some(RuleState::Pending { .. }) => {
+            self.states.insert(key, RuleState::Firing { since: now });
+            transitions.push(alert(AlertState::Firing, now));
+          }
+          Some(RuleState::Firing { since }) => {
+            let threshold =
+              rule.op.resolve_threshold(rule.threshold, rule.hysteresis);
+            if !rule.op.holds(value, threshold) {
+              self.states.remove(&key);
+              transitions.push(alert(AlertState::Resolved, since));
+            }
+          }
+        }
+      }
+    }
+    // The instances missing from the event, such as an unmounted disk or a
+    // disabled section, resolve their alerts
+    let missing = self
+      .states
+      .keys()
+      .filter(|key| !seen.contains(*key))
+      .cloned()
+      .collect::<Vec<_>>();
+    for (rule, instance) in missing {
+      let state = self.states.remove(&(rule.clone(), instance.clone()));
+      if !matches!(state, Some(RuleState::Firing { .. })) {
+        continue;
+      }
+      let alert = self
+        .alerts
+        .iter()
+        .find(|alert| alert.rule == rule && alert.instance == instance);
+      if let Some(alert) = alert {
+        transitions.push(resolve(alert.clone(), now));
+      }
+    }
+    for alert in &transitions {
+      log::info!("Alert {} {:?}: {}", alert.rule, alert.state, alert.summary);
+      self.record(alert.clone());
+    }
+    transitions
+  }
+
+  /// Firing alerts and the most recently resolved ones
+  pub fn alerts(&self) -> Vec<AlertEvent> {
+    self.alerts.clone()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use metrs_stubs::{DiskInfo, DiskInfoKind, MemoryInfo};
+
+  const RULES: &str = r#"
+[[rules]]
+name = "memory_high"
+metric = "memory.used_percent"
+op = ">"
+threshold = "90%"
+for = "2m"
+hysteresis = 5
+severity = "critical"
+
+[[rules]]
+name = "root_disk_low"
+metric = "disk.available"
+target = "/"
+op = "<"
+threshold = "5G"
+"#;
+
+  fn event(timestamp: u64, used: u64, available_space: u64) -> MetrsdEvent {
+    let disk = |mount_point: &str| DiskInfo {
+      kind: DiskInfoKind::SSD,
+      device_name: "/dev/sda".into(),
+      file_system: "ext4".into(),
+      mount_point: mount_point.into(),
+      total_space: 100 * 1024 * 1024 * 1024,
+      available_space,
+      is_removable: false,
+      read_bytes: 0,
+      written_bytes: 0,
+      total_read_bytes: 0,
+      total_written_bytes: 0,
+      read_rate: 0.0,
+      written_rate: 0.0,
+    };
+    MetrsdEvent {
+      timestamp,
+      memory: MemoryInfo {
+        total: 100,
+        used,
+        ..Default::default()
+      },
+      disks: vec![disk("/"), disk("/data")],
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn test_parse_rules() {
+    let rules = toml::from_str::<AlertRules>(RULES).unwrap();
+    assert_eq!(rules.rules.len(), 2);
+    let rule = &rules.rules[0];
+    assert_eq!(rule.metric, AlertMetric::MemoryUsedPercent);
+    assert_eq!(rule.threshold, 90.0);
+    assert_eq!(rule.for_duration, Duration::from_secs(120));
+    assert_eq!(rule.severity, "critical");
+    let rule = &rules.rules[1];
+    assert_eq!(rule.threshold, 5.0 * 1024.0 * 1024.0 * 1024.0);
+    assert_eq!(rule.for_duration, Duration::ZERO);
+    assert_eq!(rule.severity, "warning");
+    assert!(rules.validate().is_ok());
+    let duplicate = format!("{RULES}\n[[rules]]\nname = \"memory_high\"\nmetric = \"cpu.usage\"\nop = \">\"\nthreshold = 90\n");
+    let rules = toml::from_str::<AlertRules>(&duplicate).unwrap();
+    assert!(rules.validate().is_err());
+    let unknown = "[[rules]]\nname = \"a\"\nmetric = \"unknown\"\nop = \">\"\nthreshold = 1\n";
+    assert!(toml::from_str::<AlertRules>(unknown).is_err());
+  }
+
+  #[test]
+  fn test_for_and_hysteresis() {
+    let rules = toml::from_str::<AlertRules>(RULES).unwrap();
+    let mut engine = AlertEngine::new(rules);
+    let gib = 1024 * 1024 * 1024;
+    // Pending for less than 2 minutes
+    assert!(engine.evaluate(&event(0, 95, 50 * gib)).is_empty());
+    assert!(engine.evaluate(&event(60_000, 95, 50 * gib)).is_empty());
+    let alerts = engine.evaluate(&event(120_000, 95, 50 * gib));
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(alerts[0].rule, "memory_high");
+    assert_eq!(alerts[0].state, AlertState::Firing);
+    assert_eq!(alerts[0].started_at, 120_000);
+    // Below the threshold but within the hysteresis
+    assert!(engine.evaluate(&event(130_000, 88, 50 * gib)).is_empty());
+    let alerts = engine.evaluate(&event(140_000, 84, 50 * gib));
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(alerts[0].state, AlertState::Resolved);
+    assert_eq!(alerts[0].started_at, 120_000);
+    // The condition must hold again for 2 minutes
+    assert!(engine.evaluate(&event(150_000, 95, 50 * gib)).is_empty());
+    assert!(engine.evaluate(&event(160_000, 50, 50 * gib)).is_empty());
+    assert!(engine.evaluate(&event(280_000, 95, 50 * gib)).is_empty());
+    // Rules without duration fire right away, on their target only
+    let alerts = engine.evaluate(&event(290_000, 50, gib));
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(alerts[0].rule, "root_disk_low");
+    assert_eq!(alerts[0].instance, "/");
+    let alerts = engine.alerts();
+    assert_eq!(alerts.len(), 2);
+    assert_eq!(alerts[0].state, AlertState::Resolved);
+    assert_eq!(alerts[1].state, AlertState::Firing);
+  }
+
+  #[test]
+  fn test_set_rules() {
+    let rules = toml::from_str::<AlertRules>(RULES).unwrap();
+    let mut engine = AlertEngine::new(rules.clone());
+    let gib = 1024 * 1024 * 1024;
+    assert_eq!(engine.evaluate(&event(0, 95, gib)).len(), 1);
+    // The pending memory rule keeps its state across the reload
+    let mut reloaded = rules.clone();
+    reloaded.rules.retain(|rule| rule.name == "memory_high");
+    engine.set_rules(reloaded);
+    assert!(engine.alerts().is_empty());
+    // The alert of the removed disk rule resolves with the next event
+    let alerts = engine.evaluate(&event(120_000, 95, gib));
+    assert_eq!(alerts.len(), 2);
+    assert_eq!(alerts[0].rule, "root_disk_low");
+    assert_eq!(alerts[0].state, AlertState::Resolved);
+    assert_eq!(alerts[0].timestamp, 120_000);
+    assert_eq!(alerts[1].rule, "memory_high");
+    assert_eq!(alerts[1].state, AlertState::Firing);
+    assert_eq!(alerts[1].started_at, 120_000);
+    // Changing the rule resets its state, the condition holds anew
+    let mut changed = rules.clone();
+    changed.rules.retain(|rule| rule.name == "memory_high");
+    changed.rules[0].threshold = 80.0;
+    engine.set_rules(changed);
+    assert!(engine
+      .alerts()
+      .iter()
+      .all(|alert| alert.state == AlertState::Resolved));
+    let alerts = engine.evaluate(&event(180_000, 95, gib));
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(alerts[0].rule, "memory_high");
+    assert_eq!(alerts[0].state, AlertState::Resolved);
+    let alerts = engine.evaluate(&event(300_000, 95, gib));
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(alerts[0].started_at, 300_000);
+  }
+
+  #[test]
+  fn test_missing_instance() {
+    let rules = toml::from_str::<AlertRules>(RULES).unwrap();
+    let mut engine = AlertEngine::new(rules);
+    let gib = 1024 * 1024 * 1024;
+    let alerts = engine.evaluate(&event(0, 50, gib));
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(alerts[0].state, AlertState::Firing);
+    // The root disk is no longer in the event
+    let mut ev = event(10_000, 50, gib);
+    ev.disks.clear();
+    let alerts = engine.evaluate(&ev);
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(alerts[0].rule, "root_disk_low");
+    assert_eq!(alerts[0].state, AlertState::Resolved);
+    assert_eq!(alerts[0].timestamp, 10_000);
+    assert!(engine.evaluate(&ev).is_empty());
+    // It fires again once back
+    let alerts = engine.evaluate(&event(20_000, 50, gib));
+    assert_eq!(alerts[0].state, AlertState::Firing);
+    assert_eq!(alerts[0].started_at, 20_000);
+  }
+}
@@ -0,0 +1,453 @@
+use std::{
+  any::{Any, TypeId},
+  cell::Cell,
+  io,
+  path::Path,
+  sync::Arc,
+};
+
+use ntex::{
+  http::{
+    StatusCode,
+    header::{self, HeaderValue},
+  },
+  io::{FilterBuf, FilterLayer, Io, Layer},
+  server::Stream,
+  service::{Middleware, Service, ServiceCtx, ServiceFactory, cfg::SharedCfg},
+  web::{WebRequest, WebResponse, DefaultError},
+};
+
+use crate::cli::Cli;
+use crate::error::{HttpError, MetrsError};
+
+/// Who can use the api of the daemon
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AuthConfig {
+  /// Bearer tokens accepted on the tcp and tls hosts, no token is required
+  /// when empty
+  pub tokens: Vec<String>,
+  /// User ids of the peers allowed on the unix hosts
+  pub allowed_uids: Vec<u32>,
+  /// Group ids of the peers allowed on the unix hosts
+  pub allowed_gids: Vec<u32>,
+}
+
+/// Read the tokens of a file, one per line, blank lines and lines starting
+/// with `#` are ignored
+fn load_tokens(path: &Path) -> Result<Vec<String>, MetrsError> {
+  let content = std::fs::read_to_string(path).map_err(|err| {
+    MetrsError::Error(format!(
+      "Unable to read tokens {}: {err}",
+      path.display()
+    ))
+  })?;
+  let tokens = content
+    .lines()
+    .map(str::trim)
+    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+    .map(ToOwned::to_owned)
+    .collect::<Vec<_>>();
+  if tokens.is_empty() {
+    return Err(MetrsError::Error(format!(
+      "No token found in {}",
+      path.display()
+    )));
+  }
+  Ok(tokens)
+}
+
+/// Compare two tokens in a time independent of where they differ
+fn token_eq(a: &str, b: &str) -> bool {
+  a.len() == b.len()
+    && a
+      .bytes()
+      .zip(b.bytes())
+      .fold(0, |diff, (a, b)| diff | (a ^ b))
+      == 0
+}
+
+impl AuthConfig {
+  /// Build the authentication of the daemon, reading the tokens file
+  pub fn load(cli: &Cli) -> Result<Self, MetrsError> {
+    let tokens = match &cli.auth_tokens {
+      None => Vec::new(),
+      Some(path) => load_tokens(path)?,
+    };
+    Ok(Self {
+      tokens,
+      allowed_uids: cli.allowed_uids.clone(),
+      allowed_gids: cli.allowed_gids.clone(),
+    })
+  }
+
+  /// Whether the peer credentials of the unix hosts are checked
+  pub fn checks_peers(&self) -> bool {
+    !self.allowed_uids.is_empty() || !self.allowed_gids.is_empty()
+  }
+
+  /// Whether a unix peer is allowed to use the api
+  pub fn allows_peer(&self, peer: &PeerCred) -> bool {
+    self.allowed_uids.contains(&peer.uid)
+      || self.allowed_gids.contains(&peer.gid)
+  }
+
+  /// Check the credentials of the peer of a request on a unix host
+  fn authorize_peer(
+    &self,
+    req: &WebRequest<DefaultError>,
+  ) -> Result<(), HttpError> {
+    let peer = req
+      .io()
+      .and_then(|io| io.query::<PeerCred>().get())
+      .ok_or_else(|| HttpError {
+        status: StatusCode::FORBIDDEN,
+        msg: "Missing peer credentials".into(),
+      })?;
+    if !self.allows_peer(&peer) {
+      return Err(HttpError {
+        status: StatusCode::FORBIDDEN,
+        msg: format!("Peer uid {} gid {} is not allowed", peer.uid, peer.gid),
+      });
+    }
+    Ok(())
+  }
+
+  /// Check the bearer token of a request
+  fn authenticate(
+    &self,
+    req: &WebRequest<DefaultError>,
+  ) -> Result<(), HttpError> {
+    let token = req
+      .headers()
+      .get(header::AUTHORIZATION)
+      .and_then(|value| value.to_str().ok())
+      .and_then(|value| value.strip_prefix("Bearer "))
+      .ok_or_else(|| HttpError {
+        status: StatusCode::UNAUTHORIZED,
+        msg: "Missing bearer token".into(),
+      })?;
+    if !self
+      .tokens
+      .iter()
+      .any(|valid| token_eq(valid, token.trim()))
+    {
+      return Err(HttpError {
+        status: StatusCode::UNAUTHORIZED,
+        msg: "Invalid bearer token".into(),
+      });
+    }
+    Ok(())
+  }
+}
+
+/// Require a valid bearer token on the requests of the tcp and tls hosts and
+/// allowed peer credentials on the ones of the unix hosts. Without allowed
+/// uids or gids the unix hosts are only guarded by the permissions of the
+/// socket, even when tokens are set.
+#[derive(Debug, Clone)]
+pub struct BearerAuth {
+  config: Arc<AuthConfig>,
+}
+
+impl BearerAuth {
+  pub fn new(config: Arc<AuthConfig>) -> Self {
+    Self { config }
+  }
+}
+
+impl<S> Middleware<S, SharedCfg> for BearerAuth {
+  type Service = BearerAuthMiddleware<S>;
+
+  fn create(&self, service: S, _: SharedCfg) -> Self::Service {
+    BearerAuthMiddleware {
+      service,
+      config: self.config.clone(),
+    }
+  }
+}
+
+pub struct BearerAuthMiddleware<S> {
+  service: S,
+  config: Arc<AuthConfig>,
+}
+
+impl<S> Service<WebRequest<DefaultError>> for BearerAuthMiddleware<S>
+where
+  S: Service<WebRequest<DefaultError>, Response = WebResponse>,
+{
+  type Response = WebResponse;
+  type Error = S::Error;
+
+  ntex::forward_poll!(service);
+  ntex::forward_ready!(service);
+  ntex::forward_shutdown!(service);
+
+  async fn call(
+    &self,
+    req: WebRequest<DefaultError>,
+    ctx: ServiceCtx<'_, Self>,
+  ) -> Result<Self::Response, Self::Error> {
+    // Only the connections over tcp have a peer address
+    if req.peer_addr().is_none() {
+      if !self.config.checks_peers() {
+        return ctx.call(&self.service, req).await;
+      }
+      return match self.config.authorize_peer(&req) {
+        Ok(()) => ctx.call(&self.service, req).await,
+        Err(err) => Ok(req.render_error(&err)),
+      };
+    }
+    if self.config.tokens.is_empty() {
+      return ctx.call(&self.service, req).await;
+    }
+    match self.config.authenticate(&req) {
+      Ok(()) => ctx.call(&self.service, req).await,
+      Err(err) => {
+        let mut res = req.render_error(&err);
+        res
+          .headers_mut()
+          .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        Ok(res)
+      }
+    }
+  }
+}
+
+/// Credentials of the peer of a unix host
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeerCred {
+  pub uid: u32,
+  pub gid: u32,
+}
+
+thread_local! {
+  /// Credentials of the connection accepted last by the worker, read by
+  /// [`accept_peer`] and taken by [`PeerCredentials`]
+  static ACCEPTED: Cell<Option<PeerCred>> = const { Cell::new(None) };
+}
+
+/// Read the credentials of the peers of the unix hosts as they are accepted
+/// by the server, they are handed to the connection by [`PeerCredentials`]
+pub fn accept_peer(host: &str, stream: Stream) -> Result<Stream, String> {
+  let stream = match stream {
+    Stream::Uds(stream) => stream,
+    stream => return Ok(stream),
+  };
+  let stream = stream
+    .set_nonblocking(true)
+    .and_then(|_| tokio::net::UnixStream::from_std(stream))
+    .map_err(|err| format!("Unable to accept connection on {host}: {err}"))?;
+  let cred = stream.peer_cred().map_err(|err| {
+    format!("Unable to read peer credentials on {host}: {err}")
+  })?;
+  ACCEPTED.set(Some(PeerCred {
+    uid: cred.uid(),
+    gid: cred.gid(),
+  }));
+  stream
+    .into_std()
+    .map(Stream::Uds)
+    .map_err(|err| format!("Unable to accept connection on {host}: {err}"))
+}
+
+/// Io layer answering the queries of the peer credentials of a connection
+#[derive(Debug)]
+pub struct PeerCredLayer(Option<PeerCred>);
+
+impl FilterLayer for PeerCredLayer {
+  fn query(&self, id: TypeId) -> Option<Box<dyn Any>> {
+    match self.0 {
+      Some(peer) if id == TypeId::of::<PeerCred>() => Some(Box::new(peer)),
+      _ => None,
+    }
+  }
+
+  fn process_read_buf(&self, buf: &FilterBuf<'_>) -> io::Result<()> {
+    buf.with_read_buffers(|src, dst| {
+      if let Some(src) = src.take() {
+        dst.extend_from_slice(&src);
+      }
+      Ok(())
+    })
+  }
+
+  fn process_write_buf(&self, buf: &FilterBuf<'_>) -> io::Result<()> {
+    buf.with_write_buffers(|src, dst| {
+      src.move_to(dst);
+      Ok(())
+    })
+  }
+}
+
+/// Attach the credentials read by [`accept_peer`] to the connections of the
+/// unix hosts so [`BearerAuth`] can check them
+#[derive(Debug, Clone)]
+pub struct PeerCredentials<T> {
+  factory: T,
+}
+
+impl<T> PeerCredentials<T> {
+  pub fn new(factory: T) -> Self {
+    Self { factory }
+  }
+}
+
+impl<T, C> ServiceFactory<Io, C> for PeerCredentials<T>
+where
+  T: ServiceFactory<Io<Layer<PeerCredLayer>>, C>,
+{
+  type Response = T::Response;
+  type Error = T::Error;
+  type InitError = T::InitError;
+  type Service = PeerCredentialsService<T::Service>;
+
+  async fn create(&self, cfg: C) -> Result<Self::Service, Self::InitError> {
+    let service = self.factory.create(cfg).await?;
+    Ok(PeerCredentialsService { service })
+  }
+}
+
+pub struct PeerCredentialsService<S> {
+  service: S,
+}
+
+impl<S> Service<Io> for PeerCredentialsService<S>
+where
+  S: Service<Io<Layer<PeerCredLayer>>>,
+{
+  type Response = S::Response;
+  type Error = S::Error;
+
+  ntex::forward_poll!(service);
+  ntex::forward_shutdown!(service);
+
+  // The readiness isn't forwarded so the worker calls the service right
+  // after accepting the connection, before it accepts another one and
+  // replaces the credentials
+  async fn call(
+    &self,
+    io: Io,
+    ctx: ServiceCtx<'_, Self>,
+  ) -> Result<Self::Response, Self::Error> {
+    let io = io.add_filter(PeerCredLayer(ACCEPTED.take()));
+    ctx.call(&self.service, io).await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{
+    io::{Read, Write},
+    os::unix::{fs::MetadataExt, net::UnixStream},
+  };
+
+  use clap::Parser;
+  use ntex::web;
+
+  use crate::event_emitter::EventEmitter;
+  use crate::reload::Reloader;
+
+  use super::*;
+
+  #[ntex::test]
+  async fn test_bearer_auth() {
+    let config = Arc::new(AuthConfig {
+      tokens: vec!["secret".into()],
+      ..Default::default()
+    });
+    let srv = web::test::server(async move || {
+      web::App::new()
+        .middleware(BearerAuth::new(config.clone()))
+        .route(
+          "/alerts",
+          web::get().to(|| async { web::HttpResponse::Ok().json(&[0; 0]) }),
+        )
+    })
+    .await;
+    let resp = srv.get("/alerts").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+      resp.headers().get(header::WWW_AUTHENTICATE).unwrap(),
+      "Bearer"
+    );
+    let body = resp.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(body["msg"], "Missing bearer token");
+    let resp = srv
+      .get("/alerts")
+      .bearer_auth("wrong")
+      .send()
+      .await
+      .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let resp = srv
+      .get("/alerts")
+      .bearer_auth("secret")
+      .send()
+      .await
+      .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+  }
+
+  #[test]
+  fn test_load_tokens() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tokens");
+    std::fs::write(&path, "# ci\nsecret-1\n\n  secret-2  \n").unwrap();
+    assert_eq!(load_tokens(&path).unwrap(), vec!["secret-1", "secret-2"]);
+    std::fs::write(&path, "# nothing\n").unwrap();
+    assert!(load_tokens(&path).is_err());
+    assert!(token_eq("secret", "secret"));
+    assert!(!token_eq("secret", "secreT"));
+    assert!(!token_eq("secret", "secret-1"));
+  }
+
+  /// Send a request over a unix socket and return the raw response
+  async fn request(path: std::path::PathBuf) -> std::io::Result<String> {
+    web::block(move || {
+      let mut stream = UnixStream::connect(path)?;
+      stream.write_all(
+        b"GET /alerts HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+      )?;
+      let mut resp = String::new();
+      stream.read_to_string(&mut resp)?;
+      Ok::<_, std::io::Error>(resp)
+    })
+    .await
+    .map_err(|err| std::io::Error::other(err.to_string()))
+  }
+
+  #[ntex::test]
+  async fn test_peer_credentials() {
+    let dir = tempfile::tempdir().unwrap();
+    let uid = std::fs::metadata(dir.path()).unwrap().uid();
+    let serve = |name: &str, allowed_uid: u32| {
+      let path = dir.path().join(name);
+      let event_emitter = EventEmitter::default();
+      let cli = Cli::parse_from(["metrsd"]);
+      let reloader = Reloader::new(event_emitter.clone(), ["metrsd"], cli);
+      let config = AuthConfig {
+        allowed_uids: vec![allowed_uid],
+        ..Default::default()
+      };
+      let host = format!("unix://{}", path.display());
+      let srv = crate::server::gen_srv(
+        &[host],
+        &Default::default(),
+        config,
+        event_emitter,
+        reloader,
+      )
+      .unwrap();
+      (srv, path)
+    };
+    let (srv, allowed) = serve("allowed.sock", uid);
+    let resp = request(allowed).await.unwrap();
+    assert!(resp.starts_with("HTTP/1.1 200"), "{resp}");
+    srv.stop(true).await;
+    let (srv, denied) = serve("denied.sock", uid + 1);
+    let resp = request(denied).await.unwrap();
+    assert!(resp.starts_with("HTTP/1.1 403"), "{resp}");
+    assert!(resp.contains(&format!("Peer uid {uid} gid")), "{resp}");
+    srv.stop(true).await;
+  }
+}
@@ -0,0 +1,202 @@
+use std::{
+  fs,
+  collections::HashMap,
+  path::{Path, PathBuf},
+};
+
+use metrs_stubs::CgroupInfo;
+
+/// Collect the resources used by every cgroup of a cgroup v2 hierarchy
+pub struct CgroupCollector {
+  root: PathBuf,
+  /// Cpu time of every cgroup at the last collection used to compute usage
+  cpu_usage_usec: HashMap<String, u64>,
+}
+
+/// Read a cgroup interface file without its trailing new line
+fn read_file(path: &Path) -> Option<String> {
+  fs::read_to_string(path)
+    .ok()
+    .map(|value| value.trim().to_owned())
+}
+
+/// Parse a single value file where `max` means unlimited
+fn parse_limit(value: &str) -> Option<u64> {
+  match value {
+    "max" => None,
+    value => value.parse().ok(),
+  }
+}
+
+/// Get the value of a key in a flat keyed file such as `cpu.stat`
+fn parse_flat_keyed(content: &str, key: &str) -> Option<u64> {
+  content.lines().find_map(|line| {
+    let (name, value) = line.split_once(' ')?;
+    if name != key {
+      return None;
+    }
+    value.trim().parse().ok()
+  })
+}
+
+/// Sum the read and written bytes of all devices in an `io.stat` file
+fn parse_io_stat(content: &str) -> (u64, u64) {
+  content
+    .lines()
+    .flat_map(|line| line.split_whitespace().skip(1))
+    .filter_map(|field| field.split_once('='))
+    .fold((0, 0), |(read, written), (key, value)| {
+      let value = value.parse::<u64>().unwrap_or_default();
+      match key {
+        "rbytes" => (read + value, written),
+        "wbytes" => (read, written + value),
+        _ => (read, written),
+      }
+    })
+}
+
+impl CgroupCollector {
+  pub fn new(root: impl Into<PathBuf>) -> Self {
+    Self {
+      root: root.into(),
+      cpu_usage_usec: HashMap::new(),
+    }
+  }
+
+  /// List the directories of the hierarchy, the root included
+  fn walk(&self) -> Vec<PathBuf> {
+    if !self.root.is_dir() {
+      return Vec::new();
+    }
+    let mut dirs = vec![self.root.clone()];
+    let mut index = 0;
+    while let Some(dir) = dirs.get(index) {
+      if let Ok(entries) = fs::read_dir(dir) {
+        let mut children = entries
+          .filter_map(Result::ok)
+          .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+          .map(|entry| entry.path())
+          .collect::<Vec<_>>();
+        children.sort();
+        dirs.extend(children);
+      }
+      index += 1;
+    }
+    dirs
+  }
+
+  /// Read the resources of a single cgroup
+  fn read_cgroup(&self, dir: &Path, elapsed: f64) -> CgroupInfo {
+    let path = dir
+      .strip_prefix(&self.root)
+      .map(|path| format!("/{}", path.display()))
+      .unwrap_or_default();
+    let cpu_usage_usec = read_file(&dir.join("cpu.stat"))
+      .and_then(|content| parse_flat_keyed(&content, "usage_usec"))
+      .unwrap_or_default();
+    let cpu_usage = match self.cpu_usage_usec.get(&path) {
+      Some(previous) if elapsed > 0.0 => {
+        cpu_usage_usec.saturating_sub(*previous) as f64 / (elapsed * 10_000.0)
+      }
+      _ => 0.0,
+    };
+    let (io_read_bytes, io_written_bytes) = read_file(&dir.join("io.stat"))
+      .map(|content| parse_io_stat(&content))
+      .unwrap_or_default();
+    CgroupInfo {
+      path,
+      cpu_usage_usec,
+      cpu_usage,
+      memory_current: read_file(&dir.join("memory.current"))
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_default(),
+      memory_max: read_file(&dir.join("memory.max"))
+        .and_then(|value| parse_limit(&value)),
+      io_read_bytes,
+      io_written_bytes,
+      pids_current: read_file(&dir.join("pids.current"))
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_default(),
+      pids_max: read_file(&dir.join("pids.max"))
+        .and_then(|value| parse_limit(&value)),
+    }
+  }
+
+  /// Collect every cgroup, `elapsed` is the time in seconds since the last
+  /// collection and is used to compute the cpu usage
+  pub fn collect(&mut self, elapsed: f64) -> Vec<CgroupInfo> {
+    let cgroups = self
+      .walk()
+      .iter()
+      .map(|dir| self.read_cgroup(dir, elapsed))
+      .collect::<Vec<_>>();
+    self.cpu_usage_usec = cgroups
+      .iter()
+      .map(|cgroup| (cgroup.path.clone(), cgroup.cpu_usage_usec))
+      .collect();
+    cgroups
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn write_file(dir: &Path, name: &str, value: &str) {
+    fs::create_dir_all(dir).unwrap();
+    fs::write(dir.join(name), value).unwrap();
+  }
+
+  #[test]
+  fn test_parse() {
+    assert_eq!(parse_limit("max"), None);
+    assert_eq!(parse_limit("1024"), Some(1024));
+    let cpu_stat = "usage_usec 1500\nuser_usec 1000\nsystem_usec 500\n";
+    assert_eq!(parse_flat_keyed(cpu_stat, "usage_usec"), Some(1500));
+    assert_eq!(parse_flat_keyed(cpu_stat, "nr_periods"), None);
+    let io_stat = "8:0 rbytes=100 wbytes=200 rios=1 wios=2\n\
+                   8:16 rbytes=10 wbytes=20 rios=1 wios=2\n";
+    assert_eq!(parse_io_stat(io_stat), (110, 220));
+  }
+
+  #[test]
+  fn test_collect() {
+    let root = tempfile::tempdir().unwrap();
+    write_file(root.path(), "cpu.stat", "usage_usec 5000000\n");
+    write_file(root.path(), "memory.current", "4096\n");
+    let container = root.path().join("system.slice/nanocl.service");
+    write_file(&container, "cpu.stat", "usage_usec 1000000\n");
+    write_file(&container, "memory.current", "1024\n");
+    write_file(&container, "memory.max", "2048\n");
+    write_file(&container, "io.stat", "8:0 rbytes=100 wbytes=200\n");
+    write_file(&container, "pids.current", "3\n");
+    write_file(&container, "pids.max", "max\n");
+    let mut collector = CgroupCollector::new(root.path());
+    let cgroups = collector.collect(1.0);
+    assert_eq!(cgroups.len(), 3);
+    assert_eq!(cgroups[0].path, "/");
+    assert_eq!(cgroups[0].memory_current, 4096);
+    assert_eq!(cgroups[0].memory_max, None);
+    assert_eq!(cgroups[1].path, "/system.slice");
+    let cgroup = &cgroups[2];
+    assert_eq!(cgroup.path, "/system.slice/nanocl.service");
+    assert_eq!(cgroup.cpu_usage_usec, 1_000_000);
+    assert_eq!(cgroup.cpu_usage, 0.0);
+    assert_eq!(cgroup.memory_current, 1024);
+    assert_eq!(cgroup.memory_max, Some(2048));
+    assert_eq!(cgroup.io_read_bytes, 100);
+    assert_eq!(cgroup.io_written_bytes, 200);
+    assert_eq!(cgroup.pids_current, 3);
+    assert_eq!(cgroup.pids_max, None);
+    // One second of cpu time over two seconds
+    write_file(&container, "cpu.stat", "usage_usec 2000000\n");
+    let cgroups = collector.collect(2.0);
+    assert_eq!(cgroups[2].cpu_usage, 50.0);
+  }
+
+  #[test]
+  fn test_collect_missing_root() {
+    let mut collector = CgroupCollector::new("/non/existent/cgroup");
+    assert!(collector.collect(1.0).is_empty());
+  }
+}
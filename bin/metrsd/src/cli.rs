@@ -1,19 +1,193 @@
-use clap::Parser;
+use std::{path::PathBuf, time::Duration};
 
-#[derive(Debug, Parser)]
+use clap::{Parser, Subcommand, ValueEnum};
+
+use metrs_stubs::{EventSection, parse_duration, parse_size};
+
+use crate::alert::AlertRules;
+use crate::rollup::RollupTier;
+use crate::history::HistorySize;
+
+/// Resource used to select the top processes
+#[derive(Debug, Default, Clone, Copy, PartialEq, ValueEnum)]
+pub enum ProcessSort {
+  #[default]
+  Cpu,
+  Memory,
+}
+
+/// Parse a section of the events to collect
+pub fn parse_collector(s: &str) -> Result<EventSection, String> {
+  match s.parse()? {
+    EventSection::Alerts => Err("Alerts are not collected".into()),
+    section => Ok(section),
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum Command {
+  /// Manage the configuration of the daemon
+  #[clap(subcommand)]
+  Config(ConfigCommand),
+}
+
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum ConfigCommand {
+  /// Validate the configuration and print the effective one
+  Check,
+}
+
+#[derive(Debug, Clone, Parser)]
 pub struct Cli {
-  /// Hosts to listen on
+  #[clap(subcommand)]
+  pub command: Option<Command>,
+  /// Path of a toml configuration file, its values apply when not set on
+  /// the command line or in the environment
+  #[clap(long, global = true, env = "METRSD_CONFIG")]
+  pub config: Option<PathBuf>,
+  /// Hosts to listen on (e.g. unix:///run/metrsd.sock, tcp://0.0.0.0:8080,
+  /// tls://0.0.0.0:8443)
   #[clap(
     short = 'H',
     long,
     value_parser,
     value_delimiter = ' ',
-    required = true
+    env = "METRSD_HOSTS"
   )]
   pub hosts: Vec<String>,
+  /// Path of the pem certificate chain served on the tls:// hosts
+  #[clap(long, env = "METRSD_TLS_CERT")]
+  pub tls_cert: Option<PathBuf>,
+  /// Path of the pem private key of the tls certificate
+  #[clap(long, env = "METRSD_TLS_KEY")]
+  pub tls_key: Option<PathBuf>,
+  /// Path of a pem certificate authority, clients of the tls:// hosts must
+  /// present a certificate it signed when set
+  #[clap(long, env = "METRSD_TLS_CLIENT_CA")]
+  pub tls_client_ca: Option<PathBuf>,
+  /// Path of a file with the bearer tokens accepted on the tcp:// and
+  /// tls:// hosts, one per line, no token is required when not set
+  #[clap(long, env = "METRSD_AUTH_TOKENS")]
+  pub auth_tokens: Option<PathBuf>,
+  /// User ids allowed to connect to the unix:// hosts, checked with the
+  /// credentials of the peer
+  #[clap(
+    long = "allowed-uid",
+    value_delimiter = ',',
+    env = "METRSD_ALLOWED_UIDS"
+  )]
+  pub allowed_uids: Vec<u32>,
+  /// Group ids allowed to connect to the unix:// hosts, checked with the
+  /// credentials of the peer
+  #[clap(
+    long = "allowed-gid",
+    value_delimiter = ',',
+    env = "METRSD_ALLOWED_GIDS"
+  )]
+  pub allowed_gids: Vec<u32>,
   /// Interval between two metrics publications
-  #[clap(short, long, default_value = "10")]
+  #[clap(short, long, default_value = "10", env = "METRSD_TICK_INTERVAL")]
   pub tick_interval: u64,
+  /// Smallest interval subscribers can request between two publications
+  #[clap(
+    long,
+    default_value = "1",
+    value_parser = clap::value_parser!(u64).range(1..),
+    env = "METRSD_MIN_TICK_INTERVAL"
+  )]
+  pub min_tick_interval: u64,
+  /// Path of a recording made with `metrs record`, served in a loop instead
+  /// of the metrics of the host
+  #[clap(long, env = "METRSD_REPLAY")]
+  pub replay: Option<PathBuf>,
+  /// Sections of the events to collect (e.g. memory,cpus), all when not set
+  #[clap(
+    long,
+    value_delimiter = ',',
+    value_parser = parse_collector,
+    env = "METRSD_COLLECTORS"
+  )]
+  pub collectors: Vec<EventSection>,
+  /// Mount points of the disks to collect, all when not set
+  #[clap(long, value_delimiter = ',', env = "METRSD_DISKS")]
+  pub disks: Vec<String>,
+  /// Names of the network interfaces to collect, all when not set
+  #[clap(long, value_delimiter = ',', env = "METRSD_INTERFACES")]
+  pub interfaces: Vec<String>,
+  /// Number of processes to include in each event, 0 to disable
+  #[clap(long, default_value = "0", env = "METRSD_TOP_PROCESSES")]
+  pub top_processes: usize,
+  /// Resource used to select the top processes
+  #[clap(
+    long,
+    value_enum,
+    default_value = "cpu",
+    env = "METRSD_TOP_PROCESSES_BY"
+  )]
+  pub top_processes_by: ProcessSort,
+  /// Path where the sysfs of the host is mounted
+  #[clap(long, default_value = "/sys", env = "METRSD_SYSFS_ROOT")]
+  pub sysfs_root: PathBuf,
+  /// Path of a cgroup v2 hierarchy to collect, disabled when not set
+  #[clap(long, env = "METRSD_CGROUP_ROOT")]
+  pub cgroup_root: Option<PathBuf>,
+  /// Events to keep in memory, as a count (e.g. 360) or a duration
+  /// (e.g. 90s, 30m, 1h, 2d)
+  #[clap(long, default_value = "1h", env = "METRSD_HISTORY")]
+  pub history: HistorySize,
+  /// Directory where the events are persisted, disabled when not set
+  #[clap(long, env = "METRSD_DATA_DIR")]
+  pub data_dir: Option<PathBuf>,
+  /// Total size of the persisted events, split evenly between the raw
+  /// events and the rollup tiers (e.g. 512M, 1G)
+  #[clap(
+    long,
+    default_value = "1G",
+    value_parser = parse_size,
+    env = "METRSD_RETENTION_SIZE"
+  )]
+  pub retention_size: u64,
+  /// Age of the oldest persisted events (e.g. 12h, 7d)
+  #[clap(
+    long,
+    default_value = "7d",
+    value_parser = parse_duration,
+    env = "METRSD_RETENTION_AGE"
+  )]
+  pub retention_age: Duration,
+  /// Rollup tier as its resolution and retention (e.g. 1m:1d), persisted
+  /// under the data directory when set
+  #[clap(long = "rollup", value_delimiter = ',', env = "METRSD_ROLLUPS")]
+  pub rollups: Vec<RollupTier>,
+  /// Path of a toml file with the alert rules to evaluate
+  #[clap(long, env = "METRSD_ALERT_RULES")]
+  pub alert_rules: Option<PathBuf>,
+  /// Rules set in the alerts section of the configuration file
+  #[clap(skip)]
+  pub alert_rule_list: AlertRules,
+  /// Url the alerts are posted to when they fire or resolve
+  #[clap(
+    long = "alert-webhook",
+    value_delimiter = ',',
+    env = "METRSD_ALERT_WEBHOOKS"
+  )]
+  pub alert_webhooks: Vec<String>,
+  /// Number of times a failed alert webhook is retried
+  #[clap(long, default_value = "3", env = "METRSD_ALERT_WEBHOOK_RETRIES")]
+  pub alert_webhook_retries: u32,
+  /// Delay before the first retry of an alert webhook, doubled on every
+  /// following one up to a minute (e.g. 1s, 1m)
+  #[clap(
+    long,
+    default_value = "1s",
+    value_parser = parse_duration,
+    env = "METRSD_ALERT_WEBHOOK_BACKOFF"
+  )]
+  pub alert_webhook_backoff: Duration,
+  /// Shell command run with the details of an alert in its environment when
+  /// it fires or resolves
+  #[clap(long, env = "METRSD_ALERT_EXEC")]
+  pub alert_exec: Option<String>,
 }
 
 /// Cli arguments unit test
@@ -47,5 +221,80 @@ mod tests {
     assert_eq!(args.hosts.len(), 2);
     assert_eq!(args.hosts[0], "unix:///run/toto.sock");
     assert_eq!(args.hosts[1], "tcp://0.0.0.0:1245");
+    assert_eq!(args.top_processes, 0);
+    assert_eq!(args.min_tick_interval, 1);
+    assert_eq!(args.replay, None);
+    assert_eq!(args.top_processes_by, ProcessSort::Cpu);
+    assert_eq!(args.sysfs_root, PathBuf::from("/sys"));
+    assert_eq!(args.cgroup_root, None);
+    assert_eq!(args.history, HistorySize::default());
+    assert_eq!(args.data_dir, None);
+    assert_eq!(args.retention_size, 1024 * 1024 * 1024);
+    assert_eq!(args.retention_age, Duration::from_secs(7 * 86400));
+    assert!(args.rollups.is_empty());
+    assert_eq!(args.alert_rules, None);
+    assert!(args.alert_webhooks.is_empty());
+    assert_eq!(args.alert_webhook_retries, 3);
+    assert_eq!(args.alert_webhook_backoff, Duration::from_secs(1));
+    assert_eq!(args.alert_exec, None);
+    assert_eq!(args.tls_cert, None);
+    assert_eq!(args.tls_client_ca, None);
+    assert_eq!(args.auth_tokens, None);
+    assert!(args.allowed_uids.is_empty());
+
+    let args = Cli::parse_from([
+      "metrsd",
+      "-H",
+      "unix:///run/toto.sock",
+      "--top-processes",
+      "5",
+      "--top-processes-by",
+      "memory",
+      "--cgroup-root",
+      "/sys/fs/cgroup",
+      "--history",
+      "360",
+      "--data-dir",
+      "/var/lib/metrsd",
+      "--retention-size",
+      "512M",
+      "--retention-age",
+      "12h",
+      "--rollup",
+      "5m:7d",
+      "--alert-webhook",
+      "http://127.0.0.1:9000/hook",
+      "--alert-webhook-backoff",
+      "5s",
+      "--alert-exec",
+      "logger \"$METRS_ALERT_SUMMARY\"",
+      "--tls-cert",
+      "/etc/metrsd/cert.pem",
+      "--tls-key",
+      "/etc/metrsd/key.pem",
+      "--allowed-uid",
+      "0,1000",
+      "--allowed-gid",
+      "998",
+    ]);
+
+    assert_eq!(args.top_processes, 5);
+    assert_eq!(args.top_processes_by, ProcessSort::Memory);
+    assert_eq!(args.cgroup_root, Some(PathBuf::from("/sys/fs/cgroup")));
+    assert_eq!(args.history, HistorySize::Count(360));
+    assert_eq!(args.data_dir, Some(PathBuf::from("/var/lib/metrsd")));
+    assert_eq!(args.retention_size, 512 * 1024 * 1024);
+    assert_eq!(args.retention_age, Duration::from_secs(12 * 3600));
+    assert_eq!(args.rollups, vec!["5m:7d".parse().unwrap()]);
+    assert_eq!(args.alert_webhooks, vec!["http://127.0.0.1:9000/hook"]);
+    assert_eq!(args.alert_webhook_backoff, Duration::from_secs(5));
+    assert_eq!(
+      args.alert_exec.as_deref(),
+      Some("logger \"$METRS_ALERT_SUMMARY\"")
+    );
+    assert_eq!(args.tls_cert, Some(PathBuf::from("/etc/metrsd/cert.pem")));
+    assert_eq!(args.tls_key, Some(PathBuf::from("/etc/metrsd/key.pem")));
+    assert_eq!(args.allowed_uids, vec![0, 1000]);
+    assert_eq!(args.allowed_gids, vec![998]);
   }
 }
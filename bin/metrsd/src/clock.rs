@@ -0,0 +1,91 @@
+use std::time::Duration;
+#[cfg(test)]
+use std::sync::Arc;
+
+use ntex::time::sleep;
+#[cfg(test)]
+use tokio::sync::watch;
+
+use crate::metrics::now_millis;
+
+/// Clock advanced by hand, the collection waits until it's advanced past
+/// the tick interval so tests don't depend on the wall clock
+#[cfg(test)]
+#[derive(Clone)]
+pub struct ManualClock(Arc<watch::Sender<u64>>);
+
+#[cfg(test)]
+impl ManualClock {
+  /// Create a clock starting at the given unix timestamp in milliseconds
+  pub fn new(now: u64) -> Self {
+    Self(Arc::new(watch::Sender::new(now)))
+  }
+
+  /// Current unix timestamp of the clock in milliseconds
+  pub fn now(&self) -> u64 {
+    *self.0.borrow()
+  }
+
+  /// Move the clock forward, waking the collection once its tick is due
+  pub fn advance(&self, duration: Duration) {
+    self
+      .0
+      .send_modify(|now| *now += duration.as_millis() as u64);
+  }
+}
+
+/// Clock pacing the collection of the metrics
+#[derive(Clone)]
+pub enum TickClock {
+  /// Wall clock of the host
+  System,
+  /// Clock advanced by hand by the tests
+  #[cfg(test)]
+  Manual(ManualClock),
+}
+
+impl TickClock {
+  /// Current unix timestamp in milliseconds
+  pub fn now(&self) -> u64 {
+    match self {
+      Self::System => now_millis(),
+      #[cfg(test)]
+      Self::Manual(clock) => clock.now(),
+    }
+  }
+
+  /// Wait for the given duration to elapse on the clock
+  pub async fn sleep(&self, duration: Duration) {
+    match self {
+      Self::System => sleep(duration).await,
+      #[cfg(test)]
+      Self::Manual(clock) => {
+        let deadline = clock.now() + duration.as_millis() as u64;
+        let mut now = clock.0.subscribe();
+        // The sender is kept by the clock so the channel is never closed
+        let _ = now.wait_for(|now| *now >= deadline).await;
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use futures::FutureExt;
+
+  use super::*;
+
+  #[ntex::test]
+  async fn test_manual_clock() {
+    let manual = ManualClock::new(1_000);
+    let clock = TickClock::Manual(manual.clone());
+    let mut wait = Box::pin(clock.sleep(Duration::from_secs(10)));
+    assert!((&mut wait).now_or_never().is_none());
+    manual.advance(Duration::from_secs(5));
+    assert!((&mut wait).now_or_never().is_none());
+    manual.advance(Duration::from_secs(5));
+    assert!(wait.now_or_never().is_some());
+    assert_eq!(clock.now(), 11_000);
+    assert!(TickClock::System.now() > clock.now());
+  }
+}
@@ -0,0 +1,168 @@
+#[cfg(test)]
+use std::sync::{Arc, Mutex};
+
+use metrs_stubs::{EventFilter, EventSection, MetrsdEvent};
+
+use crate::metrics::MetricsConfig;
+
+/// Source of the metrics published on every tick, the collection loop sets
+/// the timestamp and the sequence of the events and applies the filter
+pub trait Collector {
+  /// Collect the metrics, `elapsed` is the number of seconds since the
+  /// previous collection of each section to compute the rates
+  fn collect(
+    &mut self,
+    config: &MetricsConfig,
+    elapsed: &Elapsed,
+  ) -> MetrsdEvent;
+}
+
+/// Number of seconds since the previous collection of the sections with
+/// rates
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Elapsed {
+  pub disks: f64,
+  pub networks: f64,
+  pub cgroups: f64,
+}
+
+#[cfg(test)]
+impl Elapsed {
+  /// Same number of seconds for every section
+  pub fn all(seconds: f64) -> Self {
+    Self {
+      disks: seconds,
+      networks: seconds,
+      cgroups: seconds,
+    }
+  }
+}
+
+/// Unix timestamps in milliseconds of the last collection of the sections
+/// with rates. A section disabled by the filter isn't refreshed, so once
+/// it's enabled again its rates are computed over the whole period it was
+/// disabled.
+#[derive(Debug, Clone, Copy)]
+pub struct LastRefresh {
+  disks: u64,
+  networks: u64,
+  cgroups: u64,
+}
+
+impl LastRefresh {
+  /// Consider every section collected at the given timestamp
+  pub fn new(now: u64) -> Self {
+    Self {
+      disks: now,
+      networks: now,
+      cgroups: now,
+    }
+  }
+
+  /// Number of seconds between the last collection of each section and now
+  pub fn elapsed(&self, now: u64) -> Elapsed {
+    let seconds = |last: u64| now.saturating_sub(last) as f64 / 1000.0;
+    Elapsed {
+      disks: seconds(self.disks),
+      networks: seconds(self.networks),
+      cgroups: seconds(self.cgroups),
+    }
+  }
+
+  /// Record the collection of the sections enabled by the filter
+  pub fn refreshed(&mut self, now: u64, filter: &EventFilter) {
+    if filter.includes(EventSection::Disks) {
+      self.disks = now;
+    }
+    if filter.includes(EventSection::Networks) {
+      self.networks = now;
+    }
+    if filter.includes(EventSection::Cgroups) {
+      self.cgroups = now;
+    }
+  }
+}
+
+/// Collector yielding predefined events in a loop, used to test the
+/// emitter, the api and the clients without depending on the host
+#[cfg(test)]
+#[derive(Debug, Clone, Default)]
+pub struct ScriptedCollector {
+  events: Vec<MetrsdEvent>,
+  next: usize,
+  elapsed: Arc<Mutex<Vec<Elapsed>>>,
+}
+
+#[cfg(test)]
+impl ScriptedCollector {
+  /// Yield the given events in order, starting over after the last one
+  pub fn new(events: impl IntoIterator<Item = MetrsdEvent>) -> Self {
+    Self {
+      events: events.into_iter().collect(),
+      ..Default::default()
+    }
+  }
+
+  /// Elapsed times given to every collection so far, shared by the clones
+  /// so a test can inspect them while the collector runs
+  pub fn elapsed(&self) -> Vec<Elapsed> {
+    self.elapsed.lock().unwrap().clone()
+  }
+}
+
+#[cfg(test)]
+impl Collector for ScriptedCollector {
+  fn collect(&mut self, _: &MetricsConfig, elapsed: &Elapsed) -> MetrsdEvent {
+    self.elapsed.lock().unwrap().push(*elapsed);
+    let Some(ev) = self.events.get(self.next) else {
+      return MetrsdEvent::default();
+    };
+    self.next = (self.next + 1) % self.events.len();
+    ev.clone()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_scripted_collector() {
+    let config = MetricsConfig::default();
+    let events = ["first", "second"].map(|hostname| MetrsdEvent {
+      hostname: hostname.into(),
+      ..Default::default()
+    });
+    let mut collector = ScriptedCollector::new(events);
+    let hostnames = (0..3)
+      .map(|_| collector.collect(&config, &Elapsed::all(1.0)).hostname)
+      .collect::<Vec<_>>();
+    assert_eq!(hostnames, ["first", "second", "first"]);
+    let mut empty = ScriptedCollector::default();
+    assert!(empty
+      .collect(&config, &Elapsed::all(1.0))
+      .hostname
+      .is_empty());
+    assert_eq!(collector.elapsed().len(), 3);
+  }
+
+  #[test]
+  fn test_last_refresh() {
+    let mut last_refresh = LastRefresh::new(1_000);
+    let disks_disabled = EventFilter {
+      include: vec![EventSection::Networks, EventSection::Cgroups],
+      ..Default::default()
+    };
+    last_refresh.refreshed(2_000, &disks_disabled);
+    assert_eq!(
+      last_refresh.elapsed(3_000),
+      Elapsed {
+        disks: 2.0,
+        networks: 1.0,
+        cgroups: 1.0,
+      }
+    );
+    last_refresh.refreshed(3_000, &EventFilter::default());
+    assert_eq!(last_refresh.elapsed(4_000), Elapsed::all(1.0));
+  }
+}
@@ -0,0 +1,601 @@
+use std::path::{Path, PathBuf};
+
+use clap::{
+  ArgMatches, CommandFactory, FromArgMatches, ValueEnum, parser::ValueSource,
+};
+use serde::{Deserialize, Serialize};
+
+use metrs_stubs::{format_duration, format_size, parse_duration, parse_size};
+
+use crate::cli::{Cli, ProcessSort};
+use crate::tls;
+use crate::replay;
+use crate::auth::AuthConfig;
+use crate::error::MetrsError;
+use crate::alert::{AlertRule, AlertRules};
+
+/// Certificates of the tls hosts
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsSection {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub cert: Option<PathBuf>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub key: Option<PathBuf>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub client_ca: Option<PathBuf>,
+}
+
+/// Who can use the api
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthSection {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub tokens_file: Option<PathBuf>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub allowed_uids: Option<Vec<u32>>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub allowed_gids: Option<Vec<u32>>,
+}
+
+/// Sections of the events collected and how
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CollectorsSection {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub enabled: Option<Vec<String>>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub top_processes: Option<usize>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub top_processes_by: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub sysfs_root: Option<PathBuf>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub cgroup_root: Option<PathBuf>,
+}
+
+/// Disks and network interfaces collected
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FiltersSection {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub disks: Option<Vec<String>>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub interfaces: Option<Vec<String>>,
+}
+
+/// History kept in memory and persisted events
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StorageSection {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub history: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub data_dir: Option<PathBuf>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub retention_size: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub retention_age: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub rollups: Option<Vec<String>>,
+}
+
+/// Alert rules, inline or from a rules file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertsSection {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub rules_file: Option<PathBuf>,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub rules: Vec<AlertRule>,
+}
+
+/// Sinks the alerts are sent to
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExportersSection {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub webhooks: Option<Vec<String>>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub webhook_retries: Option<u32>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub webhook_backoff: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub exec: Option<String>,
+}
+
+/// Content of a configuration file, every value is optional
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub hosts: Option<Vec<String>>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub tick_interval: Option<u64>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub min_tick_interval: Option<u64>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub replay: Option<PathBuf>,
+  #[serde(default)]
+  pub tls: TlsSection,
+  #[serde(default)]
+  pub auth: AuthSection,
+  #[serde(default)]
+  pub collectors: CollectorsSection,
+  #[serde(default)]
+  pub filters: FiltersSection,
+  #[serde(default)]
+  pub storage: StorageSection,
+  #[serde(default)]
+  pub alerts: AlertsSection,
+  #[serde(default)]
+  pub exporters: ExportersSection,
+}
+
+impl ConfigFile {
+  /// Read a toml configuration file
+  pub fn load(path: &Path) -> Result<Self, MetrsError> {
+    let content = std::fs::read_to_string(path).map_err(|err| {
+      MetrsError::Error(format!(
+        "Unable to read config {}: {err}",
+        path.display()
+      ))
+    })?;
+    toml::from_str(&content).map_err(|err| {
+      MetrsError::Error(format!("Invalid config {}: {err}", path.display()))
+    })
+  }
+}
+
+/// Effective configuration of the daemon, as it would be written in a file
+impl From<&Cli> for ConfigFile {
+  fn from(cli: &Cli) -> Self {
+    let list = |items: &[String]| Some(items.to_vec());
+    Self {
+      hosts: list(&cli.hosts),
+      tick_interval: Some(cli.tick_interval),
+      min_tick_interval: Some(cli.min_tick_interval),
+      replay: cli.replay.clone(),
+      tls: TlsSection {
+        cert: cli.tls_cert.clone(),
+        key: cli.tls_key.clone(),
+        client_ca: cli.tls_client_ca.clone(),
+      },
+      auth: AuthSection {
+        tokens_file: cli.auth_tokens.clone(),
+        allowed_uids: Some(cli.allowed_uids.clone()),
+        allowed_gids: Some(cli.allowed_gids.clone()),
+      },
+      collectors: CollectorsSection {
+        enabled: Some(cli.collectors.iter().map(ToString::to_string).collect()),
+        top_processes: Some(cli.top_processes),
+        top_processes_by: cli
+          .top_processes_by
+          .to_possible_value()
+          .map(|value| value.get_name().to_owned()),
+        sysfs_root: Some(cli.sysfs_root.clone()),
+        cgroup_root: cli.cgroup_root.clone(),
+      },
+      filters: FiltersSection {
+        disks: list(&cli.disks),
+        interfaces: list(&cli.interfaces),
+      },
+      storage: StorageSection {
+        history: Some(cli.history.to_string()),
+        data_dir: cli.data_dir.clone(),
+        retention_size: Some(format_size(cli.retention_size)),
+        retention_age: Some(format_duration(cli.retention_age)),
+        rollups: Some(cli.rollups.iter().map(ToString::to_string).collect()),
+      },
+      alerts: AlertsSection {
+        rules_file: cli.alert_rules.clone(),
+        rules: cli.alert_rule_list.rules.clone(),
+      },
+      exporters: ExportersSection {
+        webhooks: list(&cli.alert_webhooks),
+        webhook_retries: Some(cli.alert_webhook_retries),
+        webhook_backoff: Some(format_duration(cli.alert_webhook_backoff)),
+        exec: cli.alert_exec.clone(),
+      },
+    }
+  }
+}
+
+/// Return true when an argument was not set on the command line nor in the
+/// environment, so the configuration file can set it
+fn from_file(matches: &ArgMatches, id: &str) -> bool {
+  !matches!(
+    matches.value_source(id),
+    Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+  )
+}
+
+/// Replace an argument with the value of the configuration file
+fn merge<T>(matches: &ArgMatches, id: &str, arg: &mut T, value: Option<T>) {
+  if let Some(value) = value {
+    if from_file(matches, id) {
+      *arg = value;
+    }
+  }
+}
+
+/// Parse a value of the configuration file
+fn parse<T>(
+  key: &str,
+  value: Option<String>,
+  parse: impl Fn(&str) -> Result<T, String>,
+) -> Result<Option<T>, MetrsError> {
+  value
+    .map(|value| parse(&value))
+    .transpose()
+    .map_err(|err| MetrsError::Error(format!("Invalid {key} in config: {err}")))
+}
+
+/// Parse a list of values of the configuration file
+fn parse_list<T>(
+  key: &str,
+  values: Option<Vec<String>>,
+  parse: impl Fn(&str) -> Result<T, String>,
+) -> Result<Option<Vec<T>>, MetrsError> {
+  values
+    .map(|values| values.iter().map(|value| parse(value)).collect())
+    .transpose()
+    .map_err(|err| MetrsError::Error(format!("Invalid {key} in config: {err}")))
+}
+
+/// Build the configuration from the parsed arguments and environment, then
+/// from the configuration file for what they don't set
+pub fn from_matches(matches: &ArgMatches) -> Result<Cli, MetrsError> {
+  let mut cli = Cli::from_arg_matches(matches)
+    .map_err(|err| MetrsError::Error(err.to_string()))?;
+  if let Some(path) = cli.config.clone() {
+    let file = ConfigFile::load(&path)?;
+    merge(matches, "hosts", &mut cli.hosts, file.hosts);
+    merge(
+      matches,
+      "tls_cert",
+      &mut cli.tls_cert,
+      file.tls.cert.map(Some),
+    );
+    merge(matches, "tls_key", &mut cli.tls_key, file.tls.key.map(Some));
+    merge(
+      matches,
+      "tls_client_ca",
+      &mut cli.tls_client_ca,
+      file.tls.client_ca.map(Some),
+    );
+    merge(
+      matches,
+      "auth_tokens",
+      &mut cli.auth_tokens,
+      file.auth.tokens_file.map(Some),
+    );
+    merge(
+      matches,
+      "allowed_uids",
+      &mut cli.allowed_uids,
+      file.auth.allowed_uids,
+    );
+    merge(
+      matches,
+      "allowed_gids",
+      &mut cli.allowed_gids,
+      file.auth.allowed_gids,
+    );
+    merge(
+      matches,
+      "tick_interval",
+      &mut cli.tick_interval,
+      file.tick_interval,
+    );
+    if file.min_tick_interval == Some(0) {
+      return Err(MetrsError::Error(
+        "Invalid min_tick_interval in config: must be greater than 0".into(),
+      ));
+    }
+    merge(
+      matches,
+      "min_tick_interval",
+      &mut cli.min_tick_interval,
+      file.min_tick_interval,
+    );
+    merge(matches, "replay", &mut cli.replay, file.replay.map(Some));
+    let collectors = file.collectors;
+    let enabled = parse_list(
+      "collectors.enabled",
+      collectors.enabled,
+      crate::cli::parse_collector,
+    )?;
+    merge(matches, "collectors", &mut cli.collectors, enabled);
+    merge(
+      matches,
+      "top_processes",
+      &mut cli.top_processes,
+      collectors.top_processes,
+    );
+    let top_processes_by = parse(
+      "collectors.top_processes_by",
+      collectors.top_processes_by,
+      |value| ProcessSort::from_str(value, true),
+    )?;
+    merge(
+      matches,
+      "top_processes_by",
+      &mut cli.top_processes_by,
+      top_processes_by,
+    );
+    merge(
+      matches,
+      "sysfs_root",
+      &mut cli.sysfs_root,
+      collectors.sysfs_root,
+    );
+    merge(
+      matches,
+      "cgroup_root",
+      &mut cli.cgroup_root,
+      collectors.cgroup_root.map(Some),
+    );
+    merge(matches, "disks", &mut cli.disks, file.filters.disks);
+    merge(
+      matches,
+      "interfaces",
+      &mut cli.interfaces,
+      file.filters.interfaces,
+    );
+    let storage = file.storage;
+    let history = parse("storage.history", storage.history, str::parse)?;
+    merge(matches, "history", &mut cli.history, history);
+    merge(
+      matches,
+      "data_dir",
+      &mut cli.data_dir,
+      storage.data_dir.map(Some),
+    );
+    let retention_size =
+      parse("storage.retention_size", storage.retention_size, parse_size)?;
+    merge(
+      matches,
+      "retention_size",
+      &mut cli.retention_size,
+      retention_size,
+    );
+    let retention_age = parse(
+      "storage.retention_age",
+      storage.retention_age,
+      parse_duration,
+    )?;
+    merge(
+      matches,
+      "retention_age",
+      &mut cli.retention_age,
+      retention_age,
+    );
+    let rollups = parse_list("storage.rollups", storage.rollups, str::parse)?;
+    merge(matches, "rollups", &mut cli.rollups, rollups);
+    merge(
+      matches,
+      "alert_rules",
+      &mut cli.alert_rules,
+      file.alerts.rules_file.map(Some),
+    );
+    cli.alert_rule_list = AlertRules {
+      rules: file.alerts.rules,
+    };
+    let exporters = file.exporters;
+    merge(
+      matches,
+      "alert_webhooks",
+      &mut cli.alert_webhooks,
+      exporters.webhooks,
+    );
+    merge(
+      matches,
+      "alert_webhook_retries",
+      &mut cli.alert_webhook_retries,
+      exporters.webhook_retries,
+    );
+    let webhook_backoff = parse(
+      "exporters.webhook_backoff",
+      exporters.webhook_backoff,
+      parse_duration,
+    )?;
+    merge(
+      matches,
+      "alert_webhook_backoff",
+      &mut cli.alert_webhook_backoff,
+      webhook_backoff,
+    );
+    merge(
+      matches,
+      "alert_exec",
+      &mut cli.alert_exec,
+      exporters.exec.map(Some),
+    );
+  }
+  if cli.hosts.is_empty() {
+    return Err(MetrsError::Error(
+      "No hosts to listen on, set --hosts or hosts in the config".into(),
+    ));
+  }
+  Ok(cli)
+}
+
+/// Parse the command line arguments, the environment and the configuration
+/// file, in that order of precedence
+pub fn load() -> Result<Cli, MetrsError> {
+  from_matches(&Cli::command().get_matches())
+}
+
+/// Alert rules of the configuration file and of the rules file
+pub fn alert_rules(cli: &Cli) -> Result<AlertRules, MetrsError> {
+  let mut rules = cli.alert_rule_list.clone();
+  if let Some(path) = &cli.alert_rules {
+    rules.rules.extend(AlertRules::load(path)?.rules);
+  }
+  rules.validate()?;
+  Ok(rules)
+}
+
+/// Validate the configuration and render the effective one as toml
+pub fn check(cli: &Cli) -> Result<String, MetrsError> {
+  alert_rules(cli)?;
+  AuthConfig::load(cli)?;
+  if let Some(path) = &cli.replay {
+    replay::open(path)?;
+  }
+  if cli.hosts.iter().any(|host| tls::is_tls_host(host)) {
+    tls::TlsConfig::from(cli).server_config()?;
+  }
+  toml::to_string(&ConfigFile::from(cli))
+    .map_err(|err| MetrsError::Error(format!("Unable to render config: {err}")))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use metrs_stubs::EventSection;
+
+  use super::*;
+
+  const CONFIG: &str = r#"
+hosts = ["tcp://0.0.0.0:8585"]
+tick_interval = 5
+
+[collectors]
+enabled = ["memory", "disks"]
+top_processes_by = "memory"
+
+[filters]
+disks = ["/"]
+
+[storage]
+history = "360"
+retention_age = "2d"
+rollups = ["5m:7d"]
+
+[[alerts.rules]]
+name = "memory_high"
+metric = "memory.used_percent"
+op = ">"
+threshold = 90
+
+[exporters]
+webhooks = ["http://127.0.0.1:9000/hook"]
+webhook_backoff = "5s"
+"#;
+
+  fn parse(args: &[&str]) -> Result<Cli, MetrsError> {
+    from_matches(&Cli::command().get_matches_from(args))
+  }
+
+  /// Arguments parsed by `test_parse_child` in a child process
+  const CHILD_ARGS: &str = "METRSD_TEST_ARGS";
+  /// File the child process writes the effective configuration to
+  const CHILD_OUTPUT: &str = "METRSD_TEST_OUTPUT";
+
+  /// Parse the arguments with the environment in a child process, the
+  /// environment is shared by the tests running in parallel so it's never
+  /// changed in place
+  fn parse_with_env(args: &[&str], env: &[(&str, &str)]) -> Cli {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("effective.toml");
+    let child = std::process::Command::new(std::env::current_exe().unwrap())
+      .args(["config::tests::test_parse_child", "--exact", "--quiet"])
+      .env(CHILD_ARGS, args.join("\n"))
+      .env(CHILD_OUTPUT, &output)
+      .envs(env.iter().copied())
+      .output()
+      .unwrap();
+    assert!(child.status.success(), "{child:?}");
+    parse(&["metrsd", "--config", output.to_str().unwrap()]).unwrap()
+  }
+
+  /// Write the effective configuration of the arguments of the parent
+  /// test, nothing to do when run by the test harness
+  #[test]
+  fn test_parse_child() {
+    let (Ok(args), Ok(output)) =
+      (std::env::var(CHILD_ARGS), std::env::var(CHILD_OUTPUT))
+    else {
+      return;
+    };
+    let args = args.split('\n').collect::<Vec<_>>();
+    let cli = parse(&args).unwrap();
+    std::fs::write(output, check(&cli).unwrap()).unwrap();
+  }
+
+  #[test]
+  fn test_config_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("metrsd.toml");
+    std::fs::write(&path, CONFIG).unwrap();
+    let path = path.to_str().unwrap();
+    let cli = parse(&["metrsd", "--config", path]).unwrap();
+    assert_eq!(cli.hosts, vec!["tcp://0.0.0.0:8585"]);
+    assert_eq!(
+      cli.collectors,
+      vec![EventSection::Memory, EventSection::Disks]
+    );
+    assert_eq!(cli.top_processes_by, ProcessSort::Memory);
+    assert_eq!(cli.disks, vec!["/"]);
+    assert_eq!(cli.history, crate::history::HistorySize::Count(360));
+    assert_eq!(cli.retention_age, Duration::from_secs(2 * 86400));
+    assert_eq!(cli.rollups, vec!["5m:7d".parse().unwrap()]);
+    assert_eq!(cli.alert_rule_list.rules.len(), 1);
+    assert_eq!(cli.alert_webhook_backoff, Duration::from_secs(5));
+    // Values not in the file keep their default
+    assert_eq!(cli.min_tick_interval, 1);
+    assert_eq!(cli.retention_size, 1024 * 1024 * 1024);
+    // The command line and the environment take precedence over the file
+    let env = [("METRSD_TICK_INTERVAL", "20")];
+    let cli = parse_with_env(
+      &[
+        "metrsd",
+        "--config",
+        path,
+        "-H",
+        "unix:///run/metrsd.sock",
+        "--history",
+        "1h",
+      ],
+      &env,
+    );
+    assert_eq!(cli.tick_interval, 20);
+    let cli = parse_with_env(&["metrsd", "--config", path, "-t", "30"], &env);
+    assert_eq!(cli.tick_interval, 30);
+    let cli = parse(&["metrsd", "config", "check", "--config", path]).unwrap();
+    assert_eq!(
+      cli.command,
+      Some(crate::cli::Command::Config(
+        crate::cli::ConfigCommand::Check
+      ))
+    );
+    assert_eq!(cli.tick_interval, 5);
+  }
+
+  #[test]
+  fn test_config_check() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("metrsd.toml");
+    std::fs::write(&path, CONFIG).unwrap();
+    let cli =
+      parse(&["metrsd", "--config", path.to_str().unwrap(), "-t", "15"])
+        .unwrap();
+    let effective = check(&cli).unwrap();
+    // The effective configuration is a valid configuration file
+    let file = toml::from_str::<ConfigFile>(&effective).unwrap();
+    assert_eq!(file.tick_interval, Some(15));
+    assert_eq!(file.storage.retention_size.as_deref(), Some("1G"));
+    assert_eq!(file.alerts.rules.len(), 1);
+    std::fs::write(&path, "unknown = 1").unwrap();
+    let err = parse(&["metrsd", "--config", path.to_str().unwrap()]);
+    assert!(err.is_err());
+    std::fs::write(&path, "[storage]\nhistory = \"1w\"").unwrap();
+    let err = parse(&["metrsd", "--config", path.to_str().unwrap()]);
+    assert!(err.unwrap_err().to_string().contains("storage.history"));
+    let err = parse(&["metrsd"]).unwrap_err();
+    assert!(err.to_string().contains("No hosts"));
+  }
+}
@@ -13,14 +13,30 @@ use ntex::{
   web::error::{Error, BlockingError},
 };
 use futures::Stream;
-use tokio::sync::mpsc::{Sender, Receiver, channel};
+use tokio::sync::{
+  Notify,
+  mpsc::{Sender, Receiver, channel},
+};
 
 use metrs_stubs::*;
 
 use crate::error::HttpError;
+use crate::alert::{AlertEngine, AlertRules};
+use crate::metrics::{MetricsConfig, now_millis};
+use crate::notify::Notifier;
+use crate::rollup::Rollups;
+use crate::store::SegmentStore;
+use crate::history::{self, History, HistorySize};
 
 // Wrap Receiver in our own type, with correct error type
-pub struct Client(Receiver<Bytes>);
+pub struct Client(Receiver<Bytes>, u64);
+
+impl Client {
+  /// Identifier of the subscriber used to update its filter
+  pub fn id(&self) -> u64 {
+    self.1
+  }
+}
 
 impl Stream for Client {
   type Item = Result<Bytes, Error>;
@@ -40,22 +56,282 @@ impl Stream for Client {
 #[derive(Clone)]
 pub struct EventEmitter {
   inner: Arc<Mutex<EventEmitterInner>>,
+  /// Store persisting the events, history is served from it when set
+  store: Option<Arc<Mutex<SegmentStore>>>,
+  /// Rollup tiers served by history queries with a large step or window
+  rollups: Arc<Mutex<Rollups>>,
+  /// Alert rules evaluated against every event
+  alerts: Arc<Mutex<AlertEngine>>,
+  /// Sinks the alerts are sent to when they fire or resolve
+  notifier: Arc<Mutex<Notifier>>,
+  /// Options of the collection, read by the metrics loop on every tick
+  metrics_config: Arc<Mutex<MetricsConfig>>,
+}
+
+/// Margin accepted on the interval of a subscriber so the jitter of the
+/// collection doesn't make it skip an event
+const INTERVAL_TOLERANCE: Duration = Duration::from_millis(500);
+
+/// Number of messages buffered for a subscriber
+const CLIENT_BUFFER: usize = 100;
+
+/// Time in milliseconds server-sent events clients wait before reconnecting
+const SSE_RETRY: u64 = 3000;
+
+/// Format of the stream sent to a subscriber
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StreamFormat {
+  /// One json event per line
+  Ndjson,
+  /// Server-sent events as expected by browsers `EventSource`
+  Sse,
+  /// One json event per message, used by websocket subscribers
+  Json,
+}
+
+impl StreamFormat {
+  /// Pick the format from the `Accept` header of the request
+  pub fn from_accept(accept: Option<&str>) -> Self {
+    match accept {
+      Some(accept) if accept.contains("text/event-stream") => Self::Sse,
+      _ => Self::Ndjson,
+    }
+  }
+
+  /// Check the filter can be served in this format, only server-sent events
+  /// tell the alerts apart from the metrics so other formats serve them on
+  /// their own stream
+  pub fn check_filter(&self, filter: &EventFilter) -> Result<(), String> {
+    match self {
+      Self::Ndjson | Self::Json
+        if filter.wants_alerts() && filter.wants_metrics() =>
+      {
+        Err(
+          "Alerts cannot be mixed with metric sections, subscribe to them \
+           on their own stream"
+            .into(),
+        )
+      }
+      _ => Ok(()),
+    }
+  }
+
+  pub fn content_type(&self) -> &'static str {
+    match self {
+      Self::Ndjson => "application/x-ndjson",
+      Self::Sse => "text/event-stream",
+      Self::Json => "application/json",
+    }
+  }
+
+  /// Message sent periodically to check the connection is still alive
+  fn heartbeat(&self) -> Bytes {
+    match self {
+      Self::Ndjson | Self::Json => Bytes::from(""),
+      Self::Sse => Bytes::from(": heartbeat\n\n"),
+    }
+  }
+
+  /// Frame an alert already serialized as json, alerts carry no id so
+  /// server-sent events clients keep the id of the last metrics event
+  fn alert_frame(&self, json: &str) -> Bytes {
+    match self {
+      Self::Ndjson => Bytes::from(format!("{json}\n")),
+      Self::Sse => Bytes::from(format!("event: alert\ndata: {json}\n\n")),
+      Self::Json => Bytes::from(json.to_owned()),
+    }
+  }
+
+  /// Message sent when the subscriber connects
+  fn preamble(&self) -> Option<Bytes> {
+    match self {
+      Self::Ndjson | Self::Json => None,
+      Self::Sse => Some(Bytes::from(format!("retry: {SSE_RETRY}\n\n"))),
+    }
+  }
+
+  /// Frame a metrics event already serialized as json
+  fn frame(&self, sequence: u64, json: &str) -> Bytes {
+    match self {
+      Self::Ndjson => Bytes::from(format!("{json}\n")),
+      Self::Json => Bytes::from(json.to_owned()),
+      Self::Sse => {
+        Bytes::from(format!("id: {sequence}\nevent: metrics\ndata: {json}\n\n"))
+      }
+    }
+  }
+}
+
+/// Serialize an event as json
+fn to_json(ev: &MetrsdEvent) -> Result<String, HttpError> {
+  serde_json::to_string(ev).map_err(|err| HttpError {
+    status: StatusCode::INTERNAL_SERVER_ERROR,
+    msg: format!("Unable to serialize event: {err}"),
+  })
+}
+
+/// A subscriber with the filter to apply to its events
+#[derive(Clone)]
+struct EventClient {
+  id: u64,
+  tx: Sender<Bytes>,
+  filter: EventFilter,
+  format: StreamFormat,
+  /// Timestamp in milliseconds of the last event sent to the subscriber
+  last_sent: Option<u64>,
+}
+
+impl EventClient {
+  /// Minimum interval between two events requested by the subscriber
+  fn interval(&self) -> Option<Duration> {
+    self.filter.interval.map(Duration::from_secs)
+  }
+
+  /// Return true if the subscriber is waiting for an event collected at
+  /// `timestamp`, subscribers without an interval receive the events at the
+  /// tick interval even when a faster subscriber speeds up the collection
+  fn is_due(&self, timestamp: u64, tick_interval: Duration) -> bool {
+    let interval = self.interval().unwrap_or(tick_interval);
+    match self.last_sent {
+      // The clock of the host went back, don't wait for it to catch up
+      Some(last_sent) if timestamp >= last_sent => {
+        Duration::from_millis(timestamp - last_sent) + INTERVAL_TOLERANCE
+          >= interval
+      }
+      _ => true,
+    }
+  }
 }
 
 #[derive(Clone)]
 struct EventEmitterInner {
-  clients: Vec<Sender<Bytes>>,
+  clients: Vec<EventClient>,
+  last_event: Option<MetrsdEvent>,
+  /// Most recent events replayed to reconnecting subscribers
+  history: History,
+  /// Identifier given to the next subscriber
+  next_id: u64,
+  /// Notified when a subscriber requesting an interval joins or the
+  /// configuration is reloaded
+  interval_changed: Arc<Notify>,
+}
+
+impl Default for EventEmitter {
+  fn default() -> Self {
+    Self::with_history(HistorySize::default())
+  }
 }
 
 impl EventEmitter {
-  pub fn new() -> Self {
+  /// Create an event emitter keeping the given amount of history
+  pub fn with_history(size: HistorySize) -> Self {
     let this = Self {
-      inner: Arc::new(Mutex::new(EventEmitterInner { clients: vec![] })),
+      inner: Arc::new(Mutex::new(EventEmitterInner {
+        clients: vec![],
+        last_event: None,
+        history: History::new(size),
+        next_id: 0,
+        interval_changed: Arc::new(Notify::new()),
+      })),
+      store: None,
+      rollups: Arc::new(Mutex::new(Rollups::default())),
+      alerts: Arc::new(Mutex::new(AlertEngine::default())),
+      notifier: Arc::new(Mutex::new(Notifier::default())),
+      metrics_config: Arc::new(Mutex::new(MetricsConfig::default())),
     };
     this.clone().spawn_check_connection();
     this
   }
 
+  /// Persist the events emitted in a segment store
+  pub fn with_store(mut self, store: SegmentStore) -> Self {
+    self.store = Some(Arc::new(Mutex::new(store)));
+    self
+  }
+
+  /// Aggregate the events emitted in rollup tiers
+  pub fn with_rollups(mut self, rollups: Rollups) -> Self {
+    self.rollups = Arc::new(Mutex::new(rollups));
+    self
+  }
+
+  /// Evaluate alert rules against the events emitted
+  pub fn with_alerts(mut self, alerts: AlertEngine) -> Self {
+    self.alerts = Arc::new(Mutex::new(alerts));
+    self
+  }
+
+  /// Send the alerts to webhooks or an exec hook when they fire or resolve
+  pub fn with_notifier(mut self, notifier: Notifier) -> Self {
+    self.notifier = Arc::new(Mutex::new(notifier));
+    self
+  }
+
+  /// Collect the metrics with the given options
+  pub fn with_metrics_config(mut self, config: MetricsConfig) -> Self {
+    self.metrics_config = Arc::new(Mutex::new(config));
+    self
+  }
+
+  /// Options of the collection currently applied
+  pub fn metrics_config(&self) -> Result<MetricsConfig, HttpError> {
+    let config = self
+      .metrics_config
+      .lock()
+      .map_err(|err| HttpError {
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+        msg: format!("Unable to lock metrics config mutex: {err}"),
+      })?
+      .clone();
+    Ok(config)
+  }
+
+  /// Replace the options of the collection, the metrics loop applies them
+  /// right away
+  pub fn set_metrics_config(
+    &self,
+    config: MetricsConfig,
+  ) -> Result<(), HttpError> {
+    *self.metrics_config.lock().map_err(|err| HttpError {
+      status: StatusCode::INTERNAL_SERVER_ERROR,
+      msg: format!("Unable to lock metrics config mutex: {err}"),
+    })? = config;
+    self
+      .inner
+      .lock()
+      .map_err(|err| HttpError {
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+        msg: format!("Unable to lock event emitter mutex: {err}"),
+      })?
+      .interval_changed
+      .notify_one();
+    Ok(())
+  }
+
+  /// Replace the alert rules, keeping the state of the rules still defined
+  /// the same way, the firing alerts of the others resolve with the next
+  /// event
+  pub fn set_alert_rules(&self, rules: AlertRules) -> Result<(), HttpError> {
+    self
+      .alerts
+      .lock()
+      .map_err(|err| HttpError {
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+        msg: format!("Unable to lock alerts mutex: {err}"),
+      })?
+      .set_rules(rules);
+    Ok(())
+  }
+
+  /// Replace the sinks the alerts are sent to
+  pub fn set_notifier(&self, notifier: Notifier) -> Result<(), HttpError> {
+    *self.notifier.lock().map_err(|err| HttpError {
+      status: StatusCode::INTERNAL_SERVER_ERROR,
+      msg: format!("Unable to lock notifier mutex: {err}"),
+    })? = notifier;
+    Ok(())
+  }
+
   /// Spawn a task that will check if clients are still connected
   fn spawn_check_connection(mut self) {
     rt::spawn(async move {
@@ -70,10 +346,131 @@ impl EventEmitter {
   }
 
   /// Check if clients are still connected
-  fn check_connection(&mut self) -> Result<(), HttpError> {
+  pub fn check_connection(&mut self) -> Result<(), HttpError> {
     log::trace!("Checking alive connection...");
-    let mut alive_clients = Vec::new();
-    let clients = self
+    let mut inner = self.inner.lock().map_err(|err| HttpError {
+      status: StatusCode::INTERNAL_SERVER_ERROR,
+      msg: format!("Unable to lock event emitter mutex: {err}"),
+    })?;
+    // Checked while locked so the clients subscribed or updated meanwhile
+    // are kept
+    inner
+      .clients
+      .retain(|client| client.tx.try_send(client.format.heartbeat()).is_ok());
+    log::trace!("Alive clients: {}", inner.clients.len());
+    Ok(())
+  }
+
+  /// Subscribe to the events, a subscriber reconnecting with the id of the
+  /// last event it received gets the events it missed from the history, or
+  /// the most recent one when the history doesn't go back that far
+  pub async fn subscribe(
+    &self,
+    filter: EventFilter,
+    format: StreamFormat,
+    last_event_id: Option<u64>,
+  ) -> Result<Client, HttpError> {
+    format.check_filter(&filter).map_err(|msg| HttpError {
+      status: StatusCode::BAD_REQUEST,
+      msg,
+    })?;
+    let tick_interval =
+      Duration::from_secs(self.metrics_config()?.tick_interval);
+    let this = self.clone();
+    let (tx, rx) = channel(CLIENT_BUFFER);
+    let id = web::block(move || {
+      let mut inner = this.inner.lock().map_err(|err| HttpError {
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+        msg: format!("Unable to lock event emitter mutex: {err}"),
+      })?;
+      if filter.interval.is_some() {
+        inner.interval_changed.notify_one();
+      }
+      if let Some(preamble) = format.preamble() {
+        let _ = tx.try_send(preamble);
+      }
+      let id = inner.next_id;
+      inner.next_id += 1;
+      let mut client = EventClient {
+        id,
+        tx,
+        filter,
+        format,
+        last_sent: None,
+      };
+      // Sent while locked so they cannot arrive after a newer event
+      let missed = match (last_event_id, &inner.last_event) {
+        (Some(id), Some(ev)) if id != ev.sequence => {
+          let missed = inner.history.after(id);
+          if missed.is_empty() {
+            vec![ev.clone()]
+          } else {
+            missed
+          }
+        }
+        _ => Vec::new(),
+      };
+      // Selected like the events emitted to the subscriber
+      let mut missed = missed
+        .into_iter()
+        .filter(|ev| {
+          let due = client.filter.wants_metrics()
+            && client.is_due(ev.timestamp, tick_interval);
+          if due {
+            client.last_sent = Some(ev.timestamp);
+          }
+          due
+        })
+        .collect::<Vec<_>>();
+      // Keep room for the preamble in the buffer of the subscriber
+      missed.drain(..missed.len().saturating_sub(CLIENT_BUFFER - 1));
+      for ev in &missed {
+        let json = to_json(&client.filter.apply(ev))?;
+        let _ = client.tx.try_send(client.format.frame(ev.sequence, &json));
+      }
+      inner.clients.push(client);
+      Ok::<u64, HttpError>(id)
+    })
+    .await
+    .map_err(|err| match err {
+      BlockingError::Error(err) => err,
+      BlockingError::Canceled => HttpError {
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+        msg: "Unable to subscribe to metrics server furture got cancelled"
+          .into(),
+      },
+    })?;
+    Ok(Client(rx, id))
+  }
+
+  /// Replace the filter of a connected subscriber
+  pub fn update_filter(
+    &self,
+    id: u64,
+    filter: EventFilter,
+  ) -> Result<(), HttpError> {
+    let mut inner = self.inner.lock().map_err(|err| HttpError {
+      status: StatusCode::INTERNAL_SERVER_ERROR,
+      msg: format!("Unable to lock event emitter mutex: {err}"),
+    })?;
+    if filter.interval.is_some() {
+      inner.interval_changed.notify_one();
+    }
+    let client = inner
+      .clients
+      .iter_mut()
+      .find(|client| client.id == id)
+      .ok_or(HttpError {
+        status: StatusCode::NOT_FOUND,
+        msg: format!("Subscriber {id} not found"),
+      })?;
+    client.filter = filter;
+    Ok(())
+  }
+
+  /// Smallest interval requested by the connected subscribers, if any
+  pub fn min_interval(&self) -> Result<Option<Duration>, HttpError> {
+    let min_interval = self
       .inner
       .lock()
       .map_err(|err| HttpError {
@@ -81,70 +478,226 @@ impl EventEmitter {
         msg: format!("Unable to lock event emitter mutex: {err}"),
       })?
       .clients
-      .clone();
-    for client in clients {
-      let result = client.clone().try_send(Bytes::from(""));
-      if let Ok(()) = result {
-        alive_clients.push(client.clone());
-      }
-    }
-    log::trace!("Alive clients: {}", alive_clients.len());
-    self
+      .iter()
+      .filter_map(EventClient::interval)
+      .min();
+    Ok(min_interval)
+  }
+
+  /// Wait until a subscriber requesting an interval joins
+  pub async fn interval_changed(&self) -> Result<(), HttpError> {
+    let interval_changed = self
       .inner
       .lock()
       .map_err(|err| HttpError {
         status: StatusCode::INTERNAL_SERVER_ERROR,
         msg: format!("Unable to lock event emitter mutex: {err}"),
       })?
-      .clients = alive_clients;
+      .interval_changed
+      .clone();
+    interval_changed.notified().await;
     Ok(())
   }
 
-  pub async fn subscribe(&self) -> Result<Client, HttpError> {
+  /// Get the most recent event emitted, if any
+  pub fn last_event(&self) -> Result<Option<MetrsdEvent>, HttpError> {
+    let last_event = self
+      .inner
+      .lock()
+      .map_err(|err| HttpError {
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+        msg: format!("Unable to lock event emitter mutex: {err}"),
+      })?
+      .last_event
+      .clone();
+    Ok(last_event)
+  }
+
+  /// Get the events of the history in a window, at least `step` apart, from
+  /// the rollup tier matching the step and window, or the raw events read
+  /// from the store when they are persisted. Without `since` the window
+  /// starts an hour before `until` or the most recent event, collected or
+  /// persisted.
+  pub async fn history(
+    &self,
+    since: Option<u64>,
+    until: Option<u64>,
+    step: Option<Duration>,
+  ) -> Result<Vec<MetrsdEvent>, HttpError> {
     let this = self.clone();
-    let (tx, rx) = channel(100);
     web::block(move || {
-      this
-        .inner
-        .lock()
-        .map_err(|err| HttpError {
+      let store_err = |err| HttpError {
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+        msg: format!("Unable to read segment store: {err}"),
+      };
+      let store = match &this.store {
+        Some(store) => Some(store.lock().map_err(|err| HttpError {
+          status: StatusCode::INTERNAL_SERVER_ERROR,
+          msg: format!("Unable to lock segment store mutex: {err}"),
+        })?),
+        None => None,
+      };
+      let (now, since, raw_retention, events) = {
+        let inner = this.inner.lock().map_err(|err| HttpError {
           status: StatusCode::INTERNAL_SERVER_ERROR,
           msg: format!("Unable to lock event emitter mutex: {err}"),
-        })?
-        .clients
-        .push(tx);
-      Ok::<(), HttpError>(())
+        })?;
+        // Until an event is collected after a restart the window ends at
+        // the most recent one persisted
+        let now = match (&inner.last_event, &store) {
+          (Some(ev), _) => ev.timestamp,
+          (None, Some(store)) => store
+            .last_timestamp()
+            .map_err(store_err)?
+            .unwrap_or_else(now_millis),
+          (None, None) => now_millis(),
+        };
+        let since = since.or_else(|| {
+          let window = history::DEFAULT_WINDOW.as_millis() as u64;
+          Some(until.unwrap_or(now).saturating_sub(window))
+        });
+        let raw_retention = match &this.store {
+          Some(_) => None,
+          None => inner.history.retention(),
+        };
+        let events = inner.history.range(since, until, step);
+        (now, since, raw_retention, events)
+      };
+      let raw_retention = store
+        .as_ref()
+        .map(|store| store.retention())
+        .or(raw_retention);
+      let rollups = this.rollups.lock().map_err(|err| HttpError {
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+        msg: format!("Unable to lock rollups mutex: {err}"),
+      })?;
+      if let Some(rollup) = rollups.select(since, step, now, raw_retention) {
+        return rollup.range(since, until, step).map_err(store_err);
+      }
+      match store {
+        Some(store) => store.range(since, until, step).map_err(store_err),
+        None => Ok(events),
+      }
     })
     .await
     .map_err(|err| match err {
       BlockingError::Error(err) => err,
       BlockingError::Canceled => HttpError {
         status: StatusCode::INTERNAL_SERVER_ERROR,
-        msg: "Unable to subscribe to metrics server furture got cancelled"
-          .into(),
+        msg: "Unable to read history future got cancelled".into(),
       },
-    })?;
-    Ok(Client(rx))
+    })
+  }
+
+  /// Append an event to the store, if any, and to the rollup tiers
+  async fn persist(&self, ev: MetrsdEvent, json: String) {
+    let this = self.clone();
+    let res = web::block(move || {
+      if let Some(store) = &this.store {
+        store
+          .lock()
+          .map_err(|err| format!("Unable to lock segment store mutex: {err}"))?
+          .append(ev.timestamp, &json)
+          .map_err(|err| format!("Unable to persist event: {err}"))?;
+      }
+      this
+        .rollups
+        .lock()
+        .map_err(|err| format!("Unable to lock rollups mutex: {err}"))?
+        .push(ev);
+      Ok::<(), String>(())
+    })
+    .await;
+    if let Err(err) = res {
+      log::error!("{err}");
+    }
+  }
+
+  /// Firing alerts and the most recently resolved ones
+  pub fn alerts(&self) -> Result<Vec<AlertEvent>, HttpError> {
+    let alerts = self
+      .alerts
+      .lock()
+      .map_err(|err| HttpError {
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+        msg: format!("Unable to lock alerts mutex: {err}"),
+      })?
+      .alerts();
+    Ok(alerts)
   }
 
   pub async fn emit(&self, ev: MetrsdEvent) -> Result<(), HttpError> {
     let this = self.clone();
     rt::spawn(async move {
-      let msg = Bytes::try_from(ev).map_err(|err| HttpError {
-        status: StatusCode::INTERNAL_SERVER_ERROR,
-        msg: format!("Unable to serialize event: {err}"),
-      })?;
-      let clients = this
-        .inner
+      let alerts = this
+        .alerts
         .lock()
         .map_err(|err| HttpError {
           status: StatusCode::INTERNAL_SERVER_ERROR,
-          msg: format!("Unable to lock event emitter mutex: {err}"),
+          msg: format!("Unable to lock alerts mutex: {err}"),
         })?
-        .clients
-        .clone();
+        .evaluate(&ev);
+      if !alerts.is_empty() {
+        this
+          .notifier
+          .lock()
+          .map_err(|err| HttpError {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            msg: format!("Unable to lock notifier mutex: {err}"),
+          })?
+          .notify(&alerts);
+      }
+      let tick_interval =
+        Duration::from_secs(this.metrics_config()?.tick_interval);
+      let (clients, alerted) = {
+        let mut inner = this.inner.lock().map_err(|err| HttpError {
+          status: StatusCode::INTERNAL_SERVER_ERROR,
+          msg: format!("Unable to lock event emitter mutex: {err}"),
+        })?;
+        inner.last_event = Some(ev.clone());
+        inner.history.push(ev.clone());
+        // Only the subscribers waiting for a new event receive this one
+        let clients = inner
+          .clients
+          .iter_mut()
+          .filter(|client| {
+            client.filter.wants_metrics()
+              && client.is_due(ev.timestamp, tick_interval)
+          })
+          .map(|client| {
+            client.last_sent = Some(ev.timestamp);
+            client.clone()
+          })
+          .collect::<Vec<_>>();
+        // Alerts are sent right away whatever the interval of the subscriber
+        let alerted = inner
+          .clients
+          .iter()
+          .filter(|client| !alerts.is_empty() && client.filter.wants_alerts())
+          .cloned()
+          .collect::<Vec<_>>();
+        (clients, alerted)
+      };
+      // Serialized once for all the clients without filter
+      let json = to_json(&ev)?;
+      this.persist(ev.clone(), json.clone()).await;
       for client in clients {
-        let _ = client.send(msg.clone()).await;
+        let msg = if client.filter.is_empty() {
+          client.format.frame(ev.sequence, &json)
+        } else {
+          let json = to_json(&client.filter.apply(&ev))?;
+          client.format.frame(ev.sequence, &json)
+        };
+        let _ = client.tx.send(msg).await;
+      }
+      for alert in &alerts {
+        let json = serde_json::to_string(alert).map_err(|err| HttpError {
+          status: StatusCode::INTERNAL_SERVER_ERROR,
+          msg: format!("Unable to serialize alert: {err}"),
+        })?;
+        for client in &alerted {
+          let _ = client.tx.send(client.format.alert_frame(&json)).await;
+        }
       }
       Ok::<(), HttpError>(())
     })
@@ -156,3 +709,348 @@ impl EventEmitter {
     Ok(())
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn client(interval: Option<u64>) -> EventClient {
+    let (tx, _) = channel(1);
+    EventClient {
+      id: 0,
+      tx,
+      filter: EventFilter {
+        interval,
+        ..Default::default()
+      },
+      format: StreamFormat::Ndjson,
+      last_sent: None,
+    }
+  }
+
+  #[test]
+  fn test_is_due() {
+    let tick = Duration::from_secs(10);
+    let now = 1_760_000_000_000;
+    let mut every_tick = client(None);
+    assert!(every_tick.is_due(now, tick));
+    every_tick.last_sent = Some(now);
+    assert!(!every_tick.is_due(now + 1_000, tick));
+    assert!(every_tick.is_due(now + 10_000, tick));
+    let mut every_minute = client(Some(60));
+    assert!(every_minute.is_due(now, tick));
+    every_minute.last_sent = Some(now);
+    assert!(!every_minute.is_due(now + 30_000, tick));
+    assert!(every_minute.is_due(now + 59_700, tick));
+    assert!(every_minute.is_due(now + 61_000, tick));
+    // The clock of the host went back
+    assert!(every_minute.is_due(now - 1_000, tick));
+  }
+
+  #[ntex::test]
+  async fn test_min_interval() {
+    let event_emitter = EventEmitter::default();
+    assert_eq!(event_emitter.min_interval().unwrap(), None);
+    let _every_event = event_emitter
+      .subscribe(EventFilter::default(), StreamFormat::Ndjson, None)
+      .await
+      .unwrap();
+    assert_eq!(event_emitter.min_interval().unwrap(), None);
+    let filter = EventFilter {
+      interval: Some(60),
+      ..Default::default()
+    };
+    let _every_minute = event_emitter
+      .subscribe(filter, StreamFormat::Ndjson, None)
+      .await
+      .unwrap();
+    let filter = EventFilter {
+      interval: Some(1),
+      ..Default::default()
+    };
+    let _every_second = event_emitter
+      .subscribe(filter, StreamFormat::Ndjson, None)
+      .await
+      .unwrap();
+    assert_eq!(
+      event_emitter.min_interval().unwrap(),
+      Some(Duration::from_secs(1))
+    );
+    // The permit is stored until the collector waits for it
+    event_emitter.interval_changed().await.unwrap();
+  }
+
+  #[ntex::test]
+  async fn test_emit_decimate() {
+    let event_emitter = EventEmitter::default();
+    let filter = EventFilter {
+      interval: Some(60),
+      ..Default::default()
+    };
+    let Client(mut every_minute, _) = event_emitter
+      .subscribe(filter, StreamFormat::Ndjson, None)
+      .await
+      .unwrap();
+    let Client(every_event, _) = event_emitter
+      .subscribe(EventFilter::default(), StreamFormat::Ndjson, None)
+      .await
+      .unwrap();
+    // Collected at the default tick interval of 10 seconds
+    for sequence in 1..=3 {
+      let ev = MetrsdEvent {
+        sequence,
+        timestamp: sequence * 10_000,
+        ..Default::default()
+      };
+      event_emitter.emit(ev).await.unwrap();
+    }
+    assert_eq!(every_event.len(), 3);
+    assert_eq!(every_minute.len(), 1);
+    let msg = every_minute.recv().await.unwrap();
+    let ev = serde_json::from_slice::<MetrsdEvent>(&msg).unwrap();
+    assert_eq!(ev.sequence, 1);
+  }
+
+  #[ntex::test]
+  async fn test_emit_fast_subscriber() {
+    let config = MetricsConfig {
+      tick_interval: 2,
+      ..Default::default()
+    };
+    let event_emitter = EventEmitter::default().with_metrics_config(config);
+    let filter = EventFilter {
+      interval: Some(1),
+      ..Default::default()
+    };
+    let Client(every_second, _) = event_emitter
+      .subscribe(filter, StreamFormat::Ndjson, None)
+      .await
+      .unwrap();
+    let Client(every_tick, _) = event_emitter
+      .subscribe(EventFilter::default(), StreamFormat::Ndjson, None)
+      .await
+      .unwrap();
+    // The collection is sped up to every second by the fast subscriber
+    for sequence in 1..=6 {
+      let ev = MetrsdEvent {
+        sequence,
+        timestamp: sequence * 1_000,
+        ..Default::default()
+      };
+      event_emitter.emit(ev).await.unwrap();
+    }
+    assert_eq!(every_second.len(), 6);
+    assert_eq!(every_tick.len(), 3);
+  }
+
+  #[test]
+  fn test_stream_format() {
+    assert_eq!(StreamFormat::from_accept(None), StreamFormat::Ndjson);
+    assert_eq!(
+      StreamFormat::from_accept(Some("application/json")),
+      StreamFormat::Ndjson
+    );
+    assert_eq!(
+      StreamFormat::from_accept(Some("text/event-stream")),
+      StreamFormat::Sse
+    );
+    assert_eq!(StreamFormat::Ndjson.frame(1, "{}"), Bytes::from("{}\n"));
+    assert_eq!(
+      StreamFormat::Sse.frame(7, "{}"),
+      Bytes::from("id: 7\nevent: metrics\ndata: {}\n\n")
+    );
+    assert_eq!(
+      StreamFormat::Sse.heartbeat(),
+      Bytes::from(": heartbeat\n\n")
+    );
+  }
+
+  #[ntex::test]
+  async fn test_subscribe_last_event_id() {
+    let event_emitter = EventEmitter::default();
+    let ev = MetrsdEvent {
+      sequence: 5,
+      ..Default::default()
+    };
+    event_emitter.emit(ev).await.unwrap();
+    let Client(mut up_to_date, _) = event_emitter
+      .subscribe(EventFilter::default(), StreamFormat::Sse, Some(5))
+      .await
+      .unwrap();
+    assert_eq!(
+      up_to_date.recv().await.unwrap(),
+      Bytes::from("retry: 3000\n\n")
+    );
+    assert!(up_to_date.is_empty());
+    let Client(mut behind, _) = event_emitter
+      .subscribe(EventFilter::default(), StreamFormat::Sse, Some(3))
+      .await
+      .unwrap();
+    assert_eq!(behind.recv().await.unwrap(), Bytes::from("retry: 3000\n\n"));
+    let frame = behind.recv().await.unwrap();
+    assert!(frame.starts_with(b"id: 5\nevent: metrics\ndata: {"));
+  }
+
+  #[ntex::test]
+  async fn test_subscribe_replay_history() {
+    let event_emitter = EventEmitter::default();
+    for sequence in 1..=4 {
+      let ev = MetrsdEvent {
+        sequence,
+        timestamp: sequence * 10_000,
+        ..Default::default()
+      };
+      event_emitter.emit(ev).await.unwrap();
+    }
+    let Client(mut behind, _) = event_emitter
+      .subscribe(EventFilter::default(), StreamFormat::Ndjson, Some(2))
+      .await
+      .unwrap();
+    let sequences =
+      [behind.recv().await.unwrap(), behind.recv().await.unwrap()]
+        .iter()
+        .map(|msg| serde_json::from_slice::<MetrsdEvent>(msg).unwrap().sequence)
+        .collect::<Vec<_>>();
+    assert_eq!(sequences, vec![3, 4]);
+    assert!(behind.is_empty());
+    assert_eq!(
+      event_emitter.history(None, None, None).await.unwrap().len(),
+      4
+    );
+  }
+
+  #[ntex::test]
+  async fn test_subscribe_replay_filter() {
+    let event_emitter = EventEmitter::default();
+    for sequence in 1..=6 {
+      let ev = MetrsdEvent {
+        sequence,
+        timestamp: sequence * 10_000,
+        ..Default::default()
+      };
+      event_emitter.emit(ev).await.unwrap();
+    }
+    let filter = EventFilter {
+      include: vec![EventSection::Alerts],
+      ..Default::default()
+    };
+    let Client(mut alerts_only, _) = event_emitter
+      .subscribe(filter, StreamFormat::Sse, Some(2))
+      .await
+      .unwrap();
+    assert_eq!(
+      alerts_only.recv().await.unwrap(),
+      Bytes::from("retry: 3000\n\n")
+    );
+    assert!(alerts_only.is_empty());
+    let filter = EventFilter {
+      interval: Some(20),
+      ..Default::default()
+    };
+    let Client(mut every_other, _) = event_emitter
+      .subscribe(filter, StreamFormat::Ndjson, Some(2))
+      .await
+      .unwrap();
+    let mut sequences = Vec::new();
+    while let Ok(msg) = every_other.try_recv() {
+      sequences.push(
+        serde_json::from_slice::<MetrsdEvent>(&msg)
+          .unwrap()
+          .sequence,
+      );
+    }
+    assert_eq!(sequences, vec![3, 5]);
+  }
+
+  #[ntex::test]
+  async fn test_history_default_window() {
+    let dir = tempfile::tempdir().unwrap();
+    let store =
+      SegmentStore::open(dir.path(), crate::store::StoreConfig::default())
+        .unwrap();
+    let event_emitter = EventEmitter::default().with_store(store);
+    // Two hours of events collected every 10 minutes
+    for sequence in 0..=12 {
+      let ev = MetrsdEvent {
+        sequence,
+        timestamp: sequence * 600_000,
+        ..Default::default()
+      };
+      event_emitter.emit(ev).await.unwrap();
+    }
+    let events = event_emitter.history(None, None, None).await.unwrap();
+    let sequences = events.iter().map(|ev| ev.sequence).collect::<Vec<_>>();
+    assert_eq!(sequences, vec![6, 7, 8, 9, 10, 11, 12]);
+    let events = event_emitter
+      .history(None, Some(3_600_000), None)
+      .await
+      .unwrap();
+    assert_eq!(events.len(), 7);
+    let events = event_emitter.history(Some(0), None, None).await.unwrap();
+    assert_eq!(events.len(), 13);
+    // Restarted, the window ends at the most recent event persisted
+    let store =
+      SegmentStore::open(dir.path(), crate::store::StoreConfig::default())
+        .unwrap();
+    let event_emitter = EventEmitter::default().with_store(store);
+    let events = event_emitter.history(None, None, None).await.unwrap();
+    let sequences = events.iter().map(|ev| ev.sequence).collect::<Vec<_>>();
+    assert_eq!(sequences, vec![6, 7, 8, 9, 10, 11, 12]);
+  }
+
+  #[ntex::test]
+  async fn test_check_connection() {
+    let event_emitter = EventEmitter::default();
+    let checker = {
+      let mut event_emitter = event_emitter.clone();
+      std::thread::spawn(move || {
+        for _ in 0..50 {
+          event_emitter.check_connection().unwrap();
+        }
+      })
+    };
+    // Subscribed while the connections are checked
+    let mut clients = Vec::new();
+    for _ in 0..20 {
+      let client = event_emitter
+        .subscribe(EventFilter::default(), StreamFormat::Ndjson, None)
+        .await
+        .unwrap();
+      clients.push(client);
+    }
+    checker.join().unwrap();
+    assert_eq!(event_emitter.inner.lock().unwrap().clients.len(), 20);
+    clients.truncate(5);
+    let mut checker = event_emitter.clone();
+    checker.check_connection().unwrap();
+    assert_eq!(event_emitter.inner.lock().unwrap().clients.len(), 5);
+  }
+
+  #[ntex::test]
+  async fn test_update_filter() {
+    let event_emitter = EventEmitter::default();
+    let Client(mut rx, id) = event_emitter
+      .subscribe(EventFilter::default(), StreamFormat::Json, None)
+      .await
+      .unwrap();
+    let filter = EventFilter {
+      include: vec![EventSection::Memory],
+      ..Default::default()
+    };
+    event_emitter.update_filter(id, filter).unwrap();
+    let ev = MetrsdEvent {
+      sequence: 1,
+      cpus: vec![CpuInfo::default()],
+      ..Default::default()
+    };
+    event_emitter.emit(ev).await.unwrap();
+    let msg = rx.recv().await.unwrap();
+    assert!(!msg.ends_with(b"\n"));
+    let ev = serde_json::from_slice::<MetrsdEvent>(&msg).unwrap();
+    assert!(ev.cpus.is_empty());
+    let err = event_emitter
+      .update_filter(id + 1, EventFilter::default())
+      .unwrap_err();
+    assert_eq!(err.status, StatusCode::NOT_FOUND);
+  }
+}
@@ -0,0 +1,264 @@
+use std::{collections::VecDeque, time::Duration};
+
+use metrs_stubs::{MetrsdEvent, format_duration, parse_duration, split_unit};
+
+/// How many events the history keeps
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HistorySize {
+  /// The most recent events up to a count
+  Count(usize),
+  /// The events collected during the most recent duration
+  Duration(Duration),
+}
+
+impl Default for HistorySize {
+  fn default() -> Self {
+    Self::Duration(Duration::from_secs(3600))
+  }
+}
+
+impl std::fmt::Display for HistorySize {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::Count(count) => write!(f, "{count}"),
+      Self::Duration(duration) => write!(f, "{}", format_duration(*duration)),
+    }
+  }
+}
+
+impl std::str::FromStr for HistorySize {
+  type Err = String;
+
+  /// Parse a count such as `360` or a duration such as `90s`, `30m`, `1h`
+  /// or `2d`
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match split_unit(s)? {
+      (count, "") => Ok(Self::Count(count as usize)),
+      _ => Ok(Self::Duration(parse_duration(s)?)),
+    }
+  }
+}
+
+/// Most events returned by a history query, use a larger `step` to cover a
+/// longer window
+pub const MAX_EVENTS: usize = 10_000;
+
+/// Window of a history query when `since` is not set
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(3600);
+
+/// Selection of the events ordered by timestamp in a window, at least `step`
+/// apart, keeping the most recent [`MAX_EVENTS`]
+#[derive(Debug, Clone)]
+pub struct Window {
+  since: Option<u64>,
+  until: Option<u64>,
+  step: u64,
+  last_timestamp: Option<u64>,
+  events: VecDeque<MetrsdEvent>,
+}
+
+impl Window {
+  pub fn new(
+    since: Option<u64>,
+    until: Option<u64>,
+    step: Option<Duration>,
+  ) -> Self {
+    Self {
+      since,
+      until,
+      step: step.map(|step| step.as_millis() as u64).unwrap_or_default(),
+      last_timestamp: None,
+      events: VecDeque::new(),
+    }
+  }
+
+  /// Return true if the event is past the end of the window, the events
+  /// that follow are not selected either
+  pub fn is_done(&self, ev: &MetrsdEvent) -> bool {
+    self.until.is_some_and(|until| ev.timestamp > until)
+  }
+
+  /// Select the event if it is in the window, dropping the oldest selected
+  /// once there are more than [`MAX_EVENTS`]
+  pub fn push(&mut self, ev: MetrsdEvent) {
+    if self.is_done(&ev)
+      || self.since.is_some_and(|since| ev.timestamp < since)
+      || self
+        .last_timestamp
+        .is_some_and(|last| ev.timestamp < last + self.step)
+    {
+      return;
+    }
+    self.last_timestamp = Some(ev.timestamp);
+    self.events.push_back(ev);
+    if self.events.len() > MAX_EVENTS {
+      self.events.pop_front();
+    }
+  }
+
+  pub fn into_events(self) -> Vec<MetrsdEvent> {
+    self.events.into()
+  }
+}
+
+/// Keep the events with a timestamp in the window, at least `step` apart
+pub fn window(
+  events: impl Iterator<Item = MetrsdEvent>,
+  since: Option<u64>,
+  until: Option<u64>,
+  step: Option<Duration>,
+) -> Vec<MetrsdEvent> {
+  let mut window = Window::new(since, until, step);
+  for ev in events {
+    if window.is_done(&ev) {
+      break;
+    }
+    window.push(ev);
+  }
+  window.into_events()
+}
+
+/// Bounded buffer of the most recent events ordered by timestamp
+#[derive(Debug, Clone, Default)]
+pub struct History {
+  size: HistorySize,
+  events: VecDeque<MetrsdEvent>,
+}
+
+impl History {
+  pub fn new(size: HistorySize) -> Self {
+    Self {
+      size,
+      events: VecDeque::new(),
+    }
+  }
+
+  /// Age of the oldest events kept, unknown when sized by count
+  pub fn retention(&self) -> Option<Duration> {
+    match self.size {
+      HistorySize::Count(_) => None,
+      HistorySize::Duration(duration) => Some(duration),
+    }
+  }
+
+  /// Add an event and drop the ones that no longer fit in the history
+  pub fn push(&mut self, ev: MetrsdEvent) {
+    let timestamp = ev.timestamp;
+    self.events.push_back(ev);
+    match self.size {
+      HistorySize::Count(count) => {
+        while self.events.len() > count {
+          self.events.pop_front();
+        }
+      }
+      HistorySize::Duration(duration) => {
+        let oldest = timestamp.saturating_sub(duration.as_millis() as u64);
+        while self.events.front().is_some_and(|ev| ev.timestamp < oldest) {
+          self.events.pop_front();
+        }
+      }
+    }
+  }
+
+  /// Events collected after the one with the given sequence, none when that
+  /// event is no longer in the history
+  pub fn after(&self, sequence: u64) -> Vec<MetrsdEvent> {
+    match self.events.iter().position(|ev| ev.sequence == sequence) {
+      Some(index) => self.events.iter().skip(index + 1).cloned().collect(),
+      None => Vec::new(),
+    }
+  }
+
+  /// Events with a timestamp in the window, at least `step` apart
+  pub fn range(
+    &self,
+    since: Option<u64>,
+    until: Option<u64>,
+    step: Option<Duration>,
+  ) -> Vec<MetrsdEvent> {
+    window(self.events.iter().cloned(), since, until, step)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn event(sequence: u64, timestamp: u64) -> MetrsdEvent {
+    MetrsdEvent {
+      sequence,
+      timestamp,
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn test_history_size() {
+    assert_eq!("360".parse(), Ok(HistorySize::Count(360)));
+    assert_eq!(
+      "90s".parse(),
+      Ok(HistorySize::Duration(Duration::from_secs(90)))
+    );
+    assert_eq!(
+      "30m".parse(),
+      Ok(HistorySize::Duration(Duration::from_secs(1800)))
+    );
+    assert_eq!(
+      "2d".parse(),
+      Ok(HistorySize::Duration(Duration::from_secs(172_800)))
+    );
+    assert!("1w".parse::<HistorySize>().is_err());
+    assert!("h".parse::<HistorySize>().is_err());
+    assert_eq!(HistorySize::Count(360).to_string(), "360");
+    assert_eq!(HistorySize::default().to_string(), "1h");
+  }
+
+  #[test]
+  fn test_push() {
+    let mut history = History::new(HistorySize::Count(2));
+    for sequence in 1..=3 {
+      history.push(event(sequence, sequence * 1000));
+    }
+    let sequences = history
+      .range(None, None, None)
+      .iter()
+      .map(|ev| ev.sequence)
+      .collect::<Vec<_>>();
+    assert_eq!(sequences, vec![2, 3]);
+    let mut history =
+      History::new(HistorySize::Duration(Duration::from_secs(10)));
+    for sequence in 1..=30 {
+      history.push(event(sequence, sequence * 1000));
+    }
+    let events = history.range(None, None, None);
+    assert_eq!(events.len(), 11);
+    assert_eq!(events[0].timestamp, 20_000);
+  }
+
+  #[test]
+  fn test_range() {
+    let mut history = History::default();
+    for sequence in 1..=10 {
+      history.push(event(sequence, sequence * 10_000));
+    }
+    let events = history.range(Some(30_000), Some(60_000), None);
+    let sequences = events.iter().map(|ev| ev.sequence).collect::<Vec<_>>();
+    assert_eq!(sequences, vec![3, 4, 5, 6]);
+    let events = history.range(None, None, Some(Duration::from_secs(30)));
+    let sequences = events.iter().map(|ev| ev.sequence).collect::<Vec<_>>();
+    assert_eq!(sequences, vec![1, 4, 7, 10]);
+    // The most recent events of a window too large are returned
+    let events = (0..MAX_EVENTS as u64 + 10).map(|sequence| event(sequence, 0));
+    let events = window(events, None, None, None);
+    assert_eq!(events.len(), MAX_EVENTS);
+    assert_eq!(events[0].sequence, 10);
+    assert_eq!(events[MAX_EVENTS - 1].sequence, MAX_EVENTS as u64 + 9);
+    let sequences = history
+      .after(8)
+      .iter()
+      .map(|ev| ev.sequence)
+      .collect::<Vec<_>>();
+    assert_eq!(sequences, vec![9, 10]);
+    assert!(history.after(42).is_empty());
+  }
+}
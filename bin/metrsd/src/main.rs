@@ -5,19 +5,47 @@
 */
 
 mod cli;
+mod auth;
+mod config;
+mod alert;
+mod cgroup;
+mod clock;
+mod collector;
 mod error;
+mod history;
 mod server;
+mod store;
 mod metrics;
+mod notify;
+mod prometheus;
+mod reload;
+mod replay;
+mod rollup;
+mod tls;
 mod event_emitter;
 
-use clap::Parser;
-
 use metrics::*;
 use event_emitter::EventEmitter;
 
 #[ntex::main]
 async fn main() -> std::io::Result<()> {
-  let cli = cli::Cli::parse();
+  let cli = match config::load() {
+    Err(err) => {
+      println!("{err}");
+      std::process::exit(1);
+    }
+    Ok(cli) => cli,
+  };
+  if let Some(cli::Command::Config(cli::ConfigCommand::Check)) = &cli.command {
+    match config::check(&cli) {
+      Err(err) => {
+        println!("{err}");
+        std::process::exit(1);
+      }
+      Ok(effective) => print!("{effective}"),
+    }
+    return Ok(());
+  }
   // Build env logger
   if std::env::var("LOG_LEVEL").is_err() {
     std::env::set_var("LOG_LEVEL", "metrsd=debug");
@@ -27,10 +55,86 @@ async fn main() -> std::io::Result<()> {
     .format_target(false)
     .init();
   sysinfo::set_open_files_limit(0);
-  let event_emitter = EventEmitter::new();
-  spawn_metrics(event_emitter.clone(), cli.tick_interval);
+  let mut event_emitter = EventEmitter::with_history(cli.history);
+  if let Some(data_dir) = &cli.data_dir {
+    match store::SegmentStore::open(data_dir, store::StoreConfig::from(&cli)) {
+      Err(err) => {
+        println!("Unable to open data dir {}: {err}", data_dir.display());
+        std::process::exit(1);
+      }
+      Ok(store) => event_emitter = event_emitter.with_store(store),
+    }
+  }
+  let mut rollups = Vec::new();
+  let retention_share = store::retention_share(&cli);
+  for tier in &cli.rollups {
+    let rollup = rollup::Rollup::new(*tier);
+    let rollup = match &cli.data_dir {
+      None => rollup,
+      Some(data_dir) => match rollup.with_store(data_dir, retention_share) {
+        Err(err) => {
+          println!("Unable to open data dir {}: {err}", data_dir.display());
+          std::process::exit(1);
+        }
+        Ok(rollup) => rollup,
+      },
+    };
+    rollups.push(rollup);
+  }
+  let mut event_emitter =
+    event_emitter.with_rollups(rollup::Rollups::new(rollups));
+  match config::alert_rules(&cli) {
+    Err(err) => {
+      println!("{err}");
+      std::process::exit(1);
+    }
+    Ok(rules) if rules.rules.is_empty() => {}
+    Ok(rules) => {
+      event_emitter = event_emitter.with_alerts(alert::AlertEngine::new(rules))
+    }
+  }
+  let notify_config = notify::NotifyConfig::from(&cli);
+  if !notify_config.is_empty() {
+    event_emitter =
+      event_emitter.with_notifier(notify::Notifier::new(notify_config));
+  }
+  let event_emitter =
+    event_emitter.with_metrics_config(MetricsConfig::from(&cli));
+  let reloader = reload::Reloader::new(
+    event_emitter.clone(),
+    std::env::args_os(),
+    cli.clone(),
+  );
+  if let Err(err) = reloader.clone().spawn_signal_handler() {
+    println!("Unable to handle SIGHUP: {err}");
+    std::process::exit(1);
+  }
+  let auth = match auth::AuthConfig::load(&cli) {
+    Err(err) => {
+      println!("{err}");
+      std::process::exit(1);
+    }
+    Ok(auth) => auth,
+  };
+  match &cli.replay {
+    None => spawn_metrics(event_emitter.clone()),
+    Some(path) => {
+      if let Err(err) = replay::open(path) {
+        println!("{err}");
+        std::process::exit(1);
+      }
+      log::info!("Replaying {}", path.display());
+      replay::spawn_replay(event_emitter.clone(), path.clone());
+    }
+  }
   log::info!("Server starting");
-  let srv = match server::gen_srv(&cli.hosts, event_emitter) {
+  let srv = match server::gen_srv(
+    &cli.hosts,
+    &tls::TlsConfig::from(&cli),
+    auth,
+    event_emitter,
+    reloader,
+  ) {
     Err(err) => {
       println!("{err}");
       std::process::exit(1);
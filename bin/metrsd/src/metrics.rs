@@ -1,60 +1,702 @@
-use std::time::Duration;
+use std::{
+  fs,
+  collections::HashMap,
+  path::{Path, PathBuf},
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use ntex::{rt, time::interval};
-use sysinfo::{System, Networks, Disks};
+use futures::future::{self, Either};
+use ntex::rt;
+use sysinfo::{
+  System, Networks, NetworkData, Disks, Users, RefreshKind, MemoryRefreshKind,
+  CpuRefreshKind, ProcessRefreshKind,
+};
 
-use metrs_stubs::{CpuInfo, DiskInfo, MemoryInfo, NetworkInfo, MetrsdEvent};
+use metrs_stubs::{
+  ComponentInfo, CpuInfo, DiskInfo, HostInfo, MemoryInfo, NetworkInfo,
+  ProcessInfo, MetrsdEvent, EventFilter, EventSection,
+};
 
+use crate::cli::{Cli, ProcessSort};
+use crate::cgroup::CgroupCollector;
+use crate::clock::TickClock;
+use crate::collector::{Collector, Elapsed, LastRefresh};
 use crate::event_emitter::EventEmitter;
 
-async fn sync_metrics(event_emitter: &EventEmitter, tick_interval: u64) {
-  let mut sys = System::new();
-  let interval = interval(Duration::from_secs(tick_interval));
-  loop {
-    sys.refresh_all();
-    let networks = Networks::new_with_refreshed_list()
-      .into_iter()
-      .map(|(name, net)| NetworkInfo {
-        name: name.clone(),
-        mac_addr: net.mac_address().to_string(),
-        received: net.received(),
-        transmitted: net.transmitted(),
-        packets_received: net.packets_received(),
-        packets_transmitted: net.packets_transmitted(),
-        error_received: net.errors_on_received(),
-        error_transmitted: net.errors_on_transmitted(),
+/// Options used to collect the metrics
+#[derive(Debug, Clone)]
+pub struct MetricsConfig {
+  /// Interval in seconds between two metrics publications
+  pub tick_interval: u64,
+  /// Smallest interval in seconds subscribers can request
+  pub min_tick_interval: u64,
+  /// Number of processes to include in each event, 0 to disable
+  pub top_processes: usize,
+  /// Resource used to select the top processes
+  pub top_processes_by: ProcessSort,
+  /// Path where the sysfs of the host is mounted
+  pub sysfs_root: PathBuf,
+  /// Path of a cgroup v2 hierarchy to collect, disabled when not set
+  pub cgroup_root: Option<PathBuf>,
+  /// Sections, disks and interfaces collected, everything when empty
+  pub filter: EventFilter,
+}
+
+impl Default for MetricsConfig {
+  fn default() -> Self {
+    Self {
+      tick_interval: 10,
+      min_tick_interval: 1,
+      top_processes: 0,
+      top_processes_by: ProcessSort::default(),
+      sysfs_root: PathBuf::from("/sys"),
+      cgroup_root: None,
+      filter: EventFilter::default(),
+    }
+  }
+}
+
+impl From<&Cli> for MetricsConfig {
+  fn from(cli: &Cli) -> Self {
+    Self {
+      tick_interval: cli.tick_interval,
+      min_tick_interval: cli.min_tick_interval,
+      top_processes: cli.top_processes,
+      top_processes_by: cli.top_processes_by,
+      sysfs_root: cli.sysfs_root.clone(),
+      cgroup_root: cli.cgroup_root.clone(),
+      filter: EventFilter {
+        include: cli.collectors.clone(),
+        disks: cli.disks.clone(),
+        interfaces: cli.interfaces.clone(),
+        interval: None,
+      },
+    }
+  }
+}
+
+impl MetricsConfig {
+  /// Interval between two collections, the one requested by the subscribers
+  /// when it's faster than the tick interval without going below the minimum
+  pub fn collect_interval(&self, requested: Option<Duration>) -> Duration {
+    let default = Duration::from_secs(self.tick_interval);
+    let floor = Duration::from_secs(self.min_tick_interval).min(default);
+    requested
+      .map_or(default, |requested| requested.min(default))
+      .max(floor)
+  }
+}
+
+/// Paths where the machine id can be found
+const MACHINE_ID_PATHS: [&str; 2] =
+  ["/etc/machine-id", "/var/lib/dbus/machine-id"];
+
+/// Read the machine id of the host, empty if it cannot be found
+fn machine_id() -> String {
+  MACHINE_ID_PATHS
+    .iter()
+    .find_map(|path| std::fs::read_to_string(path).ok())
+    .map(|id| id.trim().to_owned())
+    .unwrap_or_default()
+}
+
+/// Current unix timestamp in milliseconds
+pub fn now_millis() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_millis() as u64)
+    .unwrap_or_default()
+}
+
+/// Build the information of the host, the static part is computed once
+/// and the load average and uptime are refreshed on every call
+fn host_info(host: &HostInfo) -> HostInfo {
+  HostInfo {
+    load_average: System::load_average().into(),
+    uptime: System::uptime(),
+    boot_time: System::boot_time(),
+    ..host.clone()
+  }
+}
+
+/// Read a sysfs attribute without its trailing new line
+fn read_attribute(path: &Path) -> Option<String> {
+  fs::read_to_string(path)
+    .ok()
+    .map(|value| value.trim().to_owned())
+}
+
+/// Read the temperature sensors exposed by hwmon under the given sysfs root.
+/// `highest` keeps the highest temperature seen for each sensor across ticks,
+/// keyed by its hwmon directory as chips can expose sensors with the same
+/// label.
+fn collect_components(
+  sysfs_root: &Path,
+  highest: &mut HashMap<String, f32>,
+) -> Vec<ComponentInfo> {
+  let Ok(entries) = fs::read_dir(sysfs_root.join("class/hwmon")) else {
+    return Vec::new();
+  };
+  let mut hwmons = entries
+    .filter_map(Result::ok)
+    .map(|entry| entry.path())
+    .collect::<Vec<_>>();
+  hwmons.sort();
+  let mut components = Vec::new();
+  for hwmon in hwmons {
+    let name = read_attribute(&hwmon.join("name")).unwrap_or_default();
+    let chip = hwmon
+      .file_name()
+      .map(|chip| chip.to_string_lossy().into_owned())
+      .unwrap_or_default();
+    let Ok(files) = fs::read_dir(&hwmon) else {
+      continue;
+    };
+    let mut sensors = files
+      .filter_map(Result::ok)
+      .filter_map(|file| {
+        file
+          .file_name()
+          .to_str()?
+          .strip_prefix("temp")?
+          .strip_suffix("_input")?
+          .parse::<u32>()
+          .ok()
       })
-      .collect::<Vec<NetworkInfo>>();
-    let memory = MemoryInfo {
-      total: sys.total_memory(),
-      used: sys.used_memory(),
-      free: sys.free_memory(),
-      swap_total: sys.total_swap(),
-      swap_used: sys.used_swap(),
-      swap_free: sys.free_swap(),
-    };
-    let disks = Disks::new_with_refreshed_list()
-      .iter()
-      .map(DiskInfo::from)
       .collect::<Vec<_>>();
-    let cpus = sys.cpus().iter().map(CpuInfo::from).collect::<Vec<_>>();
+    sensors.sort_unstable();
+    for sensor in sensors {
+      let read_celsius = |attribute: &str| {
+        read_attribute(&hwmon.join(format!("temp{sensor}_{attribute}")))
+          .and_then(|value| value.parse::<f32>().ok())
+          .map(|millidegree| millidegree / 1000.0)
+      };
+      let label =
+        match read_attribute(&hwmon.join(format!("temp{sensor}_label"))) {
+          Some(label) => format!("{name} {label}"),
+          None => format!("{name} temp{sensor}"),
+        }
+        .trim()
+        .to_owned();
+      let temperature = read_celsius("input");
+      let key = format!("{chip}/{label}");
+      let max = [
+        temperature,
+        read_celsius("highest"),
+        highest.get(&key).copied(),
+      ]
+      .into_iter()
+      .flatten()
+      .reduce(f32::max);
+      if let Some(max) = max {
+        highest.insert(key, max);
+      }
+      components.push(ComponentInfo {
+        label,
+        temperature,
+        max,
+        critical: read_celsius("crit"),
+      });
+    }
+  }
+  components
+}
+
+/// Amount per second of a delta measured over `elapsed` seconds
+fn per_second(delta: u64, elapsed: f64) -> f64 {
+  if elapsed <= 0.0 {
+    return 0.0;
+  }
+  delta as f64 / elapsed
+}
+
+/// Build the network information of an interface with its rates
+fn network_info(name: &str, net: &NetworkData, elapsed: f64) -> NetworkInfo {
+  NetworkInfo {
+    name: name.to_owned(),
+    mac_addr: net.mac_address().to_string(),
+    received: net.received(),
+    transmitted: net.transmitted(),
+    packets_received: net.packets_received(),
+    packets_transmitted: net.packets_transmitted(),
+    error_received: net.errors_on_received(),
+    error_transmitted: net.errors_on_transmitted(),
+    total_received: net.total_received(),
+    total_transmitted: net.total_transmitted(),
+    total_packets_received: net.total_packets_received(),
+    total_packets_transmitted: net.total_packets_transmitted(),
+    total_error_received: net.total_errors_on_received(),
+    total_error_transmitted: net.total_errors_on_transmitted(),
+    received_rate: per_second(net.received(), elapsed),
+    transmitted_rate: per_second(net.transmitted(), elapsed),
+    packets_received_rate: per_second(net.packets_received(), elapsed),
+    packets_transmitted_rate: per_second(net.packets_transmitted(), elapsed),
+    error_received_rate: per_second(net.errors_on_received(), elapsed),
+    error_transmitted_rate: per_second(net.errors_on_transmitted(), elapsed),
+  }
+}
+
+/// Keep the `count` processes using the most of the given resource
+fn top_processes(
+  mut processes: Vec<ProcessInfo>,
+  by: ProcessSort,
+  count: usize,
+) -> Vec<ProcessInfo> {
+  match by {
+    ProcessSort::Cpu => {
+      processes.sort_by(|a, b| b.cpu_usage.total_cmp(&a.cpu_usage))
+    }
+    ProcessSort::Memory => {
+      processes.sort_by_key(|process| std::cmp::Reverse(process.memory))
+    }
+  }
+  processes.truncate(count);
+  processes
+}
+
+/// Collect the top processes of the system with their user name
+fn collect_processes(
+  sys: &System,
+  users: &mut Users,
+  config: &MetricsConfig,
+) -> Vec<ProcessInfo> {
+  if config.top_processes == 0 {
+    return Vec::new();
+  }
+  users.refresh();
+  let processes = sys
+    .processes()
+    .values()
+    // Threads are listed as processes on linux
+    .filter(|process| process.thread_kind().is_none())
+    .map(|process| {
+      let mut info = ProcessInfo::from(process);
+      if let Some(user) =
+        process.user_id().and_then(|uid| users.get_user_by_id(uid))
+      {
+        info.user = user.name().to_owned();
+      }
+      info
+    })
+    .collect::<Vec<_>>();
+  top_processes(processes, config.top_processes_by, config.top_processes)
+}
+
+/// Collector reading the metrics of the host with sysinfo
+pub struct SysinfoCollector {
+  sys: System,
+  users: Users,
+  // Networks and disks are kept across ticks so sysinfo can compute the
+  // amount of data exchanged since the last refresh
+  networks: Networks,
+  disks: Disks,
+  highest_temperatures: HashMap<String, f32>,
+  cgroup_root: Option<PathBuf>,
+  cgroup_collector: Option<CgroupCollector>,
+  hostname: String,
+  machine_id: String,
+  host: HostInfo,
+}
+
+impl SysinfoCollector {
+  pub fn new() -> Self {
+    Self {
+      sys: System::new(),
+      users: Users::new(),
+      networks: Networks::new_with_refreshed_list(),
+      disks: Disks::new_with_refreshed_list(),
+      highest_temperatures: HashMap::new(),
+      cgroup_root: None,
+      cgroup_collector: None,
+      hostname: System::host_name().unwrap_or_default(),
+      machine_id: machine_id(),
+      host: HostInfo {
+        kernel_version: System::kernel_version().unwrap_or_default(),
+        os_name: System::name().unwrap_or_default(),
+        os_version: System::os_version().unwrap_or_default(),
+        arch: System::cpu_arch(),
+        physical_core_count: System::physical_core_count(),
+        ..Default::default()
+      },
+    }
+  }
+}
+
+impl Collector for SysinfoCollector {
+  /// Only the sections enabled by the filter of the configuration are
+  /// refreshed, so disabling the processes or the disks saves their work
+  fn collect(
+    &mut self,
+    config: &MetricsConfig,
+    elapsed: &Elapsed,
+  ) -> MetrsdEvent {
+    if config.cgroup_root != self.cgroup_root {
+      self.cgroup_root = config.cgroup_root.clone();
+      self.cgroup_collector =
+        self.cgroup_root.as_ref().map(CgroupCollector::new);
+    }
+    let includes = |section| config.filter.includes(section);
+    let mut refresh = RefreshKind::nothing();
+    if includes(EventSection::Memory) {
+      refresh = refresh.with_memory(MemoryRefreshKind::everything());
+    }
+    if includes(EventSection::Cpus) {
+      refresh = refresh.with_cpu(CpuRefreshKind::everything());
+    }
+    if includes(EventSection::Processes) && config.top_processes > 0 {
+      refresh = refresh.with_processes(ProcessRefreshKind::everything());
+    }
+    self.sys.refresh_specifics(refresh);
+    let mut ev = MetrsdEvent {
+      hostname: self.hostname.clone(),
+      machine_id: self.machine_id.clone(),
+      ..Default::default()
+    };
+    if includes(EventSection::Host) {
+      ev.host = host_info(&self.host);
+    }
+    if includes(EventSection::Memory) {
+      ev.memory = MemoryInfo {
+        total: self.sys.total_memory(),
+        used: self.sys.used_memory(),
+        free: self.sys.free_memory(),
+        swap_total: self.sys.total_swap(),
+        swap_used: self.sys.used_swap(),
+        swap_free: self.sys.free_swap(),
+      };
+    }
+    if includes(EventSection::Cpus) {
+      ev.cpus = self.sys.cpus().iter().map(CpuInfo::from).collect();
+    }
+    if includes(EventSection::Disks) {
+      self.disks.refresh(true);
+      ev.disks = self
+        .disks
+        .iter()
+        .map(|disk| {
+          let mut info = DiskInfo::from(disk);
+          info.read_rate = per_second(info.read_bytes, elapsed.disks);
+          info.written_rate = per_second(info.written_bytes, elapsed.disks);
+          info
+        })
+        .collect();
+    }
+    if includes(EventSection::Networks) {
+      self.networks.refresh(true);
+      ev.networks = self
+        .networks
+        .iter()
+        .map(|(name, net)| network_info(name, net, elapsed.networks))
+        .collect::<Vec<NetworkInfo>>();
+    }
+    if includes(EventSection::Components) {
+      ev.components =
+        collect_components(&config.sysfs_root, &mut self.highest_temperatures);
+    }
+    if includes(EventSection::Cgroups) {
+      ev.cgroups = self
+        .cgroup_collector
+        .as_mut()
+        .map(|collector| collector.collect(elapsed.cgroups))
+        .unwrap_or_default();
+    }
+    if includes(EventSection::Processes) {
+      ev.processes = collect_processes(&self.sys, &mut self.users, config);
+    }
+    ev
+  }
+}
+
+/// Publish the metrics of the collector on every tick of the clock
+async fn sync_metrics(
+  event_emitter: &EventEmitter,
+  mut collector: impl Collector,
+  clock: TickClock,
+) {
+  let mut last_refresh = LastRefresh::new(clock.now());
+  let mut config = MetricsConfig::default();
+  let mut sequence = 0;
+  loop {
+    let started = clock.now();
+    // The options are read on every tick so a reload applies right away
+    match event_emitter.metrics_config() {
+      Err(err) => log::error!("{err}"),
+      Ok(reloaded) => config = reloaded,
+    }
+    let elapsed = last_refresh.elapsed(started);
+    sequence += 1;
     let event = MetrsdEvent {
-      cpus,
-      disks,
-      networks,
-      memory,
+      timestamp: started,
+      sequence,
+      version: env!("CARGO_PKG_VERSION").to_owned(),
+      rollup: None,
+      ..collector.collect(&config, &elapsed)
+    };
+    last_refresh.refreshed(started, &config.filter);
+    let event = if config.filter.is_empty() {
+      event
+    } else {
+      config.filter.apply(&event)
     };
     if let Err(err) = event_emitter.emit(event).await {
       log::error!("{err}");
     }
-    // Sleeping for minimum 500 ms to let time for the system to run for long
-    // enough to have useful information.
-    interval.tick().await;
+    // Wait for the next collection, the wait is shortened when a subscriber
+    // requesting a faster rate joins in the meantime.
+    loop {
+      let requested = event_emitter.min_interval().unwrap_or_else(|err| {
+        log::error!("{err}");
+        None
+      });
+      let collect_interval = config.collect_interval(requested);
+      let elapsed = Duration::from_millis(clock.now().saturating_sub(started));
+      if elapsed >= collect_interval {
+        break;
+      }
+      let wait = Box::pin(clock.sleep(collect_interval - elapsed));
+      let interval_changed = Box::pin(event_emitter.interval_changed());
+      if let Either::Right((Err(err), _)) =
+        future::select(wait, interval_changed).await
+      {
+        log::error!("{err}");
+      }
+    }
   }
 }
 
-pub fn spawn_metrics(event_emitter: EventEmitter, tick_interval: u64) {
+/// Collect the metrics of the host on a dedicated thread
+pub fn spawn_metrics(event_emitter: EventEmitter) {
+  spawn_collector(event_emitter, SysinfoCollector::new(), TickClock::System);
+}
+
+/// Publish the metrics of a collector paced by the given clock on a
+/// dedicated thread
+pub fn spawn_collector(
+  event_emitter: EventEmitter,
+  collector: impl Collector + Send + 'static,
+  clock: TickClock,
+) {
   rt::Arbiter::new().handle().spawn(async move {
-    sync_metrics(&event_emitter, tick_interval).await;
+    sync_metrics(&event_emitter, collector, clock).await;
   });
 }
+
+#[cfg(test)]
+mod tests {
+  use crate::clock::ManualClock;
+  use crate::collector::ScriptedCollector;
+
+  use super::*;
+
+  fn process(pid: u32, cpu_usage: f32, memory: u64) -> ProcessInfo {
+    ProcessInfo {
+      pid,
+      cpu_usage,
+      memory,
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn test_host_info() {
+    let host = HostInfo {
+      arch: "x86_64".into(),
+      ..Default::default()
+    };
+    let info = host_info(&host);
+    assert_eq!(info.arch, "x86_64");
+    assert!(info.boot_time > 0);
+  }
+
+  fn write_attribute(dir: &Path, name: &str, value: &str) {
+    fs::create_dir_all(dir).unwrap();
+    fs::write(dir.join(name), format!("{value}\n")).unwrap();
+  }
+
+  #[test]
+  fn test_collect_components() {
+    let root = tempfile::tempdir().unwrap();
+    let hwmon0 = root.path().join("class/hwmon/hwmon0");
+    write_attribute(&hwmon0, "name", "coretemp");
+    write_attribute(&hwmon0, "temp1_input", "45000");
+    write_attribute(&hwmon0, "temp1_label", "Package id 0");
+    write_attribute(&hwmon0, "temp1_crit", "100000");
+    write_attribute(&hwmon0, "temp2_input", "50500");
+    let hwmon1 = root.path().join("class/hwmon/hwmon1");
+    write_attribute(&hwmon1, "name", "AC");
+    let mut highest = HashMap::new();
+    let components = collect_components(root.path(), &mut highest);
+    assert_eq!(components.len(), 2);
+    assert_eq!(components[0].label, "coretemp Package id 0");
+    assert_eq!(components[0].temperature, Some(45.0));
+    assert_eq!(components[0].max, Some(45.0));
+    assert_eq!(components[0].critical, Some(100.0));
+    assert_eq!(components[1].label, "coretemp temp2");
+    assert_eq!(components[1].temperature, Some(50.5));
+    assert_eq!(components[1].critical, None);
+    // The highest temperature is kept when the sensor cools down
+    write_attribute(&hwmon0, "temp1_input", "40000");
+    let components = collect_components(root.path(), &mut highest);
+    assert_eq!(components[0].temperature, Some(40.0));
+    assert_eq!(components[0].max, Some(45.0));
+  }
+
+  #[test]
+  fn test_collect_components_same_label() {
+    let root = tempfile::tempdir().unwrap();
+    for (chip, input) in [("hwmon0", "70000"), ("hwmon1", "30000")] {
+      let hwmon = root.path().join("class/hwmon").join(chip);
+      write_attribute(&hwmon, "name", "nvme");
+      write_attribute(&hwmon, "temp1_input", input);
+      write_attribute(&hwmon, "temp1_label", "Composite");
+    }
+    let mut highest = HashMap::new();
+    let components = collect_components(root.path(), &mut highest);
+    let maxes = components.iter().map(|c| c.max).collect::<Vec<_>>();
+    assert_eq!(maxes, vec![Some(70.0), Some(30.0)]);
+    let components = collect_components(root.path(), &mut highest);
+    let maxes = components.iter().map(|c| c.max).collect::<Vec<_>>();
+    assert_eq!(maxes, vec![Some(70.0), Some(30.0)]);
+  }
+
+  #[test]
+  fn test_collect_components_without_sensors() {
+    let root = tempfile::tempdir().unwrap();
+    let mut highest = HashMap::new();
+    assert!(collect_components(root.path(), &mut highest).is_empty());
+  }
+
+  #[test]
+  fn test_collect_interval() {
+    let config = MetricsConfig::default();
+    let secs = |secs| Some(Duration::from_secs(secs));
+    assert_eq!(config.collect_interval(None), Duration::from_secs(10));
+    assert_eq!(config.collect_interval(secs(2)), Duration::from_secs(2));
+    assert_eq!(config.collect_interval(secs(60)), Duration::from_secs(10));
+    assert_eq!(
+      config.collect_interval(Some(Duration::from_millis(10))),
+      Duration::from_secs(1)
+    );
+    let config = MetricsConfig {
+      tick_interval: 5,
+      min_tick_interval: 30,
+      ..Default::default()
+    };
+    assert_eq!(config.collect_interval(secs(1)), Duration::from_secs(5));
+  }
+
+  #[test]
+  fn test_per_second() {
+    assert_eq!(per_second(100, 10.0), 10.0);
+    assert_eq!(per_second(5, 0.5), 10.0);
+    assert_eq!(per_second(100, 0.0), 0.0);
+  }
+
+  #[test]
+  fn test_top_processes() {
+    let processes = vec![
+      process(1, 5.0, 300),
+      process(2, 50.0, 100),
+      process(3, 20.0, 200),
+    ];
+    let top = top_processes(processes.clone(), ProcessSort::Cpu, 2);
+    let pids = top.iter().map(|p| p.pid).collect::<Vec<_>>();
+    assert_eq!(pids, vec![2, 3]);
+    let top = top_processes(processes.clone(), ProcessSort::Memory, 2);
+    let pids = top.iter().map(|p| p.pid).collect::<Vec<_>>();
+    assert_eq!(pids, vec![1, 3]);
+    let top = top_processes(processes, ProcessSort::Memory, 10);
+    assert_eq!(top.len(), 3);
+  }
+
+  #[test]
+  fn test_collect_processes() {
+    let mut sys = System::new();
+    sys.refresh_all();
+    let mut users = Users::new();
+    let config = MetricsConfig::default();
+    assert!(collect_processes(&sys, &mut users, &config).is_empty());
+    let config = MetricsConfig {
+      top_processes: 3,
+      top_processes_by: ProcessSort::Memory,
+      ..Default::default()
+    };
+    let processes = collect_processes(&sys, &mut users, &config);
+    assert!(!processes.is_empty());
+    assert!(processes.len() <= 3);
+    assert!(processes.iter().all(|p| !p.name.is_empty()));
+  }
+
+  #[test]
+  fn test_collect_enabled_sections() {
+    let mut collector = SysinfoCollector::new();
+    let config = MetricsConfig {
+      top_processes: 3,
+      filter: EventFilter {
+        include: vec![EventSection::Memory],
+        ..Default::default()
+      },
+      ..Default::default()
+    };
+    let ev = collector.collect(&config, &Elapsed::all(1.0));
+    assert!(ev.memory.total > 0);
+    assert!(ev.cpus.is_empty());
+    assert!(ev.disks.is_empty());
+    assert!(ev.networks.is_empty());
+    assert!(ev.processes.is_empty());
+    // Disabled sections are not even refreshed
+    assert!(collector.sys.cpus().is_empty());
+    assert!(collector.sys.processes().is_empty());
+  }
+
+  /// Wait for the collector to run the given number of collections
+  async fn wait_collections(collector: &ScriptedCollector, count: usize) {
+    while collector.elapsed().len() < count {
+      ntex::time::sleep(Duration::from_millis(10)).await;
+    }
+  }
+
+  #[ntex::test]
+  async fn test_collect_reenabled_section() {
+    let event_emitter = EventEmitter::default();
+    let config = MetricsConfig {
+      tick_interval: 1,
+      ..Default::default()
+    };
+    event_emitter.set_metrics_config(config.clone()).unwrap();
+    let clock = ManualClock::new(now_millis());
+    let collector = ScriptedCollector::new([MetrsdEvent::default()]);
+    spawn_collector(
+      event_emitter.clone(),
+      collector.clone(),
+      TickClock::Manual(clock.clone()),
+    );
+    wait_collections(&collector, 1).await;
+    // A reload disables the disks for two collections then enables them
+    event_emitter
+      .set_metrics_config(MetricsConfig {
+        filter: EventFilter {
+          include: vec![EventSection::Networks, EventSection::Cgroups],
+          ..Default::default()
+        },
+        ..config.clone()
+      })
+      .unwrap();
+    for count in 2..=3 {
+      clock.advance(Duration::from_secs(1));
+      wait_collections(&collector, count).await;
+    }
+    event_emitter.set_metrics_config(config).unwrap();
+    clock.advance(Duration::from_secs(1));
+    wait_collections(&collector, 4).await;
+    // The disk rates cover the whole period since their last collection
+    assert_eq!(
+      collector.elapsed()[3],
+      Elapsed {
+        disks: 3.0,
+        networks: 1.0,
+        cgroups: 1.0,
+      }
+    );
+  }
+}
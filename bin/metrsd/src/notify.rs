@@ -0,0 +1,447 @@
+use std::{
+  process::Command,
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+
+use ntex::{rt, web, client::Client, time::Millis};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+
+use metrs_stubs::{AlertEvent, AlertState};
+
+use crate::cli::Cli;
+
+/// Time to wait for a webhook to respond
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time to wait for the exec hook to exit before killing it
+const EXEC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Delay between two checks of the exit of the exec hook
+const EXEC_POLL: Duration = Duration::from_millis(50);
+
+/// Longest delay between two retries of a webhook
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Client posting the alerts to the webhooks
+async fn webhook_client() -> Result<Client, String> {
+  Client::builder()
+    .response_timeout(Millis::from(WEBHOOK_TIMEOUT))
+    .build(ntex::SharedCfg::default())
+    .await
+    .map_err(|err| format!("Unable to create client: {err}"))
+}
+
+/// Where the alerts are sent when they fire or resolve
+#[derive(Debug, Clone, PartialEq)]
+pub struct NotifyConfig {
+  /// Urls the alerts are posted to as json
+  pub webhooks: Vec<String>,
+  /// Shell command run with the details of the alert in its environment
+  pub exec: Option<String>,
+  /// Number of times a failed webhook is retried
+  pub retries: u32,
+  /// Delay before the first retry, doubled on every following one up to
+  /// a minute
+  pub backoff: Duration,
+}
+
+impl Default for NotifyConfig {
+  fn default() -> Self {
+    Self {
+      webhooks: Vec::new(),
+      exec: None,
+      retries: 3,
+      backoff: Duration::from_secs(1),
+    }
+  }
+}
+
+impl From<&Cli> for NotifyConfig {
+  fn from(cli: &Cli) -> Self {
+    Self {
+      webhooks: cli.alert_webhooks.clone(),
+      exec: cli.alert_exec.clone(),
+      retries: cli.alert_webhook_retries,
+      backoff: cli.alert_webhook_backoff,
+    }
+  }
+}
+
+impl NotifyConfig {
+  /// Whether any sink is configured
+  pub fn is_empty(&self) -> bool {
+    self.webhooks.is_empty() && self.exec.is_none()
+  }
+}
+
+/// Environment variables describing an alert for the exec hook
+fn alert_env(alert: &AlertEvent) -> Vec<(&'static str, String)> {
+  let state = match alert.state {
+    AlertState::Firing => "firing",
+    AlertState::Resolved => "resolved",
+  };
+  vec![
+    ("METRS_ALERT_RULE", alert.rule.clone()),
+    ("METRS_ALERT_INSTANCE", alert.instance.clone()),
+    ("METRS_ALERT_STATE", state.into()),
+    ("METRS_ALERT_SEVERITY", alert.severity.clone()),
+    ("METRS_ALERT_SUMMARY", alert.summary.clone()),
+    ("METRS_ALERT_VALUE", alert.value.to_string()),
+    ("METRS_ALERT_THRESHOLD", alert.threshold.to_string()),
+    ("METRS_ALERT_STARTED_AT", alert.started_at.to_string()),
+    ("METRS_ALERT_TIMESTAMP", alert.timestamp.to_string()),
+    ("METRS_ALERT_HOSTNAME", alert.hostname.clone()),
+  ]
+}
+
+/// Send the alerts to the configured webhooks and exec hook
+#[derive(Debug, Clone)]
+pub struct Notifier {
+  config: NotifyConfig,
+  /// Alerts queued for the webhooks
+  queue: UnboundedSender<AlertEvent>,
+  /// End of the queue read by the delivery task, taken when the task is
+  /// spawned on the first alert
+  pending: Arc<Mutex<Option<UnboundedReceiver<AlertEvent>>>>,
+}
+
+impl Default for Notifier {
+  fn default() -> Self {
+    Self::new(NotifyConfig::default())
+  }
+}
+
+impl Notifier {
+  pub fn new(config: NotifyConfig) -> Self {
+    let (queue, pending) = unbounded_channel();
+    Self {
+      config,
+      queue,
+      pending: Arc::new(Mutex::new(Some(pending))),
+    }
+  }
+
+  /// Post the queued alerts to the webhooks with a single client, until
+  /// the notifier is dropped on a reload. Every webhook has its own queue so
+  /// it receives the alerts in order while a webhook retrying does not
+  /// delay the others.
+  async fn deliver(
+    config: NotifyConfig,
+    mut queue: UnboundedReceiver<AlertEvent>,
+  ) {
+    let client = match webhook_client().await {
+      Ok(client) => client,
+      Err(err) => {
+        log::error!("Unable to send alerts to webhooks: {err}");
+        return;
+      }
+    };
+    let webhooks = config
+      .webhooks
+      .iter()
+      .map(|url| {
+        let (tx, rx) = unbounded_channel();
+        let webhook =
+          Self::deliver_to(client.clone(), config.clone(), url.clone(), rx);
+        rt::spawn(webhook);
+        tx
+      })
+      .collect::<Vec<_>>();
+    while let Some(alert) = queue.recv().await {
+      for webhook in &webhooks {
+        let _ = webhook.send(alert.clone());
+      }
+    }
+  }
+
+  /// Post the queued alerts to a webhook one after the other, an alert is
+  /// retried before the next one is sent
+  async fn deliver_to(
+    client: Client,
+    config: NotifyConfig,
+    url: String,
+    mut queue: UnboundedReceiver<AlertEvent>,
+  ) {
+    while let Some(alert) = queue.recv().await {
+      if let Err(err) = Self::post(&client, &config, &url, &alert).await {
+        log::error!("Unable to send alert {} to {url}: {err}", alert.rule);
+      }
+    }
+  }
+
+  /// Spawn the delivery of the alerts to every sink, a sink failing does
+  /// not delay the others nor the events
+  pub fn notify(&self, alerts: &[AlertEvent]) {
+    if !self.config.webhooks.is_empty() {
+      let pending = self.pending.lock().ok().and_then(|mut rx| rx.take());
+      if let Some(pending) = pending {
+        rt::spawn(Self::deliver(self.config.clone(), pending));
+      }
+    }
+    for alert in alerts {
+      if !self.config.webhooks.is_empty() {
+        let _ = self.queue.send(alert.clone());
+      }
+      if let Some(command) = &self.config.exec {
+        let command = command.clone();
+        let alert = alert.clone();
+        rt::spawn(async move {
+          if let Err(err) = Self::exec(&command, &alert, EXEC_TIMEOUT).await {
+            log::error!("Unable to run alert hook for {}: {err}", alert.rule);
+          }
+        });
+      }
+    }
+  }
+
+  /// Post an alert to a webhook, retrying with an exponential backoff capped
+  /// at [`MAX_BACKOFF`] until it responds with a success status
+  pub async fn post(
+    client: &Client,
+    config: &NotifyConfig,
+    url: &str,
+    alert: &AlertEvent,
+  ) -> Result<(), String> {
+    let mut backoff = config.backoff;
+    let mut attempt = 0;
+    loop {
+      let err = match client.post(url).send_json(alert).await {
+        Ok(res) if res.status().is_success() => return Ok(()),
+        Ok(res) => format!("Unexpected status {}", res.status()),
+        Err(err) => err.to_string(),
+      };
+      if attempt >= config.retries {
+        return Err(err);
+      }
+      attempt += 1;
+      log::warn!(
+        "Alert {} to {url} failed: {err}, retrying in {}ms",
+        alert.rule,
+        backoff.as_millis()
+      );
+      ntex::time::sleep(Millis::from(backoff)).await;
+      backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+  }
+
+  /// Run the exec hook with the details of the alert in its environment,
+  /// killing it once it runs longer than the timeout
+  pub async fn exec(
+    command: &str,
+    alert: &AlertEvent,
+    timeout: Duration,
+  ) -> Result<(), String> {
+    let json = serde_json::to_string(alert)
+      .map_err(|err| format!("Unable to serialize alert: {err}"))?;
+    let mut cmd = Command::new("sh");
+    cmd
+      .arg("-c")
+      .arg(command)
+      .envs(alert_env(alert))
+      .env("METRS_ALERT_JSON", json);
+    let status = web::block(move || {
+      let mut child = cmd.spawn()?;
+      let deadline = Instant::now() + timeout;
+      loop {
+        if let Some(status) = child.try_wait()? {
+          return Ok(status);
+        }
+        if Instant::now() >= deadline {
+          child.kill()?;
+          child.wait()?;
+          return Err(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            format!("Command timed out after {}ms", timeout.as_millis()),
+          ));
+        }
+        std::thread::sleep(EXEC_POLL);
+      }
+    })
+    .await
+    .map_err(|err| err.to_string())?;
+    if !status.success() {
+      return Err(format!("Command exited with {status}"));
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+  };
+
+  use super::*;
+
+  fn alert() -> AlertEvent {
+    AlertEvent {
+      rule: "memory_high".into(),
+      instance: String::new(),
+      state: AlertState::Firing,
+      severity: "warning".into(),
+      summary: "memory.used_percent > 90".into(),
+      value: 95.0,
+      threshold: 90.0,
+      started_at: 1000,
+      timestamp: 1000,
+      hostname: "localhost".into(),
+    }
+  }
+
+  #[ntex::test]
+  async fn test_webhook_retry() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    // Stand-in for a webhook failing twice before accepting the alert
+    let srv = web::test::server({
+      let calls = calls.clone();
+      async move || {
+        let calls = calls.clone();
+        web::App::new().route(
+          "/hook",
+          web::post().to(move |alert: web::types::Json<AlertEvent>| {
+            let calls = calls.clone();
+            async move {
+              assert_eq!(alert.rule, "memory_high");
+              if calls.fetch_add(1, Ordering::SeqCst) < 2 {
+                return web::HttpResponse::ServiceUnavailable().finish();
+              }
+              web::HttpResponse::Ok().finish()
+            }
+          }),
+        )
+      }
+    })
+    .await;
+    let url = srv.url("/hook");
+    let config = NotifyConfig {
+      webhooks: vec![url.clone()],
+      backoff: Duration::from_millis(10),
+      ..Default::default()
+    };
+    let client = webhook_client().await.unwrap();
+    Notifier::post(&client, &config, &url, &alert())
+      .await
+      .unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+    let config = NotifyConfig {
+      retries: 1,
+      ..config
+    };
+    calls.store(0, Ordering::SeqCst);
+    let err = Notifier::post(&client, &config, &url, &alert())
+      .await
+      .unwrap_err();
+    assert!(err.contains("503"), "{err}");
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+  }
+
+  #[ntex::test]
+  async fn test_notify() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let srv = web::test::server({
+      let calls = calls.clone();
+      async move || {
+        let calls = calls.clone();
+        web::App::new().route(
+          "/hook",
+          web::post().to(move |_: web::types::Json<AlertEvent>| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            async { web::HttpResponse::Ok().finish() }
+          }),
+        )
+      }
+    })
+    .await;
+    let notifier = Notifier::new(NotifyConfig {
+      webhooks: vec![srv.url("/hook")],
+      ..Default::default()
+    });
+    // Both batches go through the same delivery task and client
+    notifier.notify(&[alert(), alert()]);
+    notifier.notify(&[alert()]);
+    for _ in 0..100 {
+      if calls.load(Ordering::SeqCst) == 3 {
+        break;
+      }
+      ntex::time::sleep(Millis(10)).await;
+    }
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+    assert!(notifier.pending.lock().unwrap().is_none());
+  }
+
+  #[ntex::test]
+  async fn test_notify_order() {
+    let states = Arc::new(Mutex::new(Vec::new()));
+    // Stand-in for a webhook failing the first time it is called
+    let srv = web::test::server({
+      let states = states.clone();
+      async move || {
+        let states = states.clone();
+        web::App::new().route(
+          "/hook",
+          web::post().to(move |alert: web::types::Json<AlertEvent>| {
+            let mut states = states.lock().unwrap();
+            states.push(alert.state);
+            let res = match states.len() {
+              1 => web::HttpResponse::ServiceUnavailable().finish(),
+              _ => web::HttpResponse::Ok().finish(),
+            };
+            async { res }
+          }),
+        )
+      }
+    })
+    .await;
+    let notifier = Notifier::new(NotifyConfig {
+      webhooks: vec![srv.url("/hook")],
+      backoff: Duration::from_millis(100),
+      ..Default::default()
+    });
+    let resolved = AlertEvent {
+      state: AlertState::Resolved,
+      ..alert()
+    };
+    notifier.notify(&[alert()]);
+    notifier.notify(&[resolved]);
+    for _ in 0..100 {
+      if states.lock().unwrap().len() == 3 {
+        break;
+      }
+      ntex::time::sleep(Millis(10)).await;
+    }
+    // The resolved alert waits for the firing one to be retried
+    assert_eq!(
+      *states.lock().unwrap(),
+      vec![AlertState::Firing, AlertState::Firing, AlertState::Resolved]
+    );
+  }
+
+  #[ntex::test]
+  async fn test_exec() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("alert");
+    let command = format!(
+      "echo \"$METRS_ALERT_RULE $METRS_ALERT_STATE $METRS_ALERT_VALUE\" > {}",
+      path.display()
+    );
+    Notifier::exec(&command, &alert(), EXEC_TIMEOUT)
+      .await
+      .unwrap();
+    let content = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(content.trim(), "memory_high firing 95");
+    assert!(Notifier::exec("exit 3", &alert(), EXEC_TIMEOUT)
+      .await
+      .is_err());
+    // A hanging hook is killed instead of holding a blocking thread
+    let started = Instant::now();
+    let timeout = Duration::from_millis(200);
+    let err = Notifier::exec("sleep 10", &alert(), timeout)
+      .await
+      .unwrap_err();
+    assert!(err.contains("timed out"), "{err}");
+    assert!(started.elapsed() < Duration::from_secs(5));
+  }
+}
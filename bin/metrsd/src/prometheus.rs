@@ -0,0 +1,454 @@
+use std::fmt::Write;
+
+use metrs_stubs::{MetrsdEvent, NetworkInfo};
+
+/// Content type of the prometheus text exposition format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// A single sample of a metric family with its labels
+struct Sample {
+  labels: Vec<(&'static str, String)>,
+  value: f64,
+}
+
+impl Sample {
+  fn new(value: impl Into<f64>) -> Self {
+    Self {
+      labels: Vec::new(),
+      value: value.into(),
+    }
+  }
+
+  fn label(mut self, name: &'static str, value: impl Into<String>) -> Self {
+    self.labels.push((name, value.into()));
+    self
+  }
+}
+
+/// Name, help and value getter of a network interface counter
+type NetworkCounter = (&'static str, &'static str, fn(&NetworkInfo) -> u64);
+
+/// Escape a label value as required by the text format
+fn escape_label(value: &str) -> String {
+  value
+    .replace('\\', "\\\\")
+    .replace('"', "\\\"")
+    .replace('\n', "\\n")
+}
+
+/// Write a metric family with its HELP and TYPE lines
+fn write_family(
+  out: &mut String,
+  name: &str,
+  kind: &str,
+  help: &str,
+  samples: impl IntoIterator<Item = Sample>,
+) {
+  let _ = writeln!(out, "# HELP {name} {help}");
+  let _ = writeln!(out, "# TYPE {name} {kind}");
+  for sample in samples {
+    out.push_str(name);
+    if !sample.labels.is_empty() {
+      let labels = sample
+        .labels
+        .iter()
+        .map(|(name, value)| format!("{name}=\"{}\"", escape_label(value)))
+        .collect::<Vec<_>>()
+        .join(",");
+      let _ = write!(out, "{{{labels}}}");
+    }
+    let _ = writeln!(out, " {}", sample.value);
+  }
+}
+
+/// Render a metrsd event using the prometheus text exposition format
+pub fn render(ev: &MetrsdEvent) -> String {
+  let mut out = String::new();
+  write_family(
+    &mut out,
+    "metrs_info",
+    "gauge",
+    "Information about the host and the daemon.",
+    [Sample::new(1)
+      .label("hostname", &ev.hostname)
+      .label("machine_id", &ev.machine_id)
+      .label("version", &ev.version)],
+  );
+  let host = &ev.host;
+  write_family(
+    &mut out,
+    "metrs_load1",
+    "gauge",
+    "Average load within one minute.",
+    [Sample::new(host.load_average.one)],
+  );
+  write_family(
+    &mut out,
+    "metrs_load5",
+    "gauge",
+    "Average load within five minutes.",
+    [Sample::new(host.load_average.five)],
+  );
+  write_family(
+    &mut out,
+    "metrs_load15",
+    "gauge",
+    "Average load within fifteen minutes.",
+    [Sample::new(host.load_average.fifteen)],
+  );
+  write_family(
+    &mut out,
+    "metrs_boot_time_seconds",
+    "gauge",
+    "Unix timestamp of when the host booted.",
+    [Sample::new(host.boot_time as f64)],
+  );
+  write_family(
+    &mut out,
+    "metrs_uptime_seconds",
+    "gauge",
+    "Time since the host booted.",
+    [Sample::new(host.uptime as f64)],
+  );
+  write_family(
+    &mut out,
+    "metrs_component_temperature_celsius",
+    "gauge",
+    "Temperature of the hardware component in celsius.",
+    ev.components.iter().filter_map(|component| {
+      let temperature = component.temperature?;
+      Some(Sample::new(temperature).label("component", &component.label))
+    }),
+  );
+  write_family(
+    &mut out,
+    "metrs_component_critical_celsius",
+    "gauge",
+    "Critical temperature of the hardware component in celsius.",
+    ev.components.iter().filter_map(|component| {
+      let critical = component.critical?;
+      Some(Sample::new(critical).label("component", &component.label))
+    }),
+  );
+  let memory = &ev.memory;
+  write_family(
+    &mut out,
+    "metrs_memory_total_bytes",
+    "gauge",
+    "Total amount of memory in bytes.",
+    [Sample::new(memory.total as f64)],
+  );
+  write_family(
+    &mut out,
+    "metrs_memory_used_bytes",
+    "gauge",
+    "Amount of used memory in bytes.",
+    [Sample::new(memory.used as f64)],
+  );
+  write_family(
+    &mut out,
+    "metrs_memory_free_bytes",
+    "gauge",
+    "Amount of free memory in bytes.",
+    [Sample::new(memory.free as f64)],
+  );
+  write_family(
+    &mut out,
+    "metrs_swap_total_bytes",
+    "gauge",
+    "Total amount of swap in bytes.",
+    [Sample::new(memory.swap_total as f64)],
+  );
+  write_family(
+    &mut out,
+    "metrs_swap_used_bytes",
+    "gauge",
+    "Amount of used swap in bytes.",
+    [Sample::new(memory.swap_used as f64)],
+  );
+  write_family(
+    &mut out,
+    "metrs_swap_free_bytes",
+    "gauge",
+    "Amount of free swap in bytes.",
+    [Sample::new(memory.swap_free as f64)],
+  );
+  write_family(
+    &mut out,
+    "metrs_cpu_usage_percent",
+    "gauge",
+    "Usage of the cpu in percent.",
+    ev.cpus
+      .iter()
+      .map(|cpu| Sample::new(cpu.usage).label("cpu", &cpu.name)),
+  );
+  write_family(
+    &mut out,
+    "metrs_cpu_frequency_hertz",
+    "gauge",
+    "Current frequency of the cpu in hertz.",
+    ev.cpus.iter().map(|cpu| {
+      Sample::new(cpu.frequency as f64 * 1_000_000.0).label("cpu", &cpu.name)
+    }),
+  );
+  write_family(
+    &mut out,
+    "metrs_disk_total_bytes",
+    "gauge",
+    "Total space of the disk in bytes.",
+    ev.disks.iter().map(|disk| {
+      Sample::new(disk.total_space as f64)
+        .label("device", &disk.device_name)
+        .label("mount_point", &disk.mount_point)
+        .label("file_system", &disk.file_system)
+    }),
+  );
+  write_family(
+    &mut out,
+    "metrs_disk_available_bytes",
+    "gauge",
+    "Available space of the disk in bytes.",
+    ev.disks.iter().map(|disk| {
+      Sample::new(disk.available_space as f64)
+        .label("device", &disk.device_name)
+        .label("mount_point", &disk.mount_point)
+        .label("file_system", &disk.file_system)
+    }),
+  );
+  write_family(
+    &mut out,
+    "metrs_cgroup_cpu_usage_seconds_total",
+    "counter",
+    "Cpu time consumed by the cgroup.",
+    ev.cgroups.iter().map(|cgroup| {
+      Sample::new(cgroup.cpu_usage_usec as f64 / 1_000_000.0)
+        .label("cgroup", &cgroup.path)
+    }),
+  );
+  write_family(
+    &mut out,
+    "metrs_cgroup_memory_current_bytes",
+    "gauge",
+    "Memory used by the cgroup in bytes.",
+    ev.cgroups.iter().map(|cgroup| {
+      Sample::new(cgroup.memory_current as f64).label("cgroup", &cgroup.path)
+    }),
+  );
+  write_family(
+    &mut out,
+    "metrs_cgroup_memory_max_bytes",
+    "gauge",
+    "Memory limit of the cgroup in bytes.",
+    ev.cgroups.iter().filter_map(|cgroup| {
+      let max = cgroup.memory_max?;
+      Some(Sample::new(max as f64).label("cgroup", &cgroup.path))
+    }),
+  );
+  write_family(
+    &mut out,
+    "metrs_cgroup_io_read_bytes_total",
+    "counter",
+    "Bytes read by the cgroup.",
+    ev.cgroups.iter().map(|cgroup| {
+      Sample::new(cgroup.io_read_bytes as f64).label("cgroup", &cgroup.path)
+    }),
+  );
+  write_family(
+    &mut out,
+    "metrs_cgroup_io_written_bytes_total",
+    "counter",
+    "Bytes written by the cgroup.",
+    ev.cgroups.iter().map(|cgroup| {
+      Sample::new(cgroup.io_written_bytes as f64).label("cgroup", &cgroup.path)
+    }),
+  );
+  write_family(
+    &mut out,
+    "metrs_cgroup_pids",
+    "gauge",
+    "Number of processes in the cgroup.",
+    ev.cgroups.iter().map(|cgroup| {
+      Sample::new(cgroup.pids_current as f64).label("cgroup", &cgroup.path)
+    }),
+  );
+  write_family(
+    &mut out,
+    "metrs_disk_read_bytes_total",
+    "counter",
+    "Bytes read from the disk.",
+    ev.disks.iter().map(|disk| {
+      Sample::new(disk.total_read_bytes as f64)
+        .label("device", &disk.device_name)
+        .label("mount_point", &disk.mount_point)
+        .label("file_system", &disk.file_system)
+    }),
+  );
+  write_family(
+    &mut out,
+    "metrs_disk_written_bytes_total",
+    "counter",
+    "Bytes written to the disk.",
+    ev.disks.iter().map(|disk| {
+      Sample::new(disk.total_written_bytes as f64)
+        .label("device", &disk.device_name)
+        .label("mount_point", &disk.mount_point)
+        .label("file_system", &disk.file_system)
+    }),
+  );
+  let networks: [NetworkCounter; 6] = [
+    (
+      "metrs_network_received_bytes_total",
+      "Bytes received by the interface.",
+      |net| net.total_received,
+    ),
+    (
+      "metrs_network_transmitted_bytes_total",
+      "Bytes transmitted by the interface.",
+      |net| net.total_transmitted,
+    ),
+    (
+      "metrs_network_received_packets_total",
+      "Packets received by the interface.",
+      |net| net.total_packets_received,
+    ),
+    (
+      "metrs_network_transmitted_packets_total",
+      "Packets transmitted by the interface.",
+      |net| net.total_packets_transmitted,
+    ),
+    (
+      "metrs_network_received_errors_total",
+      "Errors on received by the interface.",
+      |net| net.total_error_received,
+    ),
+    (
+      "metrs_network_transmitted_errors_total",
+      "Errors on transmitted by the interface.",
+      |net| net.total_error_transmitted,
+    ),
+  ];
+  for (name, help, value) in networks {
+    write_family(
+      &mut out,
+      name,
+      "counter",
+      help,
+      ev.networks.iter().map(|net| {
+        Sample::new(value(net) as f64).label("interface", &net.name)
+      }),
+    );
+  }
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use metrs_stubs::*;
+
+  fn event() -> MetrsdEvent {
+    MetrsdEvent {
+      memory: MemoryInfo {
+        total: 2048,
+        free: 1024,
+        used: 1024,
+        swap_total: 0,
+        swap_free: 0,
+        swap_used: 0,
+      },
+      cpus: vec![CpuInfo {
+        name: "cpu0".into(),
+        vendor_id: "GenuineIntel".into(),
+        brand: "Intel".into(),
+        frequency: 2400,
+        usage: 12.5,
+      }],
+      disks: vec![DiskInfo {
+        kind: DiskInfoKind::SSD,
+        device_name: "/dev/sda1".into(),
+        file_system: "ext4".into(),
+        mount_point: "/mnt/\"data\"".into(),
+        total_space: 4096,
+        available_space: 512,
+        is_removable: false,
+        read_bytes: 0,
+        written_bytes: 0,
+        total_read_bytes: 1024,
+        total_written_bytes: 2048,
+        read_rate: 0.0,
+        written_rate: 0.0,
+      }],
+      networks: vec![NetworkInfo {
+        name: "eth0".into(),
+        mac_addr: "00:00:00:00:00:00".into(),
+        total_received: 10,
+        total_transmitted: 20,
+        total_packets_received: 1,
+        total_packets_transmitted: 2,
+        ..Default::default()
+      }],
+      components: vec![ComponentInfo {
+        label: "coretemp Core 0".into(),
+        temperature: Some(42.0),
+        max: Some(60.0),
+        critical: None,
+      }],
+      cgroups: vec![CgroupInfo {
+        path: "/system.slice".into(),
+        cpu_usage_usec: 1_500_000,
+        memory_current: 1024,
+        ..Default::default()
+      }],
+      host: HostInfo {
+        load_average: LoadAverageInfo {
+          one: 0.5,
+          five: 0.25,
+          fifteen: 0.1,
+        },
+        ..Default::default()
+      },
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn test_render() {
+    let out = render(&event());
+    assert!(out
+      .contains("metrs_info{hostname=\"\",machine_id=\"\",version=\"\"} 1\n"));
+    assert!(out.contains("# HELP metrs_memory_total_bytes "));
+    assert!(out.contains("# TYPE metrs_memory_total_bytes gauge\n"));
+    assert!(out.contains("\nmetrs_memory_total_bytes 2048\n"));
+    assert!(out.contains("\nmetrs_load1 0.5\n"));
+    assert!(out.contains(
+      "metrs_component_temperature_celsius{component=\"coretemp Core 0\"} 42\n"
+    ));
+    assert!(!out.contains("metrs_component_critical_celsius{"));
+    assert!(out.contains(
+      "metrs_cgroup_cpu_usage_seconds_total{cgroup=\"/system.slice\"} 1.5\n"
+    ));
+    assert!(!out.contains("metrs_cgroup_memory_max_bytes{"));
+    assert!(out.contains("metrs_cpu_usage_percent{cpu=\"cpu0\"} 12.5\n"));
+    assert!(
+      out.contains("metrs_cpu_frequency_hertz{cpu=\"cpu0\"} 2400000000\n")
+    );
+    assert!(out.contains(
+      "metrs_disk_available_bytes{device=\"/dev/sda1\",\
+       mount_point=\"/mnt/\\\"data\\\"\",file_system=\"ext4\"} 512\n"
+    ));
+    assert!(out.contains(
+      "metrs_network_transmitted_bytes_total{interface=\"eth0\"} 20\n"
+    ));
+    assert!(out.contains("# TYPE metrs_disk_written_bytes_total counter\n"));
+    for line in out.lines().filter(|line| !line.starts_with('#')) {
+      let value = line.rsplit(' ').next().unwrap();
+      assert!(value.parse::<f64>().is_ok(), "Invalid sample: {line}");
+    }
+  }
+
+  #[test]
+  fn test_escape_label() {
+    assert_eq!(escape_label("a\\b\"c\nd"), "a\\\\b\\\"c\\nd");
+  }
+}
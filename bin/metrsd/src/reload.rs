@@ -0,0 +1,159 @@
+use std::{ffi::OsString, sync::Arc};
+
+use clap::CommandFactory;
+use signal_hook::{consts::SIGHUP, iterator::Signals};
+
+use crate::config;
+use crate::cli::Cli;
+use crate::error::MetrsError;
+use crate::tls::TlsConfig;
+use crate::metrics::MetricsConfig;
+use crate::event_emitter::EventEmitter;
+use crate::notify::{Notifier, NotifyConfig};
+
+/// Options applied when the daemon starts only
+fn restart_required(started: &Cli, cli: &Cli) -> Vec<&'static str> {
+  [
+    ("hosts", started.hosts != cli.hosts),
+    ("replay", started.replay != cli.replay),
+    ("tls", TlsConfig::from(started) != TlsConfig::from(cli)),
+    (
+      "auth",
+      started.auth_tokens != cli.auth_tokens
+        || started.allowed_uids != cli.allowed_uids
+        || started.allowed_gids != cli.allowed_gids,
+    ),
+    ("history", started.history != cli.history),
+    ("data_dir", started.data_dir != cli.data_dir),
+    (
+      "retention_size",
+      started.retention_size != cli.retention_size,
+    ),
+    ("retention_age", started.retention_age != cli.retention_age),
+    ("rollups", started.rollups != cli.rollups),
+  ]
+  .into_iter()
+  .filter_map(|(name, changed)| changed.then_some(name))
+  .collect()
+}
+
+/// Read the configuration again and apply it to the running daemon, the
+/// subscribers stay connected
+#[derive(Clone)]
+pub struct Reloader {
+  event_emitter: EventEmitter,
+  /// Arguments the daemon was started with, parsed again with the
+  /// environment and the configuration file on every reload
+  args: Arc<Vec<OsString>>,
+  /// Configuration the daemon was started with
+  started: Arc<Cli>,
+}
+
+impl Reloader {
+  pub fn new(
+    event_emitter: EventEmitter,
+    args: impl IntoIterator<Item = impl Into<OsString>>,
+    started: Cli,
+  ) -> Self {
+    Self {
+      event_emitter,
+      args: Arc::new(args.into_iter().map(Into::into).collect()),
+      started: Arc::new(started),
+    }
+  }
+
+  /// Apply the collectors, intervals, filters, alert rules and exporters of
+  /// the configuration, returning the changed options that need a restart.
+  /// Nothing is applied when the configuration is invalid.
+  pub fn reload(&self) -> Result<Vec<&'static str>, MetrsError> {
+    let matches = Cli::command()
+      .try_get_matches_from(self.args.iter())
+      .map_err(|err| MetrsError::Error(err.to_string()))?;
+    let cli = config::from_matches(&matches)?;
+    let rules = config::alert_rules(&cli)?;
+    let to_error = |err| MetrsError::Error(format!("{err}"));
+    self
+      .event_emitter
+      .set_metrics_config(MetricsConfig::from(&cli))
+      .map_err(to_error)?;
+    self
+      .event_emitter
+      .set_alert_rules(rules)
+      .map_err(to_error)?;
+    self
+      .event_emitter
+      .set_notifier(Notifier::new(NotifyConfig::from(&cli)))
+      .map_err(to_error)?;
+    let restart = restart_required(&self.started, &cli);
+    for name in &restart {
+      log::warn!("Option {name} changed, restart the daemon to apply it");
+    }
+    log::info!("Configuration reloaded");
+    Ok(restart)
+  }
+
+  /// Reload the configuration on every SIGHUP
+  pub fn spawn_signal_handler(self) -> std::io::Result<()> {
+    let mut signals = Signals::new([SIGHUP])?;
+    std::thread::Builder::new()
+      .name("metrsd reload".into())
+      .spawn(move || {
+        for _ in signals.forever() {
+          log::info!("Received SIGHUP, reloading configuration");
+          if let Err(err) = self.reload() {
+            log::error!("Unable to reload configuration: {err}");
+          }
+        }
+      })?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use clap::Parser;
+
+  use super::*;
+
+  #[ntex::test]
+  async fn test_reload() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("metrsd.toml");
+    std::fs::write(&path, "hosts = [\"tcp://0.0.0.0:8585\"]\n").unwrap();
+    let args = ["metrsd", "--config", path.to_str().unwrap(), "-t", "5"];
+    let cli =
+      config::from_matches(&Cli::command().get_matches_from(args)).unwrap();
+    let event_emitter =
+      EventEmitter::default().with_metrics_config(MetricsConfig::from(&cli));
+    let reloader = Reloader::new(event_emitter.clone(), args, cli);
+    std::fs::write(
+      &path,
+      r#"
+hosts = ["tcp://0.0.0.0:9090"]
+tick_interval = 30
+
+[collectors]
+enabled = ["memory"]
+"#,
+    )
+    .unwrap();
+    assert_eq!(reloader.reload().unwrap(), vec!["hosts"]);
+    let config = event_emitter.metrics_config().unwrap();
+    // The command line still takes precedence over the file
+    assert_eq!(config.tick_interval, 5);
+    assert_eq!(
+      config.filter.include,
+      vec![metrs_stubs::EventSection::Memory]
+    );
+    // An invalid configuration is reported and not applied
+    std::fs::write(&path, "[collectors]\nenabled = [\"gpus\"]\n").unwrap();
+    assert!(reloader.reload().is_err());
+    let config = event_emitter.metrics_config().unwrap();
+    assert_eq!(
+      config.filter.include,
+      vec![metrs_stubs::EventSection::Memory]
+    );
+    let cli = Cli::parse_from(["metrsd", "-H", "tcp://0.0.0.0:8585"]);
+    assert!(restart_required(&cli, &cli).is_empty());
+  }
+}
@@ -0,0 +1,121 @@
+use std::{
+  fs::File,
+  path::{Path, PathBuf},
+  time::Duration,
+};
+
+use ntex::{rt, time::sleep};
+
+use metrs_stubs::recording::RecordingReader;
+
+use crate::error::MetrsError;
+use crate::metrics::now_millis;
+use crate::event_emitter::EventEmitter;
+
+/// Wait before playing a recording of a single event again
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Open a recording made with `metrs record`
+pub fn open(path: &Path) -> Result<RecordingReader<File>, MetrsError> {
+  RecordingReader::open(path).map_err(|err| {
+    MetrsError::Error(format!(
+      "Unable to read recording {}: {err}",
+      path.display()
+    ))
+  })
+}
+
+/// Emit the events of a recording in a loop at the pace they were recorded.
+/// Their timestamps are shifted to the time they are played and their
+/// sequences keep increasing, so the history and the subscribers reconnecting
+/// see a continuous stream.
+async fn replay(event_emitter: &EventEmitter, path: &Path) {
+  let mut sequence = 0;
+  loop {
+    let recording = match open(path) {
+      Err(err) => {
+        log::error!("{err}");
+        return;
+      }
+      Ok(recording) => recording,
+    };
+    let mut offset = None;
+    let mut previous = None;
+    let mut interval = DEFAULT_INTERVAL;
+    for ev in recording {
+      let mut ev = match ev {
+        Err(err) => {
+          log::error!("Unable to read recording {}: {err}", path.display());
+          break;
+        }
+        Ok(ev) => ev,
+      };
+      if let Some(previous) = previous {
+        interval = Duration::from_millis(ev.timestamp.saturating_sub(previous));
+        sleep(interval).await;
+      }
+      previous = Some(ev.timestamp);
+      let offset = *offset
+        .get_or_insert_with(|| now_millis().saturating_sub(ev.timestamp));
+      ev.timestamp += offset;
+      sequence += 1;
+      ev.sequence = sequence;
+      if let Err(err) = event_emitter.emit(ev).await {
+        log::error!("{err}");
+      }
+    }
+    if previous.is_none() {
+      log::error!("No event found in recording {}", path.display());
+      return;
+    }
+    log::debug!("Recording {} ended, playing it again", path.display());
+    sleep(interval).await;
+  }
+}
+
+pub fn spawn_replay(event_emitter: EventEmitter, path: PathBuf) {
+  rt::Arbiter::new().handle().spawn(async move {
+    replay(&event_emitter, &path).await;
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use metrs_stubs::{
+    MetrsdEvent,
+    recording::{RecordingHeader, RecordingWriter},
+  };
+
+  use super::*;
+
+  #[ntex::test]
+  async fn test_replay() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("incident.mtrs");
+    let header = RecordingHeader::new("unix:///run/metrsd.sock", 0);
+    let mut recording = RecordingWriter::create(&path, &header).unwrap();
+    for sequence in 40..43 {
+      let ev = MetrsdEvent {
+        timestamp: 1_000_000 + sequence * 50,
+        sequence,
+        hostname: "recorded".into(),
+        ..Default::default()
+      };
+      recording.write(&ev).unwrap();
+    }
+    recording.finish().unwrap();
+    let event_emitter = EventEmitter::default();
+    let started = now_millis();
+    let emitter = event_emitter.clone();
+    let replayed = path.clone();
+    rt::spawn(async move { replay(&emitter, &replayed).await });
+    sleep(Duration::from_millis(400)).await;
+    let ev = event_emitter.last_event().unwrap().unwrap();
+    assert_eq!(ev.hostname, "recorded");
+    // Played again once the recording ended, with increasing sequences
+    assert!(ev.sequence > 3, "{}", ev.sequence);
+    assert!(ev.timestamp >= started, "{} < {started}", ev.timestamp);
+    let err = open(&dir.path().join("missing.mtrs")).err().unwrap();
+    assert!(err.to_string().starts_with("Unable to read recording"));
+  }
+}
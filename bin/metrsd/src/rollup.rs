@@ -0,0 +1,496 @@
+use std::{path::Path, time::Duration};
+
+use serde_json::{Map, Number, Value};
+
+use metrs_stubs::{MetrsdEvent, RollupInfo, format_duration, parse_duration};
+
+use crate::{
+  history::{History, HistorySize},
+  store::{SegmentStore, StoreConfig},
+};
+
+/// Keys identifying the items of the lists of an event, so the same disk or
+/// network interface is aggregated across events
+const IDENTITY_KEYS: [&str; 5] = ["Name", "MountPoint", "Label", "Path", "Pid"];
+
+/// Counters growing since the boot or the mount, their average is
+/// meaningless so a rollup keeps their last value
+const CUMULATIVE_KEYS: [&str; 9] = [
+  "TotalReadBytes",
+  "TotalWrittenBytes",
+  "TotalReceived",
+  "TotalTransmitted",
+  "TotalPacketsReceived",
+  "TotalPacketsTransmitted",
+  "TotalErrorReceived",
+  "TotalErrorTransmitted",
+  "Uptime",
+];
+
+/// Resolution and retention of a rollup tier
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RollupTier {
+  /// Duration of the period aggregated in a single event
+  pub resolution: Duration,
+  /// Age of the oldest rollups kept
+  pub retention: Duration,
+}
+
+impl std::str::FromStr for RollupTier {
+  type Err = String;
+
+  /// Parse a tier such as `1m:1d`
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let (resolution, retention) = s
+      .split_once(':')
+      .ok_or_else(|| format!("Invalid rollup tier: {s}"))?;
+    let resolution = parse_duration(resolution)?;
+    if resolution.is_zero() {
+      return Err("Rollup resolution must be greater than 0".into());
+    }
+    Ok(Self {
+      resolution,
+      retention: parse_duration(retention)?,
+    })
+  }
+}
+
+impl std::fmt::Display for RollupTier {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "{}:{}",
+      format_duration(self.resolution),
+      format_duration(self.retention)
+    )
+  }
+}
+
+/// How metrics are combined across events
+#[derive(Clone, Copy)]
+enum Aggregate {
+  Min,
+  Max,
+  Avg,
+  Last,
+}
+
+/// Combine numbers keeping them integers when they all are
+fn combine_numbers(numbers: &[(&Number, f64)], op: Aggregate) -> Value {
+  let values = numbers
+    .iter()
+    .filter_map(|(number, weight)| Some((number.as_f64()?, *weight)))
+    .collect::<Vec<_>>();
+  let value = match op {
+    Aggregate::Min => values.iter().map(|(v, _)| *v).fold(f64::MAX, f64::min),
+    Aggregate::Max => values.iter().map(|(v, _)| *v).fold(f64::MIN, f64::max),
+    Aggregate::Avg => {
+      let weight = values.iter().map(|(_, w)| w).sum::<f64>();
+      values.iter().map(|(v, w)| v * w).sum::<f64>() / weight
+    }
+    Aggregate::Last => values.last().map(|(v, _)| *v).unwrap_or_default(),
+  };
+  if numbers.iter().all(|(number, _)| number.is_u64()) {
+    Value::from(value.round() as u64)
+  } else if numbers.iter().all(|(number, _)| number.is_i64()) {
+    Value::from(value.round() as i64)
+  } else {
+    Number::from_f64(value)
+      .map(Value::Number)
+      .unwrap_or(Value::Null)
+  }
+}
+
+/// Identity of an item of a list, if it has one
+fn identity(value: &Value) -> Option<String> {
+  let object = value.as_object()?;
+  IDENTITY_KEYS
+    .iter()
+    .find_map(|key| object.get(*key))
+    .map(Value::to_string)
+}
+
+/// Combine the same metric of several events, the last value shapes the
+/// result and values that are not numbers are taken from it
+fn combine(values: &[(&Value, f64)], op: Aggregate) -> Value {
+  let Some((last, _)) = values.last() else {
+    return Value::Null;
+  };
+  match last {
+    Value::Number(_) => {
+      let numbers = values
+        .iter()
+        .filter_map(|(value, weight)| Some((value.as_number()?, *weight)))
+        .collect::<Vec<_>>();
+      combine_numbers(&numbers, op)
+    }
+    Value::Object(object) => {
+      let combined = object
+        .keys()
+        .map(|key| {
+          let fields = values
+            .iter()
+            .filter_map(|(value, weight)| Some((value.get(key)?, *weight)))
+            .collect::<Vec<_>>();
+          let op = match op {
+            Aggregate::Avg if CUMULATIVE_KEYS.contains(&key.as_str()) => {
+              Aggregate::Last
+            }
+            op => op,
+          };
+          (key.clone(), combine(&fields, op))
+        })
+        .collect::<Map<_, _>>();
+      Value::Object(combined)
+    }
+    Value::Array(items)
+      if items.iter().all(|item| identity(item).is_some()) =>
+    {
+      let combined = items
+        .iter()
+        .map(|item| {
+          let id = identity(item);
+          let same = values
+            .iter()
+            .filter_map(|(value, weight)| {
+              let same = value
+                .as_array()?
+                .iter()
+                .find(|other| identity(other) == id)?;
+              Some((same, *weight))
+            })
+            .collect::<Vec<_>>();
+          combine(&same, op)
+        })
+        .collect();
+      Value::Array(combined)
+    }
+    last => (*last).clone(),
+  }
+}
+
+/// Aggregate events or rollups of a finer tier into a rollup of the period,
+/// processes are not kept as they change from one event to the other
+fn aggregate(
+  events: &[MetrsdEvent],
+  start: u64,
+  resolution: Duration,
+) -> Option<MetrsdEvent> {
+  let last = events.last()?;
+  let mut avgs = Vec::new();
+  let mut mins = Vec::new();
+  let mut maxs = Vec::new();
+  for ev in events {
+    let mut ev = ev.clone();
+    ev.processes.clear();
+    let rollup = ev.rollup.take();
+    let avg = serde_json::to_value(&ev).ok()?;
+    let (min, max, count) = match rollup {
+      Some(rollup) => (
+        serde_json::to_value(&rollup.min).ok()?,
+        serde_json::to_value(&rollup.max).ok()?,
+        rollup.count,
+      ),
+      None => (avg.clone(), avg.clone(), 1),
+    };
+    avgs.push((avg, count as f64));
+    mins.push((min, 1.0));
+    maxs.push((max, 1.0));
+  }
+  let count = avgs.iter().map(|(_, count)| *count as u64).sum();
+  let to_event = |values: &[(Value, f64)], op| {
+    let values = values
+      .iter()
+      .map(|(value, weight)| (value, *weight))
+      .collect::<Vec<_>>();
+    let mut ev =
+      serde_json::from_value::<MetrsdEvent>(combine(&values, op)).ok()?;
+    ev.timestamp = start;
+    ev.sequence = last.sequence;
+    Some(ev)
+  };
+  let mut avg = to_event(&avgs, Aggregate::Avg)?;
+  avg.rollup = Some(Box::new(RollupInfo {
+    resolution: resolution.as_secs(),
+    count,
+    min: to_event(&mins, Aggregate::Min)?,
+    max: to_event(&maxs, Aggregate::Max)?,
+  }));
+  Some(avg)
+}
+
+/// A tier aggregating the events of every period of its resolution
+pub struct Rollup {
+  tier: RollupTier,
+  /// Start timestamp in milliseconds of the current period
+  start: Option<u64>,
+  /// Events of the current period
+  pending: Vec<MetrsdEvent>,
+  /// Rollups kept in memory when they are not persisted
+  history: History,
+  /// Store persisting the rollups, they are served from it when set
+  store: Option<SegmentStore>,
+}
+
+impl Rollup {
+  pub fn new(tier: RollupTier) -> Self {
+    Self {
+      tier,
+      start: None,
+      pending: Vec::new(),
+      history: History::new(HistorySize::Duration(tier.retention)),
+      store: None,
+    }
+  }
+
+  /// Persist the rollups in a segment store under the data directory, up
+  /// to the share of the retention size of the tier
+  pub fn with_store(
+    mut self,
+    data_dir: &Path,
+    retention_size: u64,
+  ) -> std::io::Result<Self> {
+    let dir =
+      data_dir.join(format!("rollup-{}s", self.tier.resolution.as_secs()));
+    let config = StoreConfig {
+      retention_size,
+      retention_age: self.tier.retention,
+      ..Default::default()
+    };
+    self.store = Some(SegmentStore::open(dir, config)?);
+    Ok(self)
+  }
+
+  /// Add an event, the rollup of the period is returned once an event of
+  /// the next period arrives
+  pub fn push(&mut self, ev: MetrsdEvent) -> Option<MetrsdEvent> {
+    let resolution = self.tier.resolution.as_millis() as u64;
+    let start = ev.timestamp - ev.timestamp % resolution;
+    let rollup = match self.start {
+      Some(current) if current != start => {
+        aggregate(&self.pending, current, self.tier.resolution)
+      }
+      _ => None,
+    };
+    if self.start != Some(start) {
+      self.pending.clear();
+      self.start = Some(start);
+    }
+    self.pending.push(ev);
+    let rollup = rollup?;
+    match self.store.as_mut() {
+      Some(store) => {
+        let res = serde_json::to_string(&rollup)
+          .map_err(std::io::Error::other)
+          .and_then(|json| store.append(rollup.timestamp, &json));
+        if let Err(err) = res {
+          log::error!("Unable to persist rollup: {err}");
+        }
+      }
+      None => self.history.push(rollup.clone()),
+    }
+    Some(rollup)
+  }
+
+  /// Get the rollups with a timestamp in the window, at least `step` apart
+  pub fn range(
+    &self,
+    since: Option<u64>,
+    until: Option<u64>,
+    step: Option<Duration>,
+  ) -> std::io::Result<Vec<MetrsdEvent>> {
+    match &self.store {
+      Some(store) => store.range(since, until, step),
+      None => Ok(self.history.range(since, until, step)),
+    }
+  }
+}
+
+/// Rollup tiers ordered from the finest to the coarsest resolution, each
+/// one aggregating the rollups of the previous one
+#[derive(Default)]
+pub struct Rollups {
+  tiers: Vec<Rollup>,
+}
+
+impl Rollups {
+  pub fn new(mut tiers: Vec<Rollup>) -> Self {
+    tiers.sort_by_key(|rollup| rollup.tier.resolution);
+    Self { tiers }
+  }
+
+  /// Add a raw event to the finest tier
+  pub fn push(&mut self, ev: MetrsdEvent) {
+    let mut next = Some(ev);
+    for rollup in self.tiers.iter_mut() {
+      match next.take() {
+        Some(ev) => next = rollup.push(ev),
+        None => break,
+      }
+    }
+  }
+
+  /// Pick the coarsest tier with a resolution within the step, or one able
+  /// to go back to `since` when the finer tiers no longer keep it. `None`
+  /// means the raw events should be served.
+  pub fn select(
+    &self,
+    since: Option<u64>,
+    step: Option<Duration>,
+    now: u64,
+    raw_retention: Option<Duration>,
+  ) -> Option<&Rollup> {
+    let mut selected = None;
+    let mut retention = raw_retention;
+    for rollup in &self.tiers {
+      let within_step = step.is_some_and(|step| step >= rollup.tier.resolution);
+      let expired = retention.zip(since).is_some_and(|(retention, since)| {
+        since < now.saturating_sub(retention.as_millis() as u64)
+      });
+      if !within_step && !expired {
+        break;
+      }
+      selected = Some(rollup);
+      retention = Some(rollup.tier.retention);
+    }
+    selected
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use metrs_stubs::{DiskInfo, DiskInfoKind, MemoryInfo, ProcessInfo};
+
+  fn disk(mount_point: &str, available_space: u64) -> DiskInfo {
+    DiskInfo {
+      kind: DiskInfoKind::SSD,
+      device_name: "/dev/sda".into(),
+      file_system: "ext4".into(),
+      mount_point: mount_point.into(),
+      total_space: 100,
+      available_space,
+      is_removable: false,
+      read_bytes: 0,
+      written_bytes: 0,
+      total_read_bytes: 0,
+      total_written_bytes: 0,
+      read_rate: 0.0,
+      written_rate: 0.0,
+    }
+  }
+
+  fn event(sequence: u64, timestamp: u64, used: u64) -> MetrsdEvent {
+    let mut root = disk("/", used);
+    root.total_read_bytes = 1000 + used;
+    MetrsdEvent {
+      sequence,
+      timestamp,
+      memory: MemoryInfo {
+        used,
+        ..Default::default()
+      },
+      disks: vec![root, disk("/data", 50)],
+      processes: vec![ProcessInfo::default()],
+      ..Default::default()
+    }
+  }
+
+  fn tier(resolution: u64, retention: u64) -> RollupTier {
+    RollupTier {
+      resolution: Duration::from_secs(resolution),
+      retention: Duration::from_secs(retention),
+    }
+  }
+
+  #[test]
+  fn test_rollup_tier() {
+    assert_eq!("1m:1d".parse(), Ok(tier(60, 86400)));
+    assert!("1m".parse::<RollupTier>().is_err());
+    assert!("0s:1d".parse::<RollupTier>().is_err());
+    assert_eq!(tier(60, 86400).to_string(), "1m:1d");
+  }
+
+  #[test]
+  fn test_aggregate() {
+    let events =
+      vec![event(1, 0, 10), event(2, 10_000, 20), event(3, 20_000, 60)];
+    let rollup = aggregate(&events, 0, Duration::from_secs(60)).unwrap();
+    assert_eq!(rollup.timestamp, 0);
+    assert_eq!(rollup.sequence, 3);
+    assert_eq!(rollup.memory.used, 30);
+    assert!(rollup.processes.is_empty());
+    assert_eq!(rollup.disks.len(), 2);
+    assert_eq!(rollup.disks[0].mount_point, "/");
+    assert_eq!(rollup.disks[0].available_space, 30);
+    assert_eq!(rollup.disks[1].available_space, 50);
+    // Cumulative counters are not averaged
+    assert_eq!(rollup.disks[0].total_read_bytes, 1060);
+    let info = rollup.rollup.as_ref().unwrap();
+    assert_eq!(info.resolution, 60);
+    assert_eq!(info.count, 3);
+    assert_eq!(info.min.memory.used, 10);
+    assert_eq!(info.max.memory.used, 60);
+    // Rollups of rollups are weighted by their number of events
+    let coarse = event(4, 60_000, 0);
+    let rollup =
+      aggregate(&[rollup, coarse], 0, Duration::from_secs(3600)).unwrap();
+    let info = rollup.rollup.as_ref().unwrap();
+    assert_eq!(info.count, 4);
+    assert_eq!(rollup.memory.used, 23);
+    assert_eq!(info.min.memory.used, 0);
+    assert_eq!(info.max.memory.used, 60);
+  }
+
+  #[test]
+  fn test_rollups() {
+    let mut rollups = Rollups::new(vec![
+      Rollup::new(tier(3600, 30 * 86400)),
+      Rollup::new(tier(60, 86400)),
+    ]);
+    for sequence in 0..=360 {
+      rollups.push(event(sequence, sequence * 10_000, sequence));
+    }
+    let minutes = rollups.tiers[0].range(None, None, None).unwrap();
+    assert_eq!(minutes.len(), 60);
+    assert_eq!(minutes[0].memory.used, 3);
+    assert_eq!(minutes[1].timestamp, 60_000);
+    let hours = rollups.tiers[1].range(None, None, None).unwrap();
+    assert!(hours.is_empty());
+    rollups.push(event(367, 3_670_000, 0));
+    let hours = rollups.tiers[1].range(None, None, None).unwrap();
+    assert_eq!(hours.len(), 1);
+    assert_eq!(hours[0].rollup.as_ref().unwrap().count, 360);
+  }
+
+  #[test]
+  fn test_select() {
+    let rollups = Rollups::new(vec![
+      Rollup::new(tier(60, 86400)),
+      Rollup::new(tier(3600, 30 * 86400)),
+    ]);
+    let resolution = |rollup: Option<&Rollup>| {
+      rollup.map(|rollup| rollup.tier.resolution.as_secs())
+    };
+    let hour = Some(Duration::from_secs(3600));
+    let now = 10 * 86400 * 1000;
+    assert_eq!(resolution(rollups.select(None, None, now, hour)), None);
+    let step = Some(Duration::from_secs(300));
+    assert_eq!(resolution(rollups.select(None, step, now, hour)), Some(60));
+    let step = Some(Duration::from_secs(7200));
+    assert_eq!(
+      resolution(rollups.select(None, step, now, hour)),
+      Some(3600)
+    );
+    // Older than the raw events, but within the minute rollups
+    let since = Some(now - 2 * 3600 * 1000);
+    assert_eq!(resolution(rollups.select(since, None, now, hour)), Some(60));
+    let since = Some(now - 2 * 86400 * 1000);
+    assert_eq!(
+      resolution(rollups.select(since, None, now, hour)),
+      Some(3600)
+    );
+    assert_eq!(resolution(rollups.select(since, None, now, None)), None);
+  }
+}
@@ -1,20 +1,220 @@
-use ntex::{web, http::StatusCode};
+use std::{sync::Arc, time::Duration};
 
-use crate::event_emitter::EventEmitter;
+use futures::StreamExt;
+use ntex::{
+  rt, web,
+  web::ws,
+  util::{ByteString, Bytes},
+  http::{HttpService, StatusCode, header, header::HeaderName},
+  service::{fn_factory_with_config, fn_service},
+};
+
+use metrs_stubs::{EventFilter, HistoryQuery, SubscribeQuery};
+
+use crate::prometheus;
+use crate::auth::{self, AuthConfig, BearerAuth, PeerCredentials};
+use crate::tls::{self, TlsConfig};
+use crate::reload::Reloader;
+use crate::event_emitter::{EventEmitter, StreamFormat};
 use crate::error::{MetrsError, HttpError};
 
+/// Stream the metrics as server-sent events when requested by the `Accept`
+/// header, as newline delimited json otherwise
 #[ntex::web::get("/subscribe")]
 async fn subscribe(
+  req: web::HttpRequest,
   event_emitter: web::types::State<EventEmitter>,
+  qs: web::types::Query<SubscribeQuery>,
 ) -> Result<web::HttpResponse, HttpError> {
-  let client = event_emitter.subscribe().await?;
+  let filter = EventFilter::try_from(&*qs).map_err(|err| HttpError {
+    status: StatusCode::BAD_REQUEST,
+    msg: err,
+  })?;
+  let header = |name| {
+    req
+      .headers()
+      .get(name)
+      .and_then(|value| value.to_str().ok())
+  };
+  let format = StreamFormat::from_accept(header(header::ACCEPT));
+  let last_event_id = header(HeaderName::from_static("last-event-id"))
+    .and_then(|id| id.trim().parse::<u64>().ok());
+  let client = event_emitter
+    .subscribe(filter, format, last_event_id)
+    .await?;
   Ok(
     web::HttpResponse::Ok()
-      .content_type("text/event-stream")
+      .content_type(format.content_type())
+      .header(header::CACHE_CONTROL, "no-cache")
       .streaming(client),
   )
 }
 
+/// Handle a frame sent by a websocket subscriber, a text frame holding a
+/// json subscribe query replaces its filter
+async fn ws_control(
+  event_emitter: EventEmitter,
+  id: u64,
+  frame: ws::Frame,
+) -> Result<Option<ws::Message>, HttpError> {
+  match frame {
+    ws::Frame::Ping(msg) => Ok(Some(ws::Message::Pong(msg))),
+    ws::Frame::Close(reason) => Ok(Some(ws::Message::Close(reason))),
+    ws::Frame::Text(text) => {
+      let filter = serde_json::from_slice::<SubscribeQuery>(&text)
+        .map_err(|err| format!("Invalid control message: {err}"))
+        .and_then(|query| EventFilter::try_from(&query))
+        .and_then(|filter| {
+          StreamFormat::Json.check_filter(&filter)?;
+          Ok(filter)
+        });
+      match filter {
+        Ok(filter) => {
+          event_emitter.update_filter(id, filter)?;
+          Ok(None)
+        }
+        Err(msg) => {
+          let err_json = serde_json::json!({ "msg": msg });
+          Ok(Some(ws::Message::Text(err_json.to_string().into())))
+        }
+      }
+    }
+    _ => Ok(None),
+  }
+}
+
+/// Push the metrics over a websocket, one json event per text message
+#[ntex::web::get("/ws")]
+async fn subscribe_ws(
+  req: web::HttpRequest,
+  event_emitter: web::types::State<EventEmitter>,
+  qs: web::types::Query<SubscribeQuery>,
+) -> Result<web::HttpResponse, web::Error> {
+  let filter = EventFilter::try_from(&*qs).map_err(|err| HttpError {
+    status: StatusCode::BAD_REQUEST,
+    msg: err,
+  })?;
+  let event_emitter = event_emitter.get_ref().clone();
+  ws::start::<_, _, &str, web::Error>(
+    req,
+    None,
+    fn_factory_with_config(move |sink: ws::WsSink| {
+      let event_emitter = event_emitter.clone();
+      let filter = filter.clone();
+      async move {
+        let mut client = event_emitter
+          .subscribe(filter, StreamFormat::Json, None)
+          .await?;
+        let id = client.id();
+        rt::spawn(async move {
+          while let Some(Ok(msg)) = client.next().await {
+            // Empty messages are the heartbeats of the event emitter
+            let msg = if msg.is_empty() {
+              ws::Message::Ping(Bytes::new())
+            } else {
+              match ByteString::try_from(msg) {
+                Ok(text) => ws::Message::Text(text),
+                Err(_) => continue,
+              }
+            };
+            if sink.send(msg).await.is_err() {
+              break;
+            }
+          }
+        });
+        Ok::<_, HttpError>(fn_service(move |frame| {
+          ws_control(event_emitter.clone(), id, frame)
+        }))
+      }
+    }),
+  )
+  .await
+}
+
+#[ntex::web::get("/metrics")]
+async fn snapshot(
+  event_emitter: web::types::State<EventEmitter>,
+) -> Result<web::HttpResponse, HttpError> {
+  let event = event_emitter.last_event()?.ok_or(HttpError {
+    status: StatusCode::SERVICE_UNAVAILABLE,
+    msg: "No metrics collected yet".into(),
+  })?;
+  Ok(web::HttpResponse::Ok().json(&event))
+}
+
+/// Get the events kept in memory collected in a window
+#[ntex::web::get("/history")]
+async fn history(
+  event_emitter: web::types::State<EventEmitter>,
+  qs: web::types::Query<HistoryQuery>,
+) -> Result<web::HttpResponse, HttpError> {
+  if qs.step == Some(0) {
+    return Err(HttpError {
+      status: StatusCode::BAD_REQUEST,
+      msg: "Step must be greater than 0".into(),
+    });
+  }
+  if let (Some(since), Some(until)) = (qs.since, qs.until) {
+    if since > until {
+      return Err(HttpError {
+        status: StatusCode::BAD_REQUEST,
+        msg: "Since must be before until".into(),
+      });
+    }
+  }
+  let events = event_emitter
+    .history(qs.since, qs.until, qs.step.map(Duration::from_secs))
+    .await?;
+  Ok(web::HttpResponse::Ok().json(&events))
+}
+
+/// Get the firing alerts and the most recently resolved ones
+#[ntex::web::get("/alerts")]
+async fn alerts(
+  event_emitter: web::types::State<EventEmitter>,
+) -> Result<web::HttpResponse, HttpError> {
+  let alerts = event_emitter.alerts()?;
+  Ok(web::HttpResponse::Ok().json(&alerts))
+}
+
+/// Read the configuration again and apply it without dropping subscribers
+#[ntex::web::post("/admin/reload")]
+async fn reload(
+  reloader: web::types::State<Reloader>,
+) -> Result<web::HttpResponse, HttpError> {
+  let reloader = reloader.get_ref().clone();
+  let restart =
+    web::block(move || reloader.reload())
+      .await
+      .map_err(|err| HttpError {
+        status: StatusCode::BAD_REQUEST,
+        msg: format!("Unable to reload configuration: {err}"),
+      })?;
+  let msg = match restart.as_slice() {
+    [] => "Configuration reloaded".to_owned(),
+    restart => format!(
+      "Configuration reloaded, restart to apply: {}",
+      restart.join(", ")
+    ),
+  };
+  Ok(web::HttpResponse::Ok().json(&serde_json::json!({ "msg": msg })))
+}
+
+#[ntex::web::get("/metrics/prometheus")]
+async fn prometheus_metrics(
+  event_emitter: web::types::State<EventEmitter>,
+) -> Result<web::HttpResponse, HttpError> {
+  let event = event_emitter.last_event()?.ok_or(HttpError {
+    status: StatusCode::SERVICE_UNAVAILABLE,
+    msg: "No metrics collected yet".into(),
+  })?;
+  Ok(
+    web::HttpResponse::Ok()
+      .content_type(prometheus::CONTENT_TYPE)
+      .body(prometheus::render(&event)),
+  )
+}
+
 async fn unhandled_route() -> Result<web::HttpResponse, HttpError> {
   Err(HttpError {
     status: StatusCode::NOT_FOUND,
@@ -24,49 +224,109 @@ async fn unhandled_route() -> Result<web::HttpResponse, HttpError> {
 
 pub fn gen_srv<T>(
   hosts: &[T],
+  tls: &TlsConfig,
+  auth: AuthConfig,
   event_emitter: EventEmitter,
+  reloader: Reloader,
 ) -> Result<ntex::server::Server, MetrsError>
 where
   T: Into<String> + Clone,
 {
-  let mut srv = web::HttpServer::new({
-    let event_emitter = event_emitter.clone();
+  let auth = Arc::new(auth);
+  let factory = {
+    let auth = auth.clone();
     move || {
       let event_emitter = event_emitter.clone();
+      let reloader = reloader.clone();
+      let auth = auth.clone();
       async move {
         web::App::new()
           .state(event_emitter)
+          .state(reloader)
+          .middleware(BearerAuth::new(auth))
           .service(subscribe)
+          .service(subscribe_ws)
+          .service(snapshot)
+          .service(history)
+          .service(alerts)
+          .service(reload)
+          .service(prometheus_metrics)
           .default_service(web::route().to(unhandled_route))
       }
     }
-  });
+  };
+  let mut srv = ntex::server::build();
+  // The credentials of the peers of the unix hosts are read as they are
+  // accepted, then checked on every request by the auth middleware
+  let checks_peers = auth.checks_peers();
+  if checks_peers {
+    srv = srv.on_accept(|host: Arc<str>, stream| async move {
+      auth::accept_peer(&host, stream)
+    });
+  }
 
-  for host in hosts {
-    let host = host.to_owned().into();
-    match &host {
+  let hosts = hosts
+    .iter()
+    .cloned()
+    .map(Into::into)
+    .collect::<Vec<String>>();
+  let tls_config = match hosts.iter().any(|host| tls::is_tls_host(host)) {
+    true => Some(tls.server_config()?),
+    false => None,
+  };
+  let map_err =
+    |err| MetrsError::Error(format!("Unable to bind server: {err}"));
+  for host in &hosts {
+    let factory = factory.clone();
+    match host {
       host if host.starts_with("unix://") => {
         let path = host.trim_start_matches("unix://");
-        srv = srv.bind_uds(path).map_err(|err| {
-          MetrsError::Error(format!("Unable to bind server: {err}"))
-        })?;
+        srv = match checks_peers {
+          true => srv.bind_uds(host, path, async move |_| {
+            PeerCredentials::new(HttpService::new(factory().await))
+          }),
+          false => {
+            if !auth.tokens.is_empty() {
+              log::warn!(
+                "No bearer token is required on {host}, set --allowed-uid \
+                 or --allowed-gid to restrict its peers"
+              );
+            }
+            srv.bind_uds(host, path, async move |_| {
+              HttpService::new(factory().await)
+            })
+          }
+        }
+        .map_err(map_err)?;
         log::info!("Listening on: {host}")
       }
       host if host.starts_with("tcp://") => {
         let addr = host.trim_start_matches("tcp://");
-        srv = srv.bind(addr).map_err(|err| {
-          MetrsError::Error(format!("Unable to bind server: {err}"))
-        })?;
+        srv = srv
+          .bind(host, addr, async move |_| HttpService::new(factory().await))
+          .map_err(map_err)?;
+        log::info!("Listening on: {host}")
+      }
+      host if tls::is_tls_host(host) => {
+        let addr = host
+          .trim_start_matches("tls://")
+          .trim_start_matches("https://");
+        if let Some(tls_config) = tls_config.clone() {
+          srv = srv
+            .bind(host, addr, async move |_| {
+              HttpService::new(factory().await).rustls(tls_config.clone())
+            })
+            .map_err(map_err)?;
+        }
         log::info!("Listening on: {host}")
       }
       _ => {
         return Err(MetrsError::Error(format!(
-          "Invalid host scheme must be [tcp,unix] got: {host}"
+          "Invalid host scheme must be [tcp,tls,https,unix] got: {host}"
         )))
       }
     }
   }
-
   Ok(srv.run())
 }
 
@@ -77,10 +337,17 @@ mod tests {
   use std::time::Duration;
 
   use ntex::web;
-  use ntex::time::interval;
   use futures::{TryStreamExt, StreamExt};
 
+  use clap::{CommandFactory, Parser};
+  use metrs_stubs::{
+    CpuInfo, DiskInfo, DiskInfoKind, MemoryInfo, MetrsdEvent, NetworkInfo,
+  };
+
   use crate::metrics;
+  use crate::clock::{ManualClock, TickClock};
+  use crate::collector::ScriptedCollector;
+  use crate::rollup::Rollup;
 
   pub fn before() {
     // Build a test env logger
@@ -93,8 +360,79 @@ mod tests {
       .try_init();
   }
 
+  /// Interval between two events published by the scripted collector
+  pub const TICK: Duration = Duration::from_secs(10);
+
+  /// Event published by the scripted collector
+  pub fn fake_event() -> MetrsdEvent {
+    let cpu = |name: &str, usage| CpuInfo {
+      name: name.into(),
+      usage,
+      ..Default::default()
+    };
+    let network = |name: &str| NetworkInfo {
+      name: name.into(),
+      received_rate: 1024.0,
+      ..Default::default()
+    };
+    MetrsdEvent {
+      hostname: "metrs-test".into(),
+      memory: MemoryInfo {
+        total: 8 * 1024 * 1024 * 1024,
+        used: 2 * 1024 * 1024 * 1024,
+        free: 6 * 1024 * 1024 * 1024,
+        ..Default::default()
+      },
+      cpus: vec![cpu("cpu0", 12.5), cpu("cpu1", 50.0)],
+      disks: vec![DiskInfo {
+        kind: DiskInfoKind::SSD,
+        device_name: "sda1".into(),
+        file_system: "ext4".into(),
+        mount_point: "/".into(),
+        total_space: 100 * 1024 * 1024 * 1024,
+        available_space: 40 * 1024 * 1024 * 1024,
+        is_removable: false,
+        read_bytes: 4096,
+        written_bytes: 8192,
+        total_read_bytes: 4096,
+        total_written_bytes: 8192,
+        read_rate: 409.6,
+        written_rate: 819.2,
+      }],
+      networks: vec![network("lo"), network("eth0")],
+      ..Default::default()
+    }
+  }
+
+  /// Publish the fake event on every tick of a manual clock
+  pub fn spawn_fake_metrics(event_emitter: &EventEmitter) -> ManualClock {
+    let clock = ManualClock::new(metrics::now_millis());
+    metrics::spawn_collector(
+      event_emitter.clone(),
+      ScriptedCollector::new([fake_event()]),
+      TickClock::Manual(clock.clone()),
+    );
+    clock
+  }
+
+  /// Wait for the first event published by the collector
+  pub async fn wait_first_event(event_emitter: &EventEmitter) {
+    while event_emitter.last_event().unwrap().is_none() {
+      ntex::time::sleep(Duration::from_millis(10)).await;
+    }
+  }
+
   pub async fn generate_server(
     event_emitter: EventEmitter,
+  ) -> web::test::TestServer {
+    let cli = crate::cli::Cli::parse_from(["metrsd"]);
+    let reloader = Reloader::new(event_emitter.clone(), ["metrsd"], cli);
+    generate_server_with_reloader(event_emitter, reloader).await
+  }
+
+  pub async fn generate_server_with_reloader(
+    event_emitter: EventEmitter,
+    reloader: Reloader,
   ) -> web::test::TestServer {
     before();
     // Create test server
@@ -103,7 +441,14 @@ mod tests {
       async move || {
         web::App::new()
           .state(event_emitter.clone())
+          .state(reloader.clone())
           .service(subscribe)
+          .service(subscribe_ws)
+          .service(snapshot)
+          .service(history)
+          .service(alerts)
+          .service(reload)
+          .service(prometheus_metrics)
           .default_service(web::route().to(unhandled_route))
       }
     })
@@ -112,15 +457,35 @@ mod tests {
 
   #[ntex::test]
   async fn test_gen_srv() {
-    let event_emitter = EventEmitter::new();
+    let event_emitter = EventEmitter::default();
+    let cli = crate::cli::Cli::parse_from(["metrsd"]);
+    let reloader = Reloader::new(event_emitter.clone(), ["metrsd"], cli);
     let hosts = vec!["unix:///tmp/metrsd.sock"];
-    let srv = gen_srv(&hosts, event_emitter.clone());
+    let srv = gen_srv(
+      &hosts,
+      &TlsConfig::default(),
+      AuthConfig::default(),
+      event_emitter.clone(),
+      reloader.clone(),
+    );
     assert!(srv.is_ok());
     let hosts = vec!["tcp://0.0.0.0:1245"];
-    let srv = gen_srv(&hosts, event_emitter.clone());
+    let srv = gen_srv(
+      &hosts,
+      &TlsConfig::default(),
+      AuthConfig::default(),
+      event_emitter.clone(),
+      reloader.clone(),
+    );
     assert!(srv.is_ok());
     let hosts = vec!["wrong_scheme://dsadas"];
-    let srv = gen_srv(&hosts, event_emitter);
+    let srv = gen_srv(
+      &hosts,
+      &TlsConfig::default(),
+      AuthConfig::default(),
+      event_emitter,
+      reloader,
+    );
     assert!(srv.is_err());
     let err = srv.unwrap_err();
     println!("{err}");
@@ -129,33 +494,470 @@ mod tests {
 
   #[ntex::test]
   async fn test_subscribe() {
-    let event_emitter = EventEmitter::new();
-    metrics::spawn_metrics(event_emitter.clone(), 10);
+    let event_emitter = EventEmitter::default();
+    let clock = spawn_fake_metrics(&event_emitter);
+    wait_first_event(&event_emitter).await;
     let srv = generate_server(event_emitter.clone()).await;
     let req = srv.get("/subscribe").send();
     let resp = req.await.unwrap();
     assert_eq!(resp.status(), StatusCode::OK);
     let mut stream = resp.into_stream();
-    let mut count = 0;
+    let mut timestamps = Vec::new();
     const MAX_COUNT: usize = 5;
+    while timestamps.len() < MAX_COUNT {
+      clock.advance(TICK);
+      let count = timestamps.len();
+      let item = stream
+        .next()
+        .await
+        .unwrap()
+        .unwrap_or_else(|_| panic!("Expect to receive {count} event"));
+      let event = serde_json::from_slice::<MetrsdEvent>(&item).unwrap();
+      assert_eq!(event.hostname, "metrs-test");
+      timestamps.push(event.timestamp);
+    }
+    // One event per tick of the clock
+    assert!(timestamps.windows(2).all(|w| w[1] - w[0] == 10_000));
+    // That will close the connection
+    drop(stream);
+    // Trigger the cleanup without waiting for its interval
+    event_emitter.clone().check_connection().unwrap();
+  }
+
+  #[ntex::test]
+  async fn test_subscribe_sse() {
+    let event_emitter = EventEmitter::default();
+    spawn_fake_metrics(&event_emitter);
+    let srv = generate_server(event_emitter.clone()).await;
+    wait_first_event(&event_emitter).await;
+    let resp = srv
+      .get("/subscribe?include=memory")
+      .header(header::ACCEPT, "text/event-stream")
+      .header("last-event-id", "0")
+      .send()
+      .await
+      .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(
+      resp.headers().get(header::CONTENT_TYPE).unwrap(),
+      "text/event-stream"
+    );
+    let mut stream = resp.into_stream();
+    let mut payload = String::new();
     while let Some(item) = stream.next().await {
-      let _ =
-        item.unwrap_or_else(|_| panic!("Expect to receive {count} event"));
-      count += 1;
-      if count == MAX_COUNT {
+      payload.push_str(std::str::from_utf8(&item.unwrap()).unwrap());
+      if payload.contains("data: ") && payload.ends_with("\n\n") {
         break;
       }
     }
-    assert_eq!(count, MAX_COUNT);
-    // That will close the connection
-    drop(stream);
-    // Wait 15 seconds to trigger cleanup
-    interval(Duration::from_secs(15)).tick().await;
+    assert!(payload.starts_with("retry: 3000\n\n"));
+    let frame = payload.trim_start_matches("retry: 3000\n\n");
+    let mut lines = frame.lines();
+    assert!(lines.next().unwrap().starts_with("id: "));
+    assert_eq!(lines.next().unwrap(), "event: metrics");
+    let data = lines.next().unwrap().trim_start_matches("data: ");
+    let event = serde_json::from_str::<metrs_stubs::MetrsdEvent>(data).unwrap();
+    assert!(event.memory.total > 0);
+  }
+
+  #[ntex::test]
+  async fn test_subscribe_filter() {
+    let event_emitter = EventEmitter::default();
+    let clock = spawn_fake_metrics(&event_emitter);
+    wait_first_event(&event_emitter).await;
+    let srv = generate_server(event_emitter.clone()).await;
+    let resp = srv
+      .get("/subscribe?include=memory,networks&interfaces=lo")
+      .send()
+      .await
+      .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    clock.advance(TICK);
+    let mut stream = resp.into_stream();
+    let mut payload = Vec::new();
+    while let Some(item) = stream.next().await {
+      payload.extend(item.unwrap().to_vec());
+      if payload.last() == Some(&b'\n') {
+        break;
+      }
+    }
+    let event =
+      serde_json::from_slice::<metrs_stubs::MetrsdEvent>(&payload).unwrap();
+    assert!(event.memory.total > 0);
+    assert!(event.cpus.is_empty());
+    assert!(event.disks.is_empty());
+    assert_eq!(event.networks.len(), 1);
+    assert_eq!(event.networks[0].name, "lo");
+  }
+
+  #[ntex::test]
+  async fn test_subscribe_interval() {
+    let config = metrics::MetricsConfig {
+      tick_interval: 60,
+      ..Default::default()
+    };
+    let event_emitter = EventEmitter::default().with_metrics_config(config);
+    let clock = spawn_fake_metrics(&event_emitter);
+    let srv = generate_server(event_emitter.clone()).await;
+    // Wait for the first event so the next one is collected for the subscriber
+    wait_first_event(&event_emitter).await;
+    let resp = srv
+      .get("/subscribe?include=memory&interval=1")
+      .send()
+      .await
+      .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    // Far from the tick interval of a minute
+    clock.advance(Duration::from_secs(1));
+    let mut stream = resp.into_stream();
+    let event = ntex::time::timeout(Duration::from_secs(10), async move {
+      // Skip the keep alive messages
+      while let Some(item) = stream.next().await {
+        let bytes = item.unwrap();
+        if !bytes.is_empty() {
+          return bytes;
+        }
+      }
+      panic!("Stream closed before receiving an event");
+    })
+    .await
+    .expect("Expect an event before the tick interval");
+    assert!(!event.is_empty());
+  }
+
+  #[ntex::test]
+  async fn test_subscribe_ws() {
+    let event_emitter = EventEmitter::default();
+    let clock = spawn_fake_metrics(&event_emitter);
+    wait_first_event(&event_emitter).await;
+    let srv = generate_server(event_emitter.clone()).await;
+    let (io, codec, _) =
+      srv.ws_at("/ws?include=memory").await.unwrap().into_inner();
+    // Skip the heartbeats until the next message
+    let next_text = async || loop {
+      match io.recv(&codec).await.unwrap().unwrap() {
+        ws::Frame::Text(text) => return text,
+        ws::Frame::Ping(msg) => {
+          io.send(ws::Message::Pong(msg), &codec).await.unwrap();
+        }
+        frame => panic!("Unexpected frame {frame:?}"),
+      }
+    };
+    // The pong is sent once the messages sent before the ping are handled,
+    // then the next tick publishes an event with the filter applied
+    let next_event = async || {
+      io.send(ws::Message::Ping(Bytes::new()), &codec)
+        .await
+        .unwrap();
+      loop {
+        match io.recv(&codec).await.unwrap().unwrap() {
+          ws::Frame::Pong(_) => break,
+          ws::Frame::Ping(msg) => {
+            io.send(ws::Message::Pong(msg), &codec).await.unwrap();
+          }
+          frame => panic!("Unexpected frame {frame:?}"),
+        }
+      }
+      clock.advance(TICK);
+      next_text().await
+    };
+    let text = next_event().await;
+    let event =
+      serde_json::from_slice::<metrs_stubs::MetrsdEvent>(&text).unwrap();
+    assert!(event.memory.total > 0);
+    assert!(event.cpus.is_empty());
+    let control = r#"{"include":"cpus"}"#;
+    io.send(ws::Message::Text(control.into()), &codec)
+      .await
+      .unwrap();
+    let text = next_event().await;
+    let event =
+      serde_json::from_slice::<metrs_stubs::MetrsdEvent>(&text).unwrap();
+    assert_eq!(event.memory.total, 0);
+    assert!(!event.cpus.is_empty());
+    let control = r#"{"include":"unknown"}"#;
+    io.send(ws::Message::Text(control.into()), &codec)
+      .await
+      .unwrap();
+    let text = next_text().await;
+    let err = serde_json::from_slice::<serde_json::Value>(&text).unwrap();
+    assert_eq!(err["msg"], "Invalid event section: unknown");
+    io.send(ws::Message::Close(None), &codec).await.unwrap();
+  }
+
+  #[ntex::test]
+  async fn test_subscribe_invalid_filter() {
+    let event_emitter = EventEmitter::default();
+    let srv = generate_server(event_emitter.clone()).await;
+    let resp = srv.get("/subscribe?include=unknown").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = srv.get("/ws?interval=0").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+  }
+
+  #[ntex::test]
+  async fn test_snapshot() {
+    let event_emitter = EventEmitter::default();
+    let srv = generate_server(event_emitter.clone()).await;
+    let resp = srv.get("/metrics").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let clock = spawn_fake_metrics(&event_emitter);
+    wait_first_event(&event_emitter).await;
+    let resp = srv.get("/metrics").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let event = resp
+      .json::<metrs_stubs::MetrsdEvent>()
+      .limit(1024 * 1024)
+      .await
+      .unwrap();
+    assert_eq!(event.memory.total, fake_event().memory.total);
+    assert_eq!(event.sequence, 1);
+    assert_eq!(event.timestamp, clock.now());
+    assert_eq!(event.version, env!("CARGO_PKG_VERSION"));
+  }
+
+  #[ntex::test]
+  async fn test_prometheus_metrics() {
+    let event_emitter = EventEmitter::default();
+    let srv = generate_server(event_emitter.clone()).await;
+    let resp = srv.get("/metrics/prometheus").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    spawn_fake_metrics(&event_emitter);
+    wait_first_event(&event_emitter).await;
+    let resp = srv.get("/metrics/prometheus").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(
+      resp.headers().get("content-type").unwrap(),
+      prometheus::CONTENT_TYPE
+    );
+    let body = resp.body().limit(1024 * 1024).await.unwrap();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body.contains("# TYPE metrs_memory_total_bytes gauge"));
+    assert!(body.contains("metrs_memory_total_bytes 8589934592\n"));
+  }
+
+  #[ntex::test]
+  async fn test_history() {
+    let event_emitter = EventEmitter::default();
+    let srv = generate_server(event_emitter.clone()).await;
+    for sequence in 1..=6 {
+      let ev = metrs_stubs::MetrsdEvent {
+        sequence,
+        timestamp: sequence * 10_000,
+        ..Default::default()
+      };
+      event_emitter.emit(ev).await.unwrap();
+    }
+    let resp = srv.get("/history").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let events = resp
+      .json::<Vec<metrs_stubs::MetrsdEvent>>()
+      .limit(1024 * 1024)
+      .await
+      .unwrap();
+    assert_eq!(events.len(), 6);
+    let resp = srv
+      .get("/history?since=20000&until=60000&step=20")
+      .send()
+      .await
+      .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let events = resp
+      .json::<Vec<metrs_stubs::MetrsdEvent>>()
+      .limit(1024 * 1024)
+      .await
+      .unwrap();
+    let sequences = events.iter().map(|ev| ev.sequence).collect::<Vec<_>>();
+    assert_eq!(sequences, vec![2, 4, 6]);
+    let resp = srv.get("/history?step=0").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = srv.get("/history?since=2&until=1").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+  }
+
+  #[ntex::test]
+  async fn test_history_store() {
+    let dir = tempfile::tempdir().unwrap();
+    let open_store = || {
+      crate::store::SegmentStore::open(dir.path(), Default::default()).unwrap()
+    };
+    let event_emitter = EventEmitter::default().with_store(open_store());
+    for sequence in 1..=3 {
+      let ev = metrs_stubs::MetrsdEvent {
+        sequence,
+        timestamp: sequence * 10_000,
+        ..Default::default()
+      };
+      event_emitter.emit(ev).await.unwrap();
+    }
+    // A restarted daemon serves the events persisted before
+    let event_emitter = EventEmitter::default().with_store(open_store());
+    let srv = generate_server(event_emitter.clone()).await;
+    let resp = srv.get("/history?since=20000").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let events = resp
+      .json::<Vec<metrs_stubs::MetrsdEvent>>()
+      .limit(1024 * 1024)
+      .await
+      .unwrap();
+    let sequences = events.iter().map(|ev| ev.sequence).collect::<Vec<_>>();
+    assert_eq!(sequences, vec![2, 3]);
+  }
+
+  #[ntex::test]
+  async fn test_history_rollup() {
+    let tier = "1m:1d".parse().unwrap();
+    let rollups = crate::rollup::Rollups::new(vec![Rollup::new(tier)]);
+    let event_emitter = EventEmitter::default().with_rollups(rollups);
+    let srv = generate_server(event_emitter.clone()).await;
+    for sequence in 0..=18 {
+      let ev = metrs_stubs::MetrsdEvent {
+        sequence,
+        timestamp: sequence * 10_000,
+        ..Default::default()
+      };
+      event_emitter.emit(ev).await.unwrap();
+    }
+    let resp = srv.get("/history?step=60").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let events = resp
+      .json::<Vec<metrs_stubs::MetrsdEvent>>()
+      .limit(1024 * 1024)
+      .await
+      .unwrap();
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[1].timestamp, 60_000);
+    assert_eq!(events[1].rollup.as_ref().unwrap().count, 6);
+    let resp = srv.get("/history?step=30").send().await.unwrap();
+    let events = resp
+      .json::<Vec<metrs_stubs::MetrsdEvent>>()
+      .limit(1024 * 1024)
+      .await
+      .unwrap();
+    assert_eq!(events.len(), 7);
+    assert!(events[0].rollup.is_none());
+  }
+
+  #[ntex::test]
+  async fn test_alerts() {
+    let rules = toml::from_str(
+      r#"
+      [[rules]]
+      name = "memory_high"
+      metric = "memory.used_percent"
+      op = ">"
+      threshold = 90
+      "#,
+    )
+    .unwrap();
+    let event_emitter = EventEmitter::default()
+      .with_alerts(crate::alert::AlertEngine::new(rules));
+    let srv = generate_server(event_emitter.clone()).await;
+    let resp = srv
+      .get("/subscribe?include=alerts")
+      .header(header::ACCEPT, "text/event-stream")
+      .send()
+      .await
+      .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let mut stream = resp.into_stream();
+    for used in [50, 95] {
+      let ev = metrs_stubs::MetrsdEvent {
+        memory: metrs_stubs::MemoryInfo {
+          total: 100,
+          used,
+          ..Default::default()
+        },
+        ..Default::default()
+      };
+      event_emitter.emit(ev).await.unwrap();
+    }
+    let mut payload = String::new();
+    while let Some(item) = stream.next().await {
+      payload.push_str(std::str::from_utf8(&item.unwrap()).unwrap());
+      if payload.contains("data: ") && payload.ends_with("\n\n") {
+        break;
+      }
+    }
+    // Only the alert is sent to a subscriber including only the alerts
+    let frame = payload.trim_start_matches("retry: 3000\n\n");
+    let mut lines = frame.lines();
+    assert_eq!(lines.next().unwrap(), "event: alert");
+    let data = lines.next().unwrap().trim_start_matches("data: ");
+    let alert = serde_json::from_str::<metrs_stubs::AlertEvent>(data).unwrap();
+    assert_eq!(alert.rule, "memory_high");
+    assert_eq!(alert.state, metrs_stubs::AlertState::Firing);
+    let resp = srv.get("/alerts").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = resp.json::<Vec<metrs_stubs::AlertEvent>>().await.unwrap();
+    assert_eq!(body, vec![alert]);
+    // Newline delimited json cannot tell the alerts apart from the metrics
+    let resp = srv
+      .get("/subscribe?include=memory,alerts")
+      .send()
+      .await
+      .unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = srv
+      .get("/subscribe?include=memory,alerts")
+      .header(header::ACCEPT, "text/event-stream")
+      .send()
+      .await
+      .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+  }
+
+  #[ntex::test]
+  async fn test_reload() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("metrsd.toml");
+    std::fs::write(&path, "hosts = [\"tcp://0.0.0.0:8585\"]\n").unwrap();
+    let args = ["metrsd", "--config", path.to_str().unwrap()];
+    let matches = crate::cli::Cli::command().get_matches_from(args);
+    let cli = crate::config::from_matches(&matches).unwrap();
+    let event_emitter = EventEmitter::default();
+    let reloader = Reloader::new(event_emitter.clone(), args, cli);
+    let srv =
+      generate_server_with_reloader(event_emitter.clone(), reloader).await;
+    let resp = srv
+      .get("/subscribe")
+      .header(header::ACCEPT, "text/event-stream")
+      .send()
+      .await
+      .unwrap();
+    let mut stream = resp.into_stream();
+    std::fs::write(
+      &path,
+      "hosts = [\"tcp://0.0.0.0:8585\"]\ntick_interval = 1\n",
+    )
+    .unwrap();
+    let resp = srv.post("/admin/reload").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = resp.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(body["msg"], "Configuration reloaded");
+    assert_eq!(event_emitter.metrics_config().unwrap().tick_interval, 1);
+    std::fs::write(&path, "tick_interval = \"fast\"\n").unwrap();
+    let resp = srv.post("/admin/reload").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(event_emitter.metrics_config().unwrap().tick_interval, 1);
+    // The subscriber is still connected after the reloads
+    event_emitter
+      .emit(metrs_stubs::MetrsdEvent::default())
+      .await
+      .unwrap();
+    let mut payload = String::new();
+    while let Some(item) = stream.next().await {
+      payload.push_str(std::str::from_utf8(&item.unwrap()).unwrap());
+      if payload.contains("data: ") && payload.ends_with("\n\n") {
+        break;
+      }
+    }
+    assert!(payload.contains("id: 0\n"));
   }
 
   #[ntex::test]
   async fn test_unhandled_route() {
-    let event_emitter = EventEmitter::new();
+    let event_emitter = EventEmitter::default();
     let srv = generate_server(event_emitter.clone()).await;
     let req = srv.get("/unhandled").send();
     let resp = req.await.unwrap();
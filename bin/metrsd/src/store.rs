@@ -0,0 +1,358 @@
+use std::{
+  fs,
+  io::{self, BufRead, BufReader, Write},
+  path::PathBuf,
+  time::Duration,
+};
+
+use metrs_stubs::MetrsdEvent;
+
+use crate::cli::Cli;
+use crate::history;
+
+/// Extension of the segment files
+const SEGMENT_EXTENSION: &str = "ndjson";
+
+/// Interval in milliseconds between two checks of the retention age when no
+/// segment is started
+const RETENTION_CHECK_INTERVAL: u64 = 60_000;
+
+/// Limits of the segment store
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoreConfig {
+  /// Size in bytes after which a new segment is started
+  pub segment_size: u64,
+  /// Total size in bytes of the segments kept
+  pub retention_size: u64,
+  /// Age of the oldest events kept
+  pub retention_age: Duration,
+}
+
+impl Default for StoreConfig {
+  fn default() -> Self {
+    Self {
+      segment_size: 16 * 1024 * 1024,
+      retention_size: 1024 * 1024 * 1024,
+      retention_age: Duration::from_secs(7 * 86400),
+    }
+  }
+}
+
+/// Share of the retention size of the raw events and of each rollup tier,
+/// the retention size is split evenly between them
+pub fn retention_share(cli: &Cli) -> u64 {
+  cli.retention_size / (cli.rollups.len() as u64 + 1)
+}
+
+impl From<&Cli> for StoreConfig {
+  fn from(cli: &Cli) -> Self {
+    Self {
+      retention_size: retention_share(cli),
+      retention_age: cli.retention_age,
+      ..Default::default()
+    }
+  }
+}
+
+/// A segment file holding the events collected from its start timestamp
+#[derive(Debug, Clone)]
+struct Segment {
+  start: u64,
+  path: PathBuf,
+  size: u64,
+}
+
+/// Append-only store of the events, as newline delimited json segments
+/// named after the timestamp of their first event
+pub struct SegmentStore {
+  dir: PathBuf,
+  config: StoreConfig,
+  /// Segment events are appended to, a new one is started on open
+  current: Option<(Segment, fs::File)>,
+  /// Timestamp of the last check of the retention
+  retention_checked: Option<u64>,
+}
+
+impl SegmentStore {
+  /// Open the store in a directory created when missing
+  pub fn open(
+    dir: impl Into<PathBuf>,
+    config: StoreConfig,
+  ) -> io::Result<Self> {
+    let dir = dir.into();
+    fs::create_dir_all(&dir)?;
+    Ok(Self {
+      dir,
+      config,
+      current: None,
+      retention_checked: None,
+    })
+  }
+
+  /// Age of the oldest events kept
+  pub fn retention(&self) -> Duration {
+    self.config.retention_age
+  }
+
+  /// List the segments ordered by start timestamp
+  fn segments(&self) -> io::Result<Vec<Segment>> {
+    let mut segments = fs::read_dir(&self.dir)?
+      .filter_map(Result::ok)
+      .filter_map(|entry| {
+        let path = entry.path();
+        if path.extension()? != SEGMENT_EXTENSION {
+          return None;
+        }
+        let start = path.file_stem()?.to_str()?.parse().ok()?;
+        let size = entry.metadata().ok()?.len();
+        Some(Segment { start, path, size })
+      })
+      .collect::<Vec<_>>();
+    segments.sort_by_key(|segment| segment.start);
+    Ok(segments)
+  }
+
+  /// Start a new segment with the timestamp of its first event
+  fn create_segment(&self, start: u64) -> io::Result<(Segment, fs::File)> {
+    let path = self.dir.join(format!("{start:020}.{SEGMENT_EXTENSION}"));
+    let file = fs::OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(&path)?;
+    let size = file.metadata()?.len();
+    Ok((Segment { start, path, size }, file))
+  }
+
+  /// Append an event already serialized as json
+  pub fn append(&mut self, timestamp: u64, json: &str) -> io::Result<()> {
+    let full = self
+      .current
+      .as_ref()
+      .is_none_or(|(segment, _)| segment.size >= self.config.segment_size);
+    if full {
+      self.current = Some(self.create_segment(timestamp)?);
+    }
+    if let Some((segment, file)) = self.current.as_mut() {
+      file.write_all(format!("{json}\n").as_bytes())?;
+      segment.size += json.len() as u64 + 1;
+    }
+    // The segments only exceed the retention size when one is started, and
+    // their age is checked periodically
+    let due = self.retention_checked.is_none_or(|checked| {
+      timestamp.saturating_sub(checked) >= RETENTION_CHECK_INTERVAL
+    });
+    if full || due {
+      self.retention_checked = Some(timestamp);
+      self.enforce_retention(timestamp)?;
+    }
+    Ok(())
+  }
+
+  /// Remove the oldest segments exceeding the retention, the current
+  /// segment is always kept
+  fn enforce_retention(&self, now: u64) -> io::Result<()> {
+    let segments = self.segments()?;
+    let oldest =
+      now.saturating_sub(self.config.retention_age.as_millis() as u64);
+    let mut total_size =
+      segments.iter().map(|segment| segment.size).sum::<u64>();
+    for (index, segment) in segments.iter().enumerate() {
+      let Some(next) = segments.get(index + 1) else {
+        break;
+      };
+      let expired = next.start <= oldest;
+      if !expired && total_size <= self.config.retention_size {
+        break;
+      }
+      log::debug!("Removing segment {}", segment.path.display());
+      fs::remove_file(&segment.path)?;
+      total_size -= segment.size;
+    }
+    Ok(())
+  }
+
+  /// Timestamp of the most recent event stored, read from the last segment
+  /// holding one
+  pub fn last_timestamp(&self) -> io::Result<Option<u64>> {
+    for segment in self.segments()?.iter().rev() {
+      let reader = BufReader::new(fs::File::open(&segment.path)?);
+      let mut last = None;
+      for line in reader.lines() {
+        if let Ok(ev) = serde_json::from_str::<MetrsdEvent>(&line?) {
+          last = Some(ev.timestamp);
+        }
+      }
+      if last.is_some() {
+        return Ok(last);
+      }
+    }
+    Ok(None)
+  }
+
+  /// Get the stored events with a timestamp in the window, at least `step`
+  /// apart. The segments are read line by line and only the events selected
+  /// are kept, lines that cannot be parsed such as the last one written
+  /// during a crash are skipped.
+  pub fn range(
+    &self,
+    since: Option<u64>,
+    until: Option<u64>,
+    step: Option<Duration>,
+  ) -> io::Result<Vec<MetrsdEvent>> {
+    let segments = self.segments()?;
+    let mut window = history::Window::new(since, until, step);
+    for (index, segment) in segments.iter().enumerate() {
+      let next_start = segments.get(index + 1).map(|next| next.start);
+      let before = next_start
+        .zip(since)
+        .is_some_and(|(next_start, since)| next_start <= since);
+      if before {
+        continue;
+      }
+      if until.is_some_and(|until| segment.start > until) {
+        break;
+      }
+      let reader = BufReader::new(fs::File::open(&segment.path)?);
+      for line in reader.lines() {
+        let Ok(ev) = serde_json::from_str::<MetrsdEvent>(&line?) else {
+          continue;
+        };
+        if window.is_done(&ev) {
+          return Ok(window.into_events());
+        }
+        window.push(ev);
+      }
+    }
+    Ok(window.into_events())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn append(store: &mut SegmentStore, sequence: u64, timestamp: u64) {
+    let ev = MetrsdEvent {
+      sequence,
+      timestamp,
+      ..Default::default()
+    };
+    let json = serde_json::to_string(&ev).unwrap();
+    store.append(timestamp, &json).unwrap();
+  }
+
+  fn sequences(events: &[MetrsdEvent]) -> Vec<u64> {
+    events.iter().map(|ev| ev.sequence).collect()
+  }
+
+  #[test]
+  fn test_reopen() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store =
+      SegmentStore::open(dir.path(), StoreConfig::default()).unwrap();
+    for sequence in 1..=3 {
+      append(&mut store, sequence, sequence * 1000);
+    }
+    drop(store);
+    let mut store =
+      SegmentStore::open(dir.path(), StoreConfig::default()).unwrap();
+    append(&mut store, 1, 4000);
+    assert_eq!(store.segments().unwrap().len(), 2);
+    let events = store.range(None, None, None).unwrap();
+    assert_eq!(events.len(), 4);
+    let events = store.range(Some(2000), Some(3000), None).unwrap();
+    assert_eq!(sequences(&events), vec![2, 3]);
+    let events = store
+      .range(None, None, Some(Duration::from_secs(2)))
+      .unwrap();
+    assert_eq!(sequences(&events), vec![1, 3]);
+  }
+
+  #[test]
+  fn test_skip_truncated_line() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store =
+      SegmentStore::open(dir.path(), StoreConfig::default()).unwrap();
+    append(&mut store, 1, 1000);
+    let (segment, file) = store.current.as_mut().unwrap();
+    file.write_all(b"{\"Timestamp\":20").unwrap();
+    let path = segment.path.clone();
+    drop(store);
+    let store = SegmentStore::open(dir.path(), StoreConfig::default()).unwrap();
+    assert!(path.exists());
+    assert_eq!(store.range(None, None, None).unwrap().len(), 1);
+  }
+
+  #[test]
+  fn test_retention_share() {
+    use clap::Parser;
+
+    let cli = Cli::parse_from(["metrsd", "--retention-size", "3G"]);
+    assert_eq!(StoreConfig::from(&cli).retention_size, 3 << 30);
+    let cli = Cli::parse_from([
+      "metrsd",
+      "--retention-size",
+      "3G",
+      "--rollup",
+      "1m:1d,1h:30d",
+    ]);
+    assert_eq!(retention_share(&cli), 1 << 30);
+    assert_eq!(StoreConfig::from(&cli).retention_size, 1 << 30);
+  }
+
+  #[test]
+  fn test_retention_size() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = StoreConfig {
+      segment_size: 1,
+      retention_size: 1,
+      ..Default::default()
+    };
+    let mut store = SegmentStore::open(dir.path(), config).unwrap();
+    for sequence in 1..=5 {
+      append(&mut store, sequence, sequence * 1000);
+    }
+    // Every event starts a segment, only the current one is kept
+    let segments = store.segments().unwrap();
+    assert_eq!(segments.len(), 1);
+    assert_eq!(segments[0].start, 5000);
+  }
+
+  #[test]
+  fn test_retention_age() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = StoreConfig {
+      segment_size: 1,
+      retention_age: Duration::from_secs(10),
+      ..Default::default()
+    };
+    let mut store = SegmentStore::open(dir.path(), config).unwrap();
+    for sequence in 1..=30 {
+      append(&mut store, sequence, sequence * 1000);
+    }
+    let events = store.range(None, None, None).unwrap();
+    // A segment is removed once the next one starts before the retention
+    assert_eq!(events.first().unwrap().timestamp, 20_000);
+    assert_eq!(events.len(), 11);
+  }
+
+  #[test]
+  fn test_retention_check_interval() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = StoreConfig {
+      retention_age: Duration::from_secs(10),
+      ..Default::default()
+    };
+    let mut store = SegmentStore::open(dir.path(), config.clone()).unwrap();
+    append(&mut store, 1, 1000);
+    drop(store);
+    let mut store = SegmentStore::open(dir.path(), config).unwrap();
+    for sequence in 2..=61 {
+      append(&mut store, sequence, sequence * 1000);
+    }
+    // The age is not checked on every append
+    assert_eq!(store.segments().unwrap().len(), 2);
+    append(&mut store, 62, 62_000);
+    assert_eq!(store.segments().unwrap().len(), 1);
+  }
+}
@@ -0,0 +1,210 @@
+use std::{
+  path::{Path, PathBuf},
+  sync::Arc,
+};
+
+use rustls::{
+  RootCertStore, ServerConfig,
+  crypto::ring,
+  server::WebPkiClientVerifier,
+  pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+};
+
+use crate::cli::Cli;
+use crate::error::MetrsError;
+
+/// Return true when a host is served over tls
+pub fn is_tls_host(host: &str) -> bool {
+  host.starts_with("tls://") || host.starts_with("https://")
+}
+
+/// Certificates of the `tls://` and `https://` hosts
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TlsConfig {
+  /// Certificate chain presented to the clients
+  pub cert: Option<PathBuf>,
+  /// Private key of the certificate
+  pub key: Option<PathBuf>,
+  /// Authority signing the certificates the clients must present, they are
+  /// not authenticated when not set
+  pub client_ca: Option<PathBuf>,
+}
+
+impl From<&Cli> for TlsConfig {
+  fn from(cli: &Cli) -> Self {
+    Self {
+      cert: cli.tls_cert.clone(),
+      key: cli.tls_key.clone(),
+      client_ca: cli.tls_client_ca.clone(),
+    }
+  }
+}
+
+/// Read the certificates of a pem file
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, MetrsError> {
+  let certs = CertificateDer::pem_file_iter(path)
+    .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+    .map_err(|err| {
+      MetrsError::Error(format!(
+        "Unable to read certificates {}: {err}",
+        path.display()
+      ))
+    })?;
+  if certs.is_empty() {
+    return Err(MetrsError::Error(format!(
+      "No certificate found in {}",
+      path.display()
+    )));
+  }
+  Ok(certs)
+}
+
+impl TlsConfig {
+  /// Build the server configuration from the pem files, the client
+  /// certificates are verified when a client authority is set
+  pub fn server_config(&self) -> Result<ServerConfig, MetrsError> {
+    let (Some(cert), Some(key)) = (&self.cert, &self.key) else {
+      return Err(MetrsError::Error(
+        "Tls hosts need a certificate, set --tls-cert and --tls-key".into(),
+      ));
+    };
+    let certs = load_certs(cert)?;
+    let key = PrivateKeyDer::from_pem_file(key).map_err(|err| {
+      MetrsError::Error(format!(
+        "Unable to read private key {}: {err}",
+        key.display()
+      ))
+    })?;
+    let provider = Arc::new(ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+      .with_safe_default_protocol_versions()
+      .map_err(|err| MetrsError::Error(format!("Invalid tls config: {err}")))?;
+    let builder = match &self.client_ca {
+      None => builder.with_no_client_auth(),
+      Some(path) => {
+        let mut roots = RootCertStore::empty();
+        for cert in load_certs(path)? {
+          roots.add(cert).map_err(|err| {
+            MetrsError::Error(format!(
+              "Invalid client authority {}: {err}",
+              path.display()
+            ))
+          })?;
+        }
+        let verifier = WebPkiClientVerifier::builder_with_provider(
+          Arc::new(roots),
+          provider,
+        )
+        .build()
+        .map_err(|err| {
+          MetrsError::Error(format!(
+            "Invalid client authority {}: {err}",
+            path.display()
+          ))
+        })?;
+        builder.with_client_cert_verifier(verifier)
+      }
+    };
+    builder.with_single_cert(certs, key).map_err(|err| {
+      MetrsError::Error(format!("Invalid tls certificate: {err}"))
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use ntex::{
+    client::{Client, Connector},
+    http::StatusCode,
+  };
+  use metrs_stubs::test_util::write_certs;
+  use rustls::ClientConfig;
+  use clap::Parser;
+
+  use crate::reload::Reloader;
+  use crate::event_emitter::EventEmitter;
+
+  use super::*;
+
+  async fn client(dir: &Path, with_cert: bool) -> Client {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(&dir.join("ca.pem")).unwrap() {
+      roots.add(cert).unwrap();
+    }
+    let builder =
+      ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots);
+    let config = if with_cert {
+      let certs = load_certs(&dir.join("client.pem")).unwrap();
+      let key = PrivateKeyDer::from_pem_file(dir.join("client.key")).unwrap();
+      builder.with_client_auth_cert(certs, key).unwrap()
+    } else {
+      builder.with_no_client_auth()
+    };
+    Client::builder()
+      .connector::<&str>(Connector::default().rustls(config))
+      .response_timeout(Duration::from_secs(5))
+      .build(ntex::SharedCfg::default())
+      .await
+      .unwrap()
+  }
+
+  #[ntex::test]
+  async fn test_tls() {
+    let dir = tempfile::tempdir().unwrap();
+    write_certs(dir.path());
+    let event_emitter = EventEmitter::default();
+    let cli = Cli::parse_from(["metrsd"]);
+    let reloader = Reloader::new(event_emitter.clone(), ["metrsd"], cli);
+    let port = ntex::server::TestServer::unused_addr().port();
+    let hosts = [format!("tls://127.0.0.1:{port}")];
+    let tls = TlsConfig::default();
+    let err = crate::server::gen_srv(
+      &hosts,
+      &tls,
+      Default::default(),
+      event_emitter.clone(),
+      reloader.clone(),
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("--tls-cert"));
+    let tls = TlsConfig {
+      cert: Some(dir.path().join("server.pem")),
+      key: Some(dir.path().join("server.key")),
+      client_ca: Some(dir.path().join("ca.pem")),
+    };
+    let srv = crate::server::gen_srv(
+      &hosts,
+      &tls,
+      Default::default(),
+      event_emitter,
+      reloader,
+    )
+    .unwrap();
+    let url = format!("https://localhost:{port}/alerts");
+    let resp = client(dir.path(), true)
+      .await
+      .get(&url)
+      .send()
+      .await
+      .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    // Clients without a certificate signed by the authority are rejected
+    assert!(client(dir.path(), false)
+      .await
+      .get(&url)
+      .send()
+      .await
+      .is_err());
+    srv.stop(true).await;
+    let tls = TlsConfig {
+      cert: Some(dir.path().join("server.key")),
+      ..tls
+    };
+    assert!(tls.server_config().is_err());
+  }
+}
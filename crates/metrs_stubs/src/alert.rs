@@ -0,0 +1,39 @@
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
+/// State of an alert after a transition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum AlertState {
+  /// The condition of the rule held for its duration
+  Firing,
+  /// The value went back past the threshold and the hysteresis
+  Resolved,
+}
+
+/// An alert rule firing or resolving for an instance such as a disk
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "PascalCase"))]
+pub struct AlertEvent {
+  /// Name of the rule
+  pub rule: String,
+  /// Disk, interface, sensor or cgroup the alert is about, empty for host
+  /// wide metrics
+  pub instance: String,
+  pub state: AlertState,
+  /// Severity given by the rule
+  pub severity: String,
+  /// Human readable description of the condition
+  pub summary: String,
+  /// Value of the metric at the transition
+  pub value: f64,
+  /// Threshold of the rule
+  pub threshold: f64,
+  /// Unix timestamp in milliseconds of when the alert started firing
+  pub started_at: u64,
+  /// Unix timestamp in milliseconds of the transition
+  pub timestamp: u64,
+  /// Hostname of the machine the alert comes from
+  pub hostname: String,
+}
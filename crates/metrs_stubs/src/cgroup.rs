@@ -0,0 +1,27 @@
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "PascalCase"))]
+pub struct CgroupInfo {
+  /// Path of the cgroup relative to the cgroup root
+  pub path: String,
+  /// Cpu time consumed by the cgroup in microseconds
+  pub cpu_usage_usec: u64,
+  /// Usage of the cpu in percent since the last collection,
+  /// can be above 100 on multi core hosts
+  pub cpu_usage: f64,
+  /// Memory used by the cgroup in bytes
+  pub memory_current: u64,
+  /// Memory limit of the cgroup in bytes, none if unlimited
+  pub memory_max: Option<u64>,
+  /// Bytes read by the cgroup from all devices
+  pub io_read_bytes: u64,
+  /// Bytes written by the cgroup to all devices
+  pub io_written_bytes: u64,
+  /// Number of processes in the cgroup
+  pub pids_current: u64,
+  /// Limit of processes in the cgroup, none if unlimited
+  pub pids_max: Option<u64>,
+}
@@ -0,0 +1,15 @@
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "PascalCase"))]
+pub struct ComponentInfo {
+  pub label: String,
+  /// Temperature in celsius, none if it cannot be read
+  pub temperature: Option<f32>,
+  /// Highest temperature in celsius seen for the component
+  pub max: Option<f32>,
+  /// Temperature in celsius at which the component is in danger
+  pub critical: Option<f32>,
+}
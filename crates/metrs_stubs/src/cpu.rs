@@ -4,7 +4,7 @@ use serde::{Serialize, Deserialize};
 #[cfg(feature = "sysinfo")]
 use sysinfo::Cpu;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[cfg_attr(feature = "serde", serde(rename_all = "PascalCase"))]
 pub struct CpuInfo {
@@ -15,6 +15,24 @@ pub struct DiskInfo {
   pub total_space: u64,
   pub available_space: u64,
   pub is_removable: bool,
+  /// Bytes read since the last collection
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub read_bytes: u64,
+  /// Bytes written since the last collection
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub written_bytes: u64,
+  /// Bytes read since the disk is mounted
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub total_read_bytes: u64,
+  /// Bytes written since the disk is mounted
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub total_written_bytes: u64,
+  /// Bytes read per second
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub read_rate: f64,
+  /// Bytes written per second
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub written_rate: f64,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -44,6 +62,7 @@ impl From<DiskKind> for DiskInfoKind {
 #[cfg(feature = "sysinfo")]
 impl From<&Disk> for DiskInfo {
   fn from(disk: &Disk) -> Self {
+    let usage = disk.usage();
     Self {
       kind: disk.kind().to_owned().into(),
       device_name: disk.name().to_str().unwrap_or_default().to_owned(),
@@ -52,6 +71,12 @@ impl From<&Disk> for DiskInfo {
       total_space: disk.total_space(),
       available_space: disk.available_space(),
       is_removable: disk.is_removable(),
+      read_bytes: usage.read_bytes,
+      written_bytes: usage.written_bytes,
+      total_read_bytes: usage.total_read_bytes,
+      total_written_bytes: usage.total_written_bytes,
+      read_rate: 0.0,
+      written_rate: 0.0,
     }
   }
 }
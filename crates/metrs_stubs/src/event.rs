@@ -1,13 +1,50 @@
-use super::{CpuInfo, DiskInfo, MemoryInfo, NetworkInfo};
+use super::{
+  CgroupInfo, ComponentInfo, CpuInfo, DiskInfo, HostInfo, MemoryInfo,
+  NetworkInfo, ProcessInfo, RollupInfo,
+};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "serde", serde(rename_all = "PascalCase"))]
 pub struct MetrsdEvent {
+  /// Unix timestamp in milliseconds of when the metrics were collected
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub timestamp: u64,
+  /// Increased by one for every event emitted by the daemon
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub sequence: u64,
+  /// Hostname of the machine the metrics come from
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub hostname: String,
+  /// Machine id of the host the metrics come from
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub machine_id: String,
+  /// Version of the daemon that collected the metrics
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub version: String,
+  /// Load average, uptime and operating system of the host
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub host: HostInfo,
   pub memory: MemoryInfo,
   pub cpus: Vec<CpuInfo>,
   pub disks: Vec<DiskInfo>,
   pub networks: Vec<NetworkInfo>,
+  /// Hardware temperature sensors
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub components: Vec<ComponentInfo>,
+  /// Cgroup v2 resources when enabled on the daemon
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub cgroups: Vec<CgroupInfo>,
+  /// Top processes when enabled on the daemon
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub processes: Vec<ProcessInfo>,
+  /// Minimum and maximum of the period when the event is a rollup of the
+  /// history
+  #[cfg_attr(
+    feature = "serde",
+    serde(default, skip_serializing_if = "Option::is_none")
+  )]
+  pub rollup: Option<Box<RollupInfo>>,
 }
 
 #[cfg(feature = "bytes")]
@@ -0,0 +1,328 @@
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
+use super::MetrsdEvent;
+
+/// A section of a metrsd event that can be selected by a subscriber
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventSection {
+  Host,
+  Memory,
+  Cpus,
+  Disks,
+  Networks,
+  Components,
+  Cgroups,
+  Processes,
+  /// Alerts firing and resolving, only sent when explicitly included
+  Alerts,
+}
+
+impl std::str::FromStr for EventSection {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "host" => Ok(Self::Host),
+      "memory" => Ok(Self::Memory),
+      "cpus" => Ok(Self::Cpus),
+      "disks" => Ok(Self::Disks),
+      "networks" => Ok(Self::Networks),
+      "components" => Ok(Self::Components),
+      "cgroups" => Ok(Self::Cgroups),
+      "processes" => Ok(Self::Processes),
+      "alerts" => Ok(Self::Alerts),
+      _ => Err(format!("Invalid event section: {s}")),
+    }
+  }
+}
+
+impl std::fmt::Display for EventSection {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let section = match self {
+      Self::Host => "host",
+      Self::Memory => "memory",
+      Self::Cpus => "cpus",
+      Self::Disks => "disks",
+      Self::Networks => "networks",
+      Self::Components => "components",
+      Self::Cgroups => "cgroups",
+      Self::Processes => "processes",
+      Self::Alerts => "alerts",
+    };
+    write!(f, "{section}")
+  }
+}
+
+/// Select the parts of the metrsd events a subscriber is interested in.
+/// Empty lists mean everything is selected.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EventFilter {
+  /// Sections to include in the events
+  pub include: Vec<EventSection>,
+  /// Mount points of the disks to include
+  pub disks: Vec<String>,
+  /// Names of the network interfaces to include
+  pub interfaces: Vec<String>,
+  /// Minimum interval in seconds between two events
+  pub interval: Option<u64>,
+}
+
+impl EventFilter {
+  /// Return true if the filter keeps the content of the events untouched
+  pub fn is_empty(&self) -> bool {
+    self.include.is_empty()
+      && self.disks.is_empty()
+      && self.interfaces.is_empty()
+  }
+
+  /// Return true if the subscriber wants the metrics events, a subscriber
+  /// including only the alerts doesn't
+  pub fn wants_metrics(&self) -> bool {
+    self.include.is_empty()
+      || self
+        .include
+        .iter()
+        .any(|section| *section != EventSection::Alerts)
+  }
+
+  /// Return true if the subscriber wants the alert events, they are not
+  /// sent by default to keep the stream of existing subscribers unchanged
+  pub fn wants_alerts(&self) -> bool {
+    self.include.contains(&EventSection::Alerts)
+  }
+
+  /// Return true if the section is selected
+  pub fn includes(&self, section: EventSection) -> bool {
+    self.include.is_empty() || self.include.contains(&section)
+  }
+
+  /// Trim an event to the sections, disks and interfaces selected
+  pub fn apply(&self, ev: &MetrsdEvent) -> MetrsdEvent {
+    let mut ev = ev.clone();
+    if !self.includes(EventSection::Host) {
+      ev.host = Default::default();
+    }
+    if !self.includes(EventSection::Memory) {
+      ev.memory = Default::default();
+    }
+    if !self.includes(EventSection::Cpus) {
+      ev.cpus.clear();
+    }
+    if !self.includes(EventSection::Disks) {
+      ev.disks.clear();
+    }
+    if !self.includes(EventSection::Networks) {
+      ev.networks.clear();
+    }
+    if !self.includes(EventSection::Components) {
+      ev.components.clear();
+    }
+    if !self.includes(EventSection::Cgroups) {
+      ev.cgroups.clear();
+    }
+    if !self.includes(EventSection::Processes) {
+      ev.processes.clear();
+    }
+    if !self.disks.is_empty() {
+      ev.disks
+        .retain(|disk| self.disks.contains(&disk.mount_point));
+    }
+    if !self.interfaces.is_empty() {
+      ev.networks
+        .retain(|net| self.interfaces.contains(&net.name));
+    }
+    ev
+  }
+}
+
+/// Query parameters of the subscribe route
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SubscribeQuery {
+  /// Comma separated list of sections to include
+  pub include: Option<String>,
+  /// Comma separated list of disk mount points to include
+  pub disks: Option<String>,
+  /// Comma separated list of network interfaces to include
+  pub interfaces: Option<String>,
+  /// Minimum interval in seconds between two events
+  pub interval: Option<u64>,
+}
+
+fn split_list(list: &Option<String>) -> Vec<String> {
+  list
+    .iter()
+    .flat_map(|list| list.split(','))
+    .map(str::trim)
+    .filter(|item| !item.is_empty())
+    .map(str::to_owned)
+    .collect()
+}
+
+fn join_list(list: &[impl ToString]) -> Option<String> {
+  if list.is_empty() {
+    return None;
+  }
+  Some(
+    list
+      .iter()
+      .map(ToString::to_string)
+      .collect::<Vec<_>>()
+      .join(","),
+  )
+}
+
+impl TryFrom<&SubscribeQuery> for EventFilter {
+  type Error = String;
+
+  fn try_from(query: &SubscribeQuery) -> Result<Self, Self::Error> {
+    if query.interval == Some(0) {
+      return Err("Interval must be greater than 0".into());
+    }
+    Ok(Self {
+      include: split_list(&query.include)
+        .iter()
+        .map(|section| section.parse())
+        .collect::<Result<_, _>>()?,
+      disks: split_list(&query.disks),
+      interfaces: split_list(&query.interfaces),
+      interval: query.interval,
+    })
+  }
+}
+
+impl From<&EventFilter> for SubscribeQuery {
+  fn from(filter: &EventFilter) -> Self {
+    Self {
+      include: join_list(&filter.include),
+      disks: join_list(&filter.disks),
+      interfaces: join_list(&filter.interfaces),
+      interval: filter.interval,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::{DiskInfo, DiskInfoKind, MemoryInfo, NetworkInfo, CpuInfo};
+
+  fn disk(mount_point: &str) -> DiskInfo {
+    DiskInfo {
+      kind: DiskInfoKind::SSD,
+      device_name: "/dev/sda".into(),
+      file_system: "ext4".into(),
+      mount_point: mount_point.into(),
+      total_space: 0,
+      available_space: 0,
+      is_removable: false,
+      read_bytes: 0,
+      written_bytes: 0,
+      total_read_bytes: 0,
+      total_written_bytes: 0,
+      read_rate: 0.0,
+      written_rate: 0.0,
+    }
+  }
+
+  fn network(name: &str) -> NetworkInfo {
+    NetworkInfo {
+      name: name.into(),
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn test_query_to_filter() {
+    let query = SubscribeQuery {
+      include: Some("memory, cpus".into()),
+      disks: Some("/,/data".into()),
+      interfaces: Some("eth0".into()),
+      interval: Some(60),
+    };
+    let filter = EventFilter::try_from(&query).unwrap();
+    assert_eq!(filter.interval, Some(60));
+    assert_eq!(
+      filter.include,
+      vec![EventSection::Memory, EventSection::Cpus]
+    );
+    assert_eq!(filter.disks, vec!["/", "/data"]);
+    assert_eq!(filter.interfaces, vec!["eth0"]);
+    assert!(filter.wants_metrics());
+    assert!(!filter.wants_alerts());
+    assert_eq!(
+      SubscribeQuery::from(&filter).include.as_deref(),
+      Some("memory,cpus")
+    );
+    let filter = EventFilter::try_from(&SubscribeQuery::default()).unwrap();
+    assert!(filter.is_empty());
+    assert_eq!(SubscribeQuery::from(&filter), SubscribeQuery::default());
+    let query = SubscribeQuery {
+      include: Some("memory,unknown".into()),
+      ..Default::default()
+    };
+    assert!(EventFilter::try_from(&query).is_err());
+    let query = SubscribeQuery {
+      include: Some("alerts".into()),
+      ..Default::default()
+    };
+    let filter = EventFilter::try_from(&query).unwrap();
+    assert!(filter.wants_alerts());
+    assert!(!filter.wants_metrics());
+    let query = SubscribeQuery {
+      interval: Some(0),
+      ..Default::default()
+    };
+    assert!(EventFilter::try_from(&query).is_err());
+    let filter = EventFilter {
+      interval: Some(10),
+      ..Default::default()
+    };
+    assert!(filter.is_empty());
+  }
+
+  #[test]
+  fn test_apply() {
+    let ev = MetrsdEvent {
+      sequence: 42,
+      memory: MemoryInfo {
+        total: 2048,
+        ..Default::default()
+      },
+      cpus: vec![CpuInfo::default()],
+      disks: vec![disk("/"), disk("/data"), disk("/boot")],
+      networks: vec![network("lo"), network("eth0")],
+      ..Default::default()
+    };
+    let filter = EventFilter::default();
+    let trimmed = filter.apply(&ev);
+    assert_eq!(trimmed.disks.len(), 3);
+    assert_eq!(trimmed.networks.len(), 2);
+    let filter = EventFilter {
+      include: vec![EventSection::Memory, EventSection::Disks],
+      disks: vec!["/".into(), "/data".into()],
+      interfaces: vec!["eth0".into()],
+      interval: None,
+    };
+    let trimmed = filter.apply(&ev);
+    assert_eq!(trimmed.sequence, 42);
+    assert_eq!(trimmed.memory.total, 2048);
+    assert!(trimmed.cpus.is_empty());
+    assert!(trimmed.networks.is_empty());
+    let mount_points = trimmed
+      .disks
+      .iter()
+      .map(|disk| disk.mount_point.as_str())
+      .collect::<Vec<_>>();
+    assert_eq!(mount_points, vec!["/", "/data"]);
+    let filter = EventFilter {
+      interfaces: vec!["eth0".into()],
+      ..Default::default()
+    };
+    let trimmed = filter.apply(&ev);
+    assert_eq!(trimmed.networks.len(), 1);
+    assert_eq!(trimmed.networks[0].name, "eth0");
+  }
+}
@@ -0,0 +1,14 @@
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
+/// Query parameters of the history route
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct HistoryQuery {
+  /// Oldest timestamp in milliseconds of the events to return
+  pub since: Option<u64>,
+  /// Newest timestamp in milliseconds of the events to return
+  pub until: Option<u64>,
+  /// Minimum interval in seconds between two events returned
+  pub step: Option<u64>,
+}
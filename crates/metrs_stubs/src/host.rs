@@ -0,0 +1,45 @@
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
+#[cfg(feature = "sysinfo")]
+use sysinfo::LoadAvg;
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "PascalCase"))]
+pub struct LoadAverageInfo {
+  /// Average load within one minute
+  pub one: f64,
+  /// Average load within five minutes
+  pub five: f64,
+  /// Average load within fifteen minutes
+  pub fifteen: f64,
+}
+
+#[cfg(feature = "sysinfo")]
+impl From<LoadAvg> for LoadAverageInfo {
+  fn from(load_avg: LoadAvg) -> Self {
+    Self {
+      one: load_avg.one,
+      five: load_avg.five,
+      fifteen: load_avg.fifteen,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "PascalCase"))]
+pub struct HostInfo {
+  pub load_average: LoadAverageInfo,
+  /// Time in seconds since the host booted
+  pub uptime: u64,
+  /// Unix timestamp in seconds of when the host booted
+  pub boot_time: u64,
+  pub kernel_version: String,
+  pub os_name: String,
+  pub os_version: String,
+  pub arch: String,
+  /// Number of physical cores, none if it cannot be determined
+  pub physical_core_count: Option<usize>,
+}
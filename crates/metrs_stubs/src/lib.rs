@@ -1,11 +1,35 @@
+mod alert;
+mod cgroup;
 mod cpu;
+mod component;
 mod disk;
+mod filter;
+mod history;
+mod host;
 mod memory;
 mod network;
+mod process;
+mod rollup;
+mod units;
 mod event;
 
+#[cfg(feature = "recording")]
+pub mod recording;
+
+#[cfg(feature = "test-util")]
+pub mod test_util;
+
+pub use alert::*;
+pub use cgroup::*;
 pub use cpu::*;
+pub use component::*;
 pub use disk::*;
+pub use filter::*;
+pub use history::*;
+pub use host::*;
 pub use memory::*;
 pub use network::*;
+pub use process::*;
+pub use rollup::*;
+pub use units::*;
 pub use event::*;
@@ -1,16 +1,58 @@
 #[cfg(feature = "serde")]
 use serde::{Serialize, Deserialize};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[cfg_attr(feature = "serde", serde(rename_all = "PascalCase"))]
 pub struct NetworkInfo {
   pub name: String,
   pub mac_addr: String,
+  /// Bytes received since the last collection
   pub received: u64,
+  /// Bytes transmitted since the last collection
   pub transmitted: u64,
+  /// Packets received since the last collection
   pub packets_received: u64,
+  /// Packets transmitted since the last collection
   pub packets_transmitted: u64,
+  /// Errors on received since the last collection
   pub error_received: u64,
+  /// Errors on transmitted since the last collection
   pub error_transmitted: u64,
+  /// Bytes received since the interface is up
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub total_received: u64,
+  /// Bytes transmitted since the interface is up
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub total_transmitted: u64,
+  /// Packets received since the interface is up
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub total_packets_received: u64,
+  /// Packets transmitted since the interface is up
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub total_packets_transmitted: u64,
+  /// Errors on received since the interface is up
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub total_error_received: u64,
+  /// Errors on transmitted since the interface is up
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub total_error_transmitted: u64,
+  /// Bytes received per second
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub received_rate: f64,
+  /// Bytes transmitted per second
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub transmitted_rate: f64,
+  /// Packets received per second
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub packets_received_rate: f64,
+  /// Packets transmitted per second
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub packets_transmitted_rate: f64,
+  /// Errors on received per second
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub error_received_rate: f64,
+  /// Errors on transmitted per second
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub error_transmitted_rate: f64,
 }
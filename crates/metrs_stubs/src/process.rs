@@ -0,0 +1,53 @@
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
+#[cfg(feature = "sysinfo")]
+use sysinfo::Process;
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "PascalCase"))]
+pub struct ProcessInfo {
+  pub pid: u32,
+  pub name: String,
+  pub cmd: Vec<String>,
+  /// Name of the user owning the process, or its id if unknown
+  pub user: String,
+  /// Usage of the cpu in percent, can be above 100 on multi core hosts
+  pub cpu_usage: f32,
+  /// Resident set size in bytes
+  pub memory: u64,
+  pub virtual_memory: u64,
+  pub disk_read_bytes: u64,
+  pub disk_written_bytes: u64,
+  /// Unix timestamp in seconds of when the process started
+  pub start_time: u64,
+  pub status: String,
+}
+
+#[cfg(feature = "sysinfo")]
+impl From<&Process> for ProcessInfo {
+  fn from(process: &Process) -> Self {
+    let disk_usage = process.disk_usage();
+    Self {
+      pid: process.pid().as_u32(),
+      name: process.name().to_string_lossy().into_owned(),
+      cmd: process
+        .cmd()
+        .iter()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect(),
+      user: process
+        .user_id()
+        .map(|uid| uid.to_string())
+        .unwrap_or_default(),
+      cpu_usage: process.cpu_usage(),
+      memory: process.memory(),
+      virtual_memory: process.virtual_memory(),
+      disk_read_bytes: disk_usage.total_read_bytes,
+      disk_written_bytes: disk_usage.total_written_bytes,
+      start_time: process.start_time(),
+      status: process.status().to_string(),
+    }
+  }
+}
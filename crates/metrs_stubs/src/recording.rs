@@ -0,0 +1,236 @@
+//! Recordings of the events streamed by a daemon, a gzip compressed file
+//! starting with a [`RecordingHeader`] line followed by one json event per
+//! line.
+
+use std::{
+  fs::File,
+  io::{self, BufRead, BufReader, BufWriter, Read, Write},
+  iter,
+  path::Path,
+};
+
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use serde::{Deserialize, Serialize};
+
+use super::MetrsdEvent;
+
+/// Format written in the header of the recordings
+pub const RECORDING_FORMAT: &str = "metrs-recording";
+
+/// Version of the recordings written
+pub const RECORDING_VERSION: u32 = 1;
+
+/// First line of a recording
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RecordingHeader {
+  pub format: String,
+  pub version: u32,
+  /// Url of the daemon the events come from
+  pub source: String,
+  /// Unix timestamp in milliseconds of when the recording started
+  pub started_at: u64,
+}
+
+impl RecordingHeader {
+  pub fn new(source: impl Into<String>, started_at: u64) -> Self {
+    Self {
+      format: RECORDING_FORMAT.to_owned(),
+      version: RECORDING_VERSION,
+      source: source.into(),
+      started_at,
+    }
+  }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Write the events to a recording
+pub struct RecordingWriter<W: Write> {
+  encoder: GzEncoder<W>,
+}
+
+impl RecordingWriter<BufWriter<File>> {
+  /// Create a recording file, replacing an existing one
+  pub fn create(path: &Path, header: &RecordingHeader) -> io::Result<Self> {
+    Self::new(BufWriter::new(File::create(path)?), header)
+  }
+}
+
+impl<W: Write> RecordingWriter<W> {
+  pub fn new(writer: W, header: &RecordingHeader) -> io::Result<Self> {
+    let mut recording = Self {
+      encoder: GzEncoder::new(writer, Compression::default()),
+    };
+    recording.write_line(header)?;
+    Ok(recording)
+  }
+
+  fn write_line<T: Serialize>(&mut self, value: &T) -> io::Result<()> {
+    serde_json::to_writer(&mut self.encoder, value)?;
+    self.encoder.write_all(b"\n")?;
+    // Flushed on every line so an interrupted recording can still be read
+    self.encoder.flush()
+  }
+
+  pub fn write(&mut self, ev: &MetrsdEvent) -> io::Result<()> {
+    self.write_line(ev)
+  }
+
+  /// Write the end of the compressed stream
+  pub fn finish(self) -> io::Result<W> {
+    self.encoder.finish()
+  }
+}
+
+/// Read the events of a recording
+pub struct RecordingReader<R: Read> {
+  header: RecordingHeader,
+  lines: iter::Peekable<io::Lines<BufReader<GzDecoder<R>>>>,
+}
+
+impl RecordingReader<File> {
+  pub fn open(path: &Path) -> io::Result<Self> {
+    Self::new(File::open(path)?)
+  }
+}
+
+impl<R: Read> RecordingReader<R> {
+  pub fn new(reader: R) -> io::Result<Self> {
+    let mut lines = BufReader::new(GzDecoder::new(reader)).lines();
+    let line = lines
+      .next()
+      .ok_or_else(|| invalid_data("Empty recording".into()))??;
+    let header =
+      serde_json::from_str::<RecordingHeader>(&line).map_err(|err| {
+        invalid_data(format!("Invalid recording header: {err}"))
+      })?;
+    if header.format != RECORDING_FORMAT {
+      return Err(invalid_data(format!(
+        "Invalid recording format: {}",
+        header.format
+      )));
+    }
+    if header.version > RECORDING_VERSION {
+      return Err(invalid_data(format!(
+        "Unsupported recording version: {}",
+        header.version
+      )));
+    }
+    Ok(Self {
+      header,
+      lines: lines.peekable(),
+    })
+  }
+
+  pub fn header(&self) -> &RecordingHeader {
+    &self.header
+  }
+
+  fn is_last_line(&mut self) -> bool {
+    match self.lines.peek() {
+      None => true,
+      Some(Err(err)) => err.kind() == io::ErrorKind::UnexpectedEof,
+      Some(Ok(_)) => false,
+    }
+  }
+}
+
+impl<R: Read> Iterator for RecordingReader<R> {
+  type Item = io::Result<MetrsdEvent>;
+
+  /// Read the next event, the end of a recording interrupted while being
+  /// written is skipped
+  fn next(&mut self) -> Option<Self::Item> {
+    let line = match self.lines.next()? {
+      Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return None,
+      Err(err) => return Some(Err(err)),
+      Ok(line) => line,
+    };
+    match serde_json::from_str(&line) {
+      Ok(ev) => Some(Ok(ev)),
+      // Only the last line can be truncated by an interruption
+      Err(_) if self.is_last_line() => None,
+      Err(err) => {
+        Some(Err(invalid_data(format!("Invalid recording event: {err}"))))
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn event(sequence: u64) -> MetrsdEvent {
+    MetrsdEvent {
+      timestamp: 1760000000000 + sequence * 1000,
+      sequence,
+      ..Default::default()
+    }
+  }
+
+  fn sequences(reader: RecordingReader<&[u8]>) -> Vec<u64> {
+    reader.map(|ev| ev.unwrap().sequence).collect()
+  }
+
+  #[test]
+  fn test_recording() {
+    let header = RecordingHeader::new("unix:///run/metrsd.sock", 1760000000000);
+    let mut writer = RecordingWriter::new(Vec::new(), &header).unwrap();
+    for sequence in 1..=3 {
+      writer.write(&event(sequence)).unwrap();
+    }
+    let data = writer.finish().unwrap();
+    let reader = RecordingReader::new(data.as_slice()).unwrap();
+    assert_eq!(reader.header(), &header);
+    assert_eq!(sequences(reader), vec![1, 2, 3]);
+  }
+
+  #[test]
+  fn test_interrupted_recording() {
+    let header = RecordingHeader::new("http://127.0.0.1:8080", 0);
+    let mut writer = RecordingWriter::new(Vec::new(), &header).unwrap();
+    writer.write(&event(1)).unwrap();
+    writer.write(&event(2)).unwrap();
+    // Dropped without finishing the compressed stream, as on a kill
+    let data = writer.encoder.get_ref().clone();
+    let reader = RecordingReader::new(data.as_slice()).unwrap();
+    assert_eq!(sequences(reader), vec![1, 2]);
+    // A truncated last line is skipped
+    let mut writer = RecordingWriter::new(Vec::new(), &header).unwrap();
+    writer.write(&event(1)).unwrap();
+    writer.encoder.write_all(b"{\"Sequence\":").unwrap();
+    let data = writer.finish().unwrap();
+    let reader = RecordingReader::new(data.as_slice()).unwrap();
+    assert_eq!(sequences(reader), vec![1]);
+  }
+
+  #[test]
+  fn test_corrupted_recording() {
+    let header = RecordingHeader::new("http://127.0.0.1:8080", 0);
+    let mut writer = RecordingWriter::new(Vec::new(), &header).unwrap();
+    writer.write(&event(1)).unwrap();
+    writer.encoder.write_all(b"not json\n").unwrap();
+    writer.write(&event(3)).unwrap();
+    let data = writer.finish().unwrap();
+    let mut reader = RecordingReader::new(data.as_slice()).unwrap();
+    assert_eq!(reader.next().unwrap().unwrap().sequence, 1);
+    let err = reader.next().unwrap().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(reader.next().unwrap().unwrap().sequence, 3);
+  }
+
+  #[test]
+  fn test_invalid_recording() {
+    assert!(RecordingReader::new(&b""[..]).is_err());
+    assert!(RecordingReader::new(&b"not gzip"[..]).is_err());
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(b"{\"Format\":\"other\",\"Version\":1,\"Source\":\"\",\"StartedAt\":0}\n").unwrap();
+    let data = encoder.finish().unwrap();
+    let err = RecordingReader::new(data.as_slice()).err().unwrap();
+    assert_eq!(err.to_string(), "Invalid recording format: other");
+  }
+}
@@ -0,0 +1,17 @@
+use super::MetrsdEvent;
+
+/// Aggregates of the events collected during a period, the event holding
+/// them is the average of the period
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "PascalCase"))]
+pub struct RollupInfo {
+  /// Duration in seconds of the period
+  pub resolution: u64,
+  /// Number of events collected during the period
+  pub count: u64,
+  /// Smallest value of every metric during the period
+  pub min: MetrsdEvent,
+  /// Largest value of every metric during the period
+  pub max: MetrsdEvent,
+}
@@ -0,0 +1,33 @@
+//! Fixtures shared by the tests of the daemon and the client
+
+use std::path::Path;
+
+use rcgen::{
+  BasicConstraints, CertificateParams, ExtendedKeyUsagePurpose, IsCa, Issuer,
+  KeyPair,
+};
+
+/// Write a certificate authority `ca.pem`, a server certificate for
+/// localhost `server.pem` and a client certificate `client.pem` signed by
+/// it, with their keys, panicking on failure
+pub fn write_certs(dir: &Path) {
+  let ca_key = KeyPair::generate().unwrap();
+  let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+  ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+  let ca = ca_params.self_signed(&ca_key).unwrap();
+  let issuer = Issuer::new(ca_params, ca_key);
+  std::fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+  for (name, usage) in [
+    ("server", ExtendedKeyUsagePurpose::ServerAuth),
+    ("client", ExtendedKeyUsagePurpose::ClientAuth),
+  ] {
+    let key = KeyPair::generate().unwrap();
+    let mut params =
+      CertificateParams::new(vec!["localhost".to_owned()]).unwrap();
+    params.extended_key_usages = vec![usage];
+    let cert = params.signed_by(&key, &issuer).unwrap();
+    std::fs::write(dir.join(format!("{name}.pem")), cert.pem()).unwrap();
+    std::fs::write(dir.join(format!("{name}.key")), key.serialize_pem())
+      .unwrap();
+  }
+}
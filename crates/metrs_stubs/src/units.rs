@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+/// Split a value such as `30m` in its number and unit
+pub fn split_unit(s: &str) -> Result<(u64, &str), String> {
+  let s = s.trim();
+  let (value, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+    Some(index) => s.split_at(index),
+    None => (s, ""),
+  };
+  let value = value
+    .parse::<u64>()
+    .map_err(|_| format!("Invalid value: {s}"))?;
+  Ok((value, unit))
+}
+
+/// Parse a duration such as `90s`, `30m`, `1h` or `2d`
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+  let (value, unit) = split_unit(s)?;
+  let factor = match unit {
+    "s" => 1,
+    "m" => 60,
+    "h" => 3600,
+    "d" => 86400,
+    _ => return Err(format!("Invalid duration unit: {unit}")),
+  };
+  let secs = value
+    .checked_mul(factor)
+    .ok_or_else(|| format!("Duration too large: {s}"))?;
+  Ok(Duration::from_secs(secs))
+}
+
+/// Format a duration with the largest unit it is a multiple of, the
+/// opposite of [`parse_duration`]
+pub fn format_duration(duration: Duration) -> String {
+  let secs = duration.as_secs();
+  match secs {
+    0 => "0s".into(),
+    secs if secs.is_multiple_of(86400) => format!("{}d", secs / 86400),
+    secs if secs.is_multiple_of(3600) => format!("{}h", secs / 3600),
+    secs if secs.is_multiple_of(60) => format!("{}m", secs / 60),
+    secs => format!("{secs}s"),
+  }
+}
+
+/// Parse a size in bytes such as `512`, `64K`, `16M` or `1G`
+pub fn parse_size(s: &str) -> Result<u64, String> {
+  let (value, unit) = split_unit(s)?;
+  let factor = match unit {
+    "" | "B" => 1,
+    "K" | "KiB" => 1024,
+    "M" | "MiB" => 1024 * 1024,
+    "G" | "GiB" => 1024 * 1024 * 1024,
+    _ => return Err(format!("Invalid size unit: {unit}")),
+  };
+  value
+    .checked_mul(factor)
+    .ok_or_else(|| format!("Size too large: {s}"))
+}
+
+/// Format a size with the largest unit it is a multiple of, the opposite of
+/// [`parse_size`]
+pub fn format_size(size: u64) -> String {
+  const UNITS: [(u64, &str); 3] =
+    [(1024 * 1024 * 1024, "G"), (1024 * 1024, "M"), (1024, "K")];
+  UNITS
+    .iter()
+    .find(|(factor, _)| size != 0 && size.is_multiple_of(*factor))
+    .map(|(factor, unit)| format!("{}{unit}", size / factor))
+    .unwrap_or_else(|| size.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_units() {
+    assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+    assert_eq!(parse_duration("10m"), Ok(Duration::from_secs(600)));
+    assert_eq!(parse_duration("2d"), Ok(Duration::from_secs(172_800)));
+    assert!(parse_duration("60").is_err());
+    assert!(parse_duration("m").is_err());
+    assert!(parse_duration("1w").is_err());
+    assert_eq!(
+      parse_duration("999999999999999d"),
+      Err("Duration too large: 999999999999999d".into())
+    );
+    assert_eq!(parse_size("512"), Ok(512));
+    assert_eq!(parse_size("16M"), Ok(16 * 1024 * 1024));
+    assert_eq!(parse_size("1GiB"), Ok(1024 * 1024 * 1024));
+    assert!(parse_size("1T").is_err());
+    assert_eq!(
+      parse_size("99999999999999G"),
+      Err("Size too large: 99999999999999G".into())
+    );
+    assert_eq!(format_duration(Duration::from_secs(90)), "90s");
+    assert_eq!(format_duration(Duration::from_secs(7200)), "2h");
+    assert_eq!(format_size(512 * 1024 * 1024), "512M");
+    assert_eq!(format_size(1536), "1536");
+  }
+}
@@ -1,39 +1,55 @@
+#[cfg(feature = "rustls")]
+use std::sync::Arc;
+
 use futures::{StreamExt, TryStreamExt};
 use ntex::{
   ServiceFactory,
   channel::mpsc::Receiver,
-  client::{Client, ClientRequest, ClientResponse, Connector},
-  http::StatusCode,
+  client::{Client, ClientBuilder, ClientRequest, ClientResponse, Connector},
+  http::{StatusCode, Uri},
+  io::Sealed,
   rt,
+  ws::{self, WsClient, WsConnection, error::WsClientError},
 };
+#[cfg(feature = "rustls")]
+use rustls::ClientConfig;
 
-use crate::error::ApiError;
+#[cfg(feature = "rustls")]
+use crate::tls::TlsOptions;
+use crate::error::{ApiError, ApiResponseError};
 
 #[derive(Clone)]
 pub struct MetrsdClient {
   client: Client,
   url: String,
+  /// Path of the unix socket of the daemon when connected through one
+  unix_socket: Option<String>,
+  /// Tls configuration of the websockets when connected over `https://`
+  #[cfg(feature = "rustls")]
+  tls: Option<Arc<ClientConfig>>,
+  /// Bearer token sent with every request
+  token: Option<String>,
 }
 
 impl MetrsdClient {
+  /// Connect to the daemon, `https://` urls need the `rustls` feature
   pub async fn connect(url: &'static str) -> Result<Self, ApiError> {
     match url {
-      url if url.starts_with("http://") || url.starts_with("https://") => {
-        let client = Client::builder()
-          .response_timeout(ntex::time::Millis::from_secs(20))
-          .build(ntex::SharedCfg::default())
-          .await
-          .map_err(|err| ApiError {
-            status: StatusCode::INTERNAL_SERVER_ERROR,
-            msg: format!("Unable to create client got error : {err}"),
-          })?;
-        Ok(MetrsdClient {
-          client,
-          url: url.to_owned(),
-        })
+      url if url.starts_with("http://") => {
+        Self::connect_http(url, Client::builder()).await
+      }
+      #[cfg(feature = "rustls")]
+      url if url.starts_with("https://") => {
+        Self::connect_https(url, &TlsOptions::default()).await
       }
+      #[cfg(not(feature = "rustls"))]
+      url if url.starts_with("https://") => Err(ApiError {
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+        msg: format!("Unable to connect to {url} without the rustls feature"),
+      }),
       url if url.starts_with("unix://") => {
         let unix_socket = url.trim_start_matches("unix://").to_owned();
+        let socket_path = unix_socket.clone();
         let mut client = Client::builder()
           .response_payload_timeout(ntex::time::Millis::from_secs(20))
           .response_timeout(ntex::time::Millis::from_secs(20));
@@ -63,6 +79,10 @@ impl MetrsdClient {
         Ok(MetrsdClient {
           client,
           url: String::from("http://localhost"),
+          unix_socket: Some(socket_path),
+          #[cfg(feature = "rustls")]
+          tls: None,
+          token: None,
         })
       }
       url => {
@@ -71,8 +91,149 @@ impl MetrsdClient {
     }
   }
 
+  /// Connect to the daemon, verifying its certificate with a custom
+  /// authority and presenting a client certificate on `https://` urls
+  #[cfg(feature = "rustls")]
+  pub async fn connect_with_tls(
+    url: &'static str,
+    tls: &TlsOptions,
+  ) -> Result<Self, ApiError> {
+    match url.starts_with("https://") {
+      true => Self::connect_https(url, tls).await,
+      false => Self::connect(url).await,
+    }
+  }
+
+  #[cfg(feature = "rustls")]
+  async fn connect_https(
+    url: &'static str,
+    tls: &TlsOptions,
+  ) -> Result<Self, ApiError> {
+    let config = Arc::new(tls.client_config()?);
+    let builder = Client::builder()
+      .connector::<&str>(Connector::default().rustls(config.as_ref().clone()));
+    let mut client = Self::connect_http(url, builder).await?;
+    client.tls = Some(config);
+    Ok(client)
+  }
+
+  async fn connect_http(
+    url: &'static str,
+    builder: ClientBuilder,
+  ) -> Result<Self, ApiError> {
+    let client = builder
+      .response_timeout(ntex::time::Millis::from_secs(20))
+      .build(ntex::SharedCfg::default())
+      .await
+      .map_err(|err| ApiError {
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+        msg: format!("Unable to create client got error : {err}"),
+      })?;
+    Ok(MetrsdClient {
+      client,
+      url: url.to_owned(),
+      unix_socket: None,
+      #[cfg(feature = "rustls")]
+      tls: None,
+      token: None,
+    })
+  }
+
+  /// Authenticate the requests with a bearer token, required by a daemon
+  /// started with `--auth-tokens`
+  pub fn with_token(mut self, token: impl Into<String>) -> Self {
+    self.token = Some(token.into());
+    self
+  }
+
   pub(crate) fn get(&self, url: String) -> ClientRequest {
-    self.client.get(self.gen_url(url))
+    let req = self.client.get(self.gen_url(url));
+    match &self.token {
+      Some(token) => req.bearer_auth(token),
+      None => req,
+    }
+  }
+
+  pub(crate) fn get_query<Q>(
+    &self,
+    url: String,
+    query: &Q,
+  ) -> Result<ClientRequest, ApiError>
+  where
+    Q: serde::Serialize,
+  {
+    self.get(url).query(query).map_err(|err| ApiError {
+      status: StatusCode::BAD_REQUEST,
+      msg: format!("Unable to serialize query got error : {err}"),
+    })
+  }
+
+  /// Open a websocket to the daemon, the uri is the one of a request built
+  /// with `get` or `get_query` so it carries the query string
+  pub(crate) async fn ws(
+    &self,
+    uri: &Uri,
+  ) -> Result<WsConnection<Sealed>, ApiError> {
+    let map_err = |err: WsClientError| match err {
+      WsClientError::InvalidResponseStatus(status) => ApiError {
+        status,
+        msg: format!("Unable to open websocket got status : {status}"),
+      },
+      err => ApiError {
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+        msg: format!("Unable to open websocket got error : {err}"),
+      },
+    };
+    fn build_err(err: impl std::fmt::Debug) -> ApiError {
+      ApiError {
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+        msg: format!("Unable to create websocket client got error : {err:?}"),
+      }
+    }
+    let mut builder = WsClient::builder(uri.clone());
+    builder.timeout(ntex::time::Millis::from_secs(20));
+    if let Some(token) = &self.token {
+      builder.bearer_auth(token);
+    }
+    if let Some(unix_socket) = &self.unix_socket {
+      let unix_socket = unix_socket.clone();
+      return builder
+        .connector(ntex::service::fn_service(move |_| {
+          let unix_socket = unix_socket.clone();
+          async move {
+            rt::unix_connect(unix_socket, ntex::SharedCfg::default())
+              .await
+              .map_err(ntex::connect::ConnectError::from)
+          }
+        }))
+        .build(ntex::SharedCfg::default())
+        .await
+        .map_err(build_err)?
+        .connect()
+        .await
+        .map(WsConnection::seal)
+        .map_err(map_err);
+    }
+    #[cfg(feature = "rustls")]
+    if let Some(tls) = &self.tls {
+      return builder
+        .rustls(tls.clone())
+        .build(ntex::SharedCfg::default())
+        .await
+        .map_err(build_err)?
+        .connect()
+        .await
+        .map(WsConnection::seal)
+        .map_err(map_err);
+    }
+    builder
+      .build(ntex::SharedCfg::default())
+      .await
+      .map_err(build_err)?
+      .connect()
+      .await
+      .map(WsConnection::seal)
+      .map_err(map_err)
   }
 
   fn gen_url(&self, url: String) -> String {
@@ -124,11 +285,58 @@ impl MetrsdClient {
     });
     rx
   }
+
+  /// Parse the text messages of a websocket as json, the daemon answers an
+  /// invalid control message with an error message
+  pub(crate) fn ws_stream<T>(
+    &self,
+    conn: WsConnection<Sealed>,
+  ) -> Receiver<Result<T, ApiError>>
+  where
+    T: serde::de::DeserializeOwned + Send + 'static,
+  {
+    let mut frames = conn.receiver();
+    let (tx, rx) = ntex::channel::mpsc::channel();
+    rt::spawn(async move {
+      while let Some(frame) = frames.next().await {
+        let text = match frame {
+          Ok(ws::Frame::Text(text)) => text,
+          Ok(ws::Frame::Close(_)) => break,
+          Ok(_) => continue,
+          Err(e) => {
+            let _ = tx.send(Err(ApiError {
+              status: StatusCode::INTERNAL_SERVER_ERROR,
+              msg: format!("Unable to read websocket got error : {e:?}"),
+            }));
+            break;
+          }
+        };
+        let item = match serde_json::from_slice::<T>(&text) {
+          Ok(t) => Ok(t),
+          Err(e) => match serde_json::from_slice::<ApiResponseError>(&text) {
+            Ok(err) => Err(ApiError {
+              status: StatusCode::BAD_REQUEST,
+              msg: err.msg,
+            }),
+            Err(_) => Err(ApiError {
+              status: StatusCode::INTERNAL_SERVER_ERROR,
+              msg: format!("Unable to parse websocket got error : {e}"),
+            }),
+          },
+        };
+        if tx.send(item).is_err() {
+          break;
+        }
+      }
+      tx.close();
+    });
+    rx
+  }
 }
 
 #[cfg(test)]
 mod tests {
-  use crate::error::is_api_error;
+  use crate::error::{MetrsClientError, is_api_error};
 
   use super::*;
 
@@ -140,12 +348,19 @@ mod tests {
     assert_eq!(client.url, "http://unknow.internal");
     let res = client.subscribe().await;
     assert!(res.is_err());
-    let client = MetrsdClient::connect("https://unknow.internal")
+    #[cfg(feature = "rustls")]
+    {
+      let client = MetrsdClient::connect("https://unknow.internal")
+        .await
+        .unwrap();
+      assert_eq!(client.url, "https://unknow.internal");
+      let res = client.subscribe().await;
+      assert!(res.is_err());
+    }
+    #[cfg(not(feature = "rustls"))]
+    assert!(MetrsdClient::connect("https://unknow.internal")
       .await
-      .unwrap();
-    assert_eq!(client.url, "https://unknow.internal");
-    let res = client.subscribe().await;
-    assert!(res.is_err());
+      .is_err());
     let client = MetrsdClient::connect("unix:///run/_non_existent.sock")
       .await
       .unwrap();
@@ -176,17 +391,129 @@ mod tests {
     assert!(res.is_err());
   }
 
+  #[cfg(feature = "rustls")]
   #[ntex::test]
-  async fn test_api_error() {
-    let client = MetrsdClient::connect("http://127.0.0.1:8080")
-      .await
+  async fn test_connect_tls() {
+    use std::path::Path;
+
+    use ntex::web;
+    use rustls::{
+      RootCertStore, ServerConfig,
+      crypto::ring,
+      pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+      server::WebPkiClientVerifier,
+    };
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = |name: &str| dir.path().join(name);
+    metrs_stubs::test_util::write_certs(dir.path());
+    let certs = |path: &Path| {
+      CertificateDer::pem_file_iter(path)
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap()
+    };
+    // Stand-in for a daemon verifying the certificate of its clients
+    let mut roots = RootCertStore::empty();
+    roots.add_parsable_certificates(certs(&path("ca.pem")));
+    let provider = Arc::new(ring::default_provider());
+    let verifier = WebPkiClientVerifier::builder_with_provider(
+      Arc::new(roots),
+      provider.clone(),
+    )
+    .build()
+    .unwrap();
+    let config = ServerConfig::builder_with_provider(provider)
+      .with_safe_default_protocol_versions()
+      .unwrap()
+      .with_client_cert_verifier(verifier)
+      .with_single_cert(
+        certs(&path("server.pem")),
+        PrivateKeyDer::from_pem_file(path("server.key")).unwrap(),
+      )
       .unwrap();
+    let srv =
+      web::test::server_with(web::test::config().rustls(config), async || {
+        web::App::new().route(
+          "/alerts",
+          web::get().to(|| async { web::HttpResponse::Ok().json(&[0; 0]) }),
+        )
+      })
+      .await;
+    let url = format!("https://localhost:{}", srv.addr().port()).leak();
+    let tls = TlsOptions {
+      ca_cert: Some(path("ca.pem")),
+      client_cert: Some(path("client.pem")),
+      client_key: Some(path("client.key")),
+    };
+    let client = MetrsdClient::connect_with_tls(url, &tls).await.unwrap();
+    assert!(client.alerts().await.unwrap().is_empty());
+    // The daemon rejects clients without a certificate
+    let tls = TlsOptions {
+      ca_cert: Some(path("ca.pem")),
+      ..Default::default()
+    };
+    let client = MetrsdClient::connect_with_tls(url, &tls).await.unwrap();
+    assert!(client.alerts().await.is_err());
+    // The certificate of the daemon is not signed by a web pki authority
+    let client = MetrsdClient::connect(url).await.unwrap();
+    assert!(client.alerts().await.is_err());
+    let tls = TlsOptions {
+      client_cert: Some(path("client.pem")),
+      ..Default::default()
+    };
+    assert!(MetrsdClient::connect_with_tls(url, &tls).await.is_err());
+  }
+
+  #[ntex::test]
+  async fn test_with_token() {
+    use ntex::{http::header, web};
+
+    // Stand-in for a daemon started with `--auth-tokens`
+    let srv = web::test::server(async || {
+      web::App::new().route(
+        "/alerts",
+        web::get().to(|req: web::HttpRequest| async move {
+          match req.headers().get(header::AUTHORIZATION) {
+            Some(value) if value == "Bearer secret" => {
+              web::HttpResponse::Ok().json(&[0; 0])
+            }
+            _ => web::HttpResponse::Unauthorized()
+              .json(&serde_json::json!({ "msg": "Invalid bearer token" })),
+          }
+        }),
+      )
+    })
+    .await;
+    let url = format!("http://127.0.0.1:{}", srv.addr().port()).leak();
+    let client = MetrsdClient::connect(url).await.unwrap();
+    assert!(client.alerts().await.is_err());
+    let client = client.with_token("secret");
+    assert!(client.alerts().await.unwrap().is_empty());
+  }
+
+  #[ntex::test]
+  async fn test_api_error() {
+    use ntex::web;
+
+    // Stand-in for a daemon answering its unhandled routes
+    let srv = web::test::server(async || {
+      web::App::new().default_service(web::route().to(|| async {
+        web::HttpResponse::NotFound()
+          .json(&serde_json::json!({ "msg": "Route not found" }))
+      }))
+    })
+    .await;
+    let url = format!("http://127.0.0.1:{}", srv.addr().port()).leak();
+    let client = MetrsdClient::connect(url).await.unwrap();
     let mut res = client.get("/test".to_string()).send().await.unwrap();
     let status = res.status();
-    let err = is_api_error(&mut res, &status).await;
-    println!("{err:?}");
-    assert!(err.is_err());
-    let err = err.unwrap_err();
-    println!("{err}");
+    match is_api_error(&mut res, &status).await {
+      Err(MetrsClientError::Api(err)) => {
+        assert_eq!(err.status, StatusCode::NOT_FOUND);
+        assert_eq!(err.msg, "Route not found");
+      }
+      res => panic!("Expect an api error got {res:?}"),
+    }
   }
 }
@@ -5,38 +5,334 @@ use metrs_stubs::*;
 use crate::client::MetrsdClient;
 use crate::error::{ApiError, MetrsClientError, is_api_error};
 
+/// Maximum size of a json payload returned by the daemon
+const MAX_PAYLOAD_SIZE: usize = 8 * 1024 * 1024;
+
+/// Maximum size of the events returned by the history route
+const MAX_HISTORY_SIZE: usize = 256 * 1024 * 1024;
+
 impl MetrsdClient {
+  /// Subscribe to the metrics
   pub async fn subscribe(
     &self,
   ) -> Result<Receiver<Result<MetrsdEvent, ApiError>>, MetrsClientError> {
-    let mut res = self.get("/subscribe".to_string()).send().await?;
+    self.subscribe_with(&EventFilter::default()).await
+  }
+
+  /// Subscribe to the metrics trimmed to the sections, disks and interfaces
+  /// selected by the filter, at its interval
+  pub async fn subscribe_with(
+    &self,
+    filter: &EventFilter,
+  ) -> Result<Receiver<Result<MetrsdEvent, ApiError>>, MetrsClientError> {
+    let query = SubscribeQuery::from(filter);
+    let mut res = self
+      .get_query("/subscribe".to_string(), &query)?
+      .send()
+      .await?;
     let status = res.status();
     is_api_error(&mut res, &status).await?;
     Ok(self.stream(res))
   }
+
+  /// Subscribe to the metrics over a websocket
+  pub async fn subscribe_ws(
+    &self,
+  ) -> Result<Receiver<Result<MetrsdEvent, ApiError>>, MetrsClientError> {
+    self.subscribe_ws_with(&EventFilter::default()).await
+  }
+
+  /// Subscribe to the metrics over a websocket, trimmed to the sections,
+  /// disks and interfaces selected by the filter, at its interval
+  pub async fn subscribe_ws_with(
+    &self,
+    filter: &EventFilter,
+  ) -> Result<Receiver<Result<MetrsdEvent, ApiError>>, MetrsClientError> {
+    let query = SubscribeQuery::from(filter);
+    let req = self.get_query("/ws".to_string(), &query)?;
+    let conn = self.ws(req.get_uri()).await?;
+    Ok(self.ws_stream(conn))
+  }
+
+  /// Subscribe to the alerts firing and resolving on the daemon
+  pub async fn subscribe_alerts(
+    &self,
+  ) -> Result<Receiver<Result<AlertEvent, ApiError>>, MetrsClientError> {
+    let filter = EventFilter {
+      include: vec![EventSection::Alerts],
+      ..Default::default()
+    };
+    let query = SubscribeQuery::from(&filter);
+    let mut res = self
+      .get_query("/subscribe".to_string(), &query)?
+      .send()
+      .await?;
+    let status = res.status();
+    is_api_error(&mut res, &status).await?;
+    Ok(self.stream(res))
+  }
+
+  /// Get the alerts firing and the most recently resolved ones
+  pub async fn alerts(&self) -> Result<Vec<AlertEvent>, MetrsClientError> {
+    let mut res = self.get("/alerts".to_string()).send().await?;
+    let status = res.status();
+    is_api_error(&mut res, &status).await?;
+    let alerts = res
+      .json::<Vec<AlertEvent>>()
+      .limit(MAX_PAYLOAD_SIZE)
+      .await?;
+    Ok(alerts)
+  }
+
+  /// Get the most recent metrics collected by the daemon
+  pub async fn snapshot(&self) -> Result<MetrsdEvent, MetrsClientError> {
+    let mut res = self.get("/metrics".to_string()).send().await?;
+    let status = res.status();
+    is_api_error(&mut res, &status).await?;
+    let event = res.json::<MetrsdEvent>().limit(MAX_PAYLOAD_SIZE).await?;
+    Ok(event)
+  }
+
+  /// Get the events kept by the daemon collected in a window, at least
+  /// `step` seconds apart
+  pub async fn history(
+    &self,
+    query: &HistoryQuery,
+  ) -> Result<Vec<MetrsdEvent>, MetrsClientError> {
+    let mut res = self
+      .get_query("/history".to_string(), query)?
+      .send()
+      .await?;
+    let status = res.status();
+    is_api_error(&mut res, &status).await?;
+    let events = res
+      .json::<Vec<MetrsdEvent>>()
+      .limit(MAX_HISTORY_SIZE)
+      .await?;
+    Ok(events)
+  }
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
 
-  use futures::StreamExt;
+  use futures::{StreamExt, stream};
+  use ntex::{service::fn_service, util::Bytes, web, web::ws};
+
+  /// Events served by the stand-in daemon
+  fn events() -> Vec<MetrsdEvent> {
+    (1..=3)
+      .map(|sequence| MetrsdEvent {
+        sequence,
+        timestamp: 1_760_000_000_000 + sequence * 1000,
+        memory: MemoryInfo {
+          total: 2048,
+          ..Default::default()
+        },
+        cpus: vec![CpuInfo::default()],
+        networks: vec![NetworkInfo::default()],
+        ..Default::default()
+      })
+      .collect()
+  }
+
+  fn bad_request(msg: String) -> web::HttpResponse {
+    web::HttpResponse::BadRequest().json(&serde_json::json!({ "msg": msg }))
+  }
+
+  /// Stand-in for a daemon serving the scripted events, trimmed by the
+  /// filter of the subscriptions, without any alert
+  async fn daemon() -> (web::test::TestServer, MetrsdClient) {
+    async fn subscribe(
+      qs: web::types::Query<SubscribeQuery>,
+    ) -> web::HttpResponse {
+      let filter = match EventFilter::try_from(&*qs) {
+        Ok(filter) => filter,
+        Err(err) => return bad_request(err),
+      };
+      let lines = events()
+        .iter()
+        .filter(|_| filter.wants_metrics())
+        .map(|ev| {
+          let mut line = serde_json::to_vec(&filter.apply(ev)).unwrap();
+          line.push(b'\n');
+          Ok::<_, web::Error>(Bytes::from(line))
+        })
+        .collect::<Vec<_>>();
+      web::HttpResponse::Ok().streaming(stream::iter(lines))
+    }
+
+    async fn subscribe_ws(
+      req: web::HttpRequest,
+      qs: web::types::Query<SubscribeQuery>,
+    ) -> Result<web::HttpResponse, web::Error> {
+      let filter = match EventFilter::try_from(&*qs) {
+        Ok(filter) => filter,
+        Err(err) => return Ok(bad_request(err)),
+      };
+      ws::start::<_, _, &str, web::Error>(
+        req,
+        None,
+        ntex::service::fn_factory_with_config(move |sink: ws::WsSink| {
+          let filter = filter.clone();
+          async move {
+            for ev in events() {
+              let text = serde_json::to_string(&filter.apply(&ev)).unwrap();
+              let _ = sink.send(ws::Message::Text(text.into())).await;
+            }
+            Ok::<_, web::Error>(fn_service(|_: ws::Frame| async {
+              Ok::<_, web::Error>(None::<ws::Message>)
+            }))
+          }
+        }),
+      )
+      .await
+    }
+
+    async fn history(qs: web::types::Query<HistoryQuery>) -> web::HttpResponse {
+      if qs.step == Some(0) {
+        return bad_request("Step must be greater than 0".into());
+      }
+      let events = events()
+        .into_iter()
+        .filter(|ev| qs.since.is_none_or(|since| ev.timestamp >= since))
+        .filter(|ev| qs.until.is_none_or(|until| ev.timestamp <= until))
+        .collect::<Vec<_>>();
+      web::HttpResponse::Ok().json(&events)
+    }
+
+    let srv = web::test::server(async || {
+      web::App::new()
+        .route("/subscribe", web::get().to(subscribe))
+        .route("/ws", web::get().to(subscribe_ws))
+        .route(
+          "/metrics",
+          web::get().to(|| async {
+            web::HttpResponse::Ok().json(&events().pop().unwrap())
+          }),
+        )
+        .route("/history", web::get().to(history))
+        .route(
+          "/alerts",
+          web::get().to(|| async { web::HttpResponse::Ok().json(&[0; 0]) }),
+        )
+    })
+    .await;
+    let url = format!("http://127.0.0.1:{}", srv.addr().port()).leak();
+    let client = MetrsdClient::connect(url).await.unwrap();
+    (srv, client)
+  }
 
   #[ntex::test]
   async fn test_subscribe() {
-    let client = MetrsdClient::connect("http://127.0.0.1:8080")
-      .await
-      .unwrap();
-    let mut stream = client.subscribe().await.unwrap();
-    let mut count = 0;
-    const MAX_COUNT: usize = 5;
-    while let Some(event) = stream.next().await {
-      println!("{:?}", event);
-      count += 1;
-      if count == MAX_COUNT {
-        break;
+    let (_srv, client) = daemon().await;
+    let stream = client.subscribe().await.unwrap();
+    let sequences = stream
+      .map(|ev| ev.unwrap().sequence)
+      .collect::<Vec<_>>()
+      .await;
+    assert_eq!(sequences, vec![1, 2, 3]);
+  }
+
+  #[ntex::test]
+  async fn test_subscribe_filter() {
+    let (_srv, client) = daemon().await;
+    let filter = EventFilter {
+      include: vec![EventSection::Memory],
+      ..Default::default()
+    };
+    let mut stream = client.subscribe_with(&filter).await.unwrap();
+    let event = stream.next().await.unwrap().unwrap();
+    assert_eq!(event.memory.total, 2048);
+    assert!(event.cpus.is_empty());
+    assert!(event.networks.is_empty());
+    let filter = EventFilter {
+      interval: Some(0),
+      ..Default::default()
+    };
+    assert!(client.subscribe_with(&filter).await.is_err());
+  }
+
+  #[ntex::test]
+  async fn test_subscribe_ws() {
+    let (_srv, client) = daemon().await;
+    let filter = EventFilter {
+      include: vec![EventSection::Memory],
+      ..Default::default()
+    };
+    let mut stream = client.subscribe_ws_with(&filter).await.unwrap();
+    let event = stream.next().await.unwrap().unwrap();
+    assert_eq!(event.sequence, 1);
+    assert_eq!(event.memory.total, 2048);
+    assert!(event.cpus.is_empty());
+    let filter = EventFilter {
+      interval: Some(0),
+      ..Default::default()
+    };
+    let err = client.subscribe_ws_with(&filter).await.unwrap_err();
+    match err {
+      MetrsClientError::Api(err) => {
+        assert_eq!(err.status, ntex::http::StatusCode::BAD_REQUEST)
       }
+      err => panic!("Expect an api error got {err}"),
     }
-    assert_eq!(count, MAX_COUNT)
+  }
+
+  #[ntex::test]
+  async fn test_snapshot() {
+    let (_srv, client) = daemon().await;
+    let event = client.snapshot().await.unwrap();
+    assert_eq!(event.sequence, 3);
+    assert_eq!(event.memory.total, 2048);
+  }
+
+  #[ntex::test]
+  async fn test_history() {
+    let (_srv, client) = daemon().await;
+    let events = client.history(&HistoryQuery::default()).await.unwrap();
+    assert_eq!(events.len(), 3);
+    let query = HistoryQuery {
+      since: Some(events[1].timestamp),
+      ..Default::default()
+    };
+    let since = client.history(&query).await.unwrap();
+    assert_eq!(since[0].sequence, events[1].sequence);
+    let query = HistoryQuery {
+      step: Some(0),
+      ..Default::default()
+    };
+    assert!(client.history(&query).await.is_err());
+  }
+
+  #[ntex::test]
+  async fn test_alerts() {
+    let (_srv, client) = daemon().await;
+    let alerts = client.alerts().await.unwrap();
+    assert!(alerts.is_empty());
+    let stream = client.subscribe_alerts().await.unwrap();
+    assert_eq!(stream.collect::<Vec<_>>().await.len(), 0);
+  }
+
+  #[test]
+  fn test_event_backward_compatible() {
+    let payload = r#"{
+      "Memory": {
+        "Total": 2048,
+        "Free": 1024,
+        "Used": 1024,
+        "SwapTotal": 0,
+        "SwapFree": 0,
+        "SwapUsed": 0
+      },
+      "Cpus": [],
+      "Disks": [],
+      "Networks": []
+    }"#;
+    let event = serde_json::from_str::<MetrsdEvent>(payload).unwrap();
+    assert_eq!(event.memory.total, 2048);
+    assert_eq!(event.sequence, 0);
+    assert_eq!(event.timestamp, 0);
+    assert!(event.hostname.is_empty());
   }
 }
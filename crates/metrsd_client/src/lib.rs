@@ -1,6 +1,10 @@
 mod event;
 mod client;
+#[cfg(feature = "rustls")]
+mod tls;
 
 pub mod error;
 pub use client::MetrsdClient;
+#[cfg(feature = "rustls")]
+pub use tls::TlsOptions;
 pub use metrs_stubs as stubs;
@@ -0,0 +1,89 @@
+use std::{
+  path::{Path, PathBuf},
+  sync::Arc,
+};
+
+use ntex::http::StatusCode;
+use rustls::{
+  ClientConfig, RootCertStore,
+  crypto::ring,
+  pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+};
+
+use crate::error::ApiError;
+
+/// Certificates used to reach a daemon listening on `https://`
+#[derive(Debug, Clone, Default)]
+pub struct TlsOptions {
+  /// Authority of the daemon certificate, the web pki roots when not set
+  pub ca_cert: Option<PathBuf>,
+  /// Certificate presented to a daemon verifying its clients
+  pub client_cert: Option<PathBuf>,
+  /// Private key of the client certificate
+  pub client_key: Option<PathBuf>,
+}
+
+fn tls_error(msg: String) -> ApiError {
+  ApiError {
+    status: StatusCode::INTERNAL_SERVER_ERROR,
+    msg,
+  }
+}
+
+/// Read the certificates of a pem file
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, ApiError> {
+  CertificateDer::pem_file_iter(path)
+    .and_then(|certs| certs.collect())
+    .map_err(|err| {
+      tls_error(format!(
+        "Unable to read certificates {} got error : {err}",
+        path.display()
+      ))
+    })
+}
+
+impl TlsOptions {
+  /// Build the rustls configuration of the client
+  pub(crate) fn client_config(&self) -> Result<ClientConfig, ApiError> {
+    let roots = match &self.ca_cert {
+      None => {
+        RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned())
+      }
+      Some(path) => {
+        let mut roots = RootCertStore::empty();
+        for cert in load_certs(path)? {
+          roots.add(cert).map_err(|err| {
+            tls_error(format!(
+              "Invalid certificate authority {} got error : {err}",
+              path.display()
+            ))
+          })?;
+        }
+        roots
+      }
+    };
+    let builder =
+      ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|err| tls_error(format!("Invalid tls config : {err}")))?
+        .with_root_certificates(roots);
+    match (&self.client_cert, &self.client_key) {
+      (None, None) => Ok(builder.with_no_client_auth()),
+      (Some(cert), Some(key)) => {
+        let certs = load_certs(cert)?;
+        let key = PrivateKeyDer::from_pem_file(key).map_err(|err| {
+          tls_error(format!(
+            "Unable to read private key {} got error : {err}",
+            key.display()
+          ))
+        })?;
+        builder.with_client_auth_cert(certs, key).map_err(|err| {
+          tls_error(format!("Invalid client certificate got error : {err}"))
+        })
+      }
+      _ => Err(tls_error(
+        "A client certificate needs both a certificate and a key".into(),
+      )),
+    }
+  }
+}